@@ -0,0 +1,11 @@
+name video-streaming
+# Mostly full-size packets at the frame rate of a 30-60 fps stream,
+# with occasional smaller audio/control packets.
+# size <encrypted body bytes> <weight>
+size 1200 75
+size 600 10
+size 160 15
+# interval <milliseconds> <weight>
+interval 16 55
+interval 33 35
+interval 100 10
@@ -0,0 +1,11 @@
+name web-browsing
+# Small requests and full-size response bursts separated by think-time pauses.
+# size <encrypted body bytes> <weight>
+size 1200 50
+size 400 20
+size 96 30
+# interval <milliseconds> <weight>
+interval 2 60
+interval 50 25
+interval 500 10
+interval 2000 5
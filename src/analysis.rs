@@ -0,0 +1,401 @@
+//! Traffic Analysis Module
+//!
+//! Offline measurement of how distinguishable fengni traffic is, without a network:
+//! a message workload is pushed through the real sending stack (Whisper encoding,
+//! `FECEncoder` for critical messages, `build_dynamic_frame` with the configured
+//! padding, or the `PacedSender` for cover traffic and morphing) on a simulated
+//! clock, and the resulting frames are summarized:
+//! - Frame size histogram
+//! - Shannon entropy of the bytes at each offset (headers should look random)
+//! - Autocorrelation of the gaps between frames
+//! - Accuracy of a simple classifier telling the trace apart from a reference
+//!   `MorphingProfile` (0.5 = indistinguishable)
+//!
+//! The `fengni-analyzer` binary wraps this for CI.
+
+use crate::cover_traffic::{PacedSender, COVER_STREAM_ID};
+use crate::dynamic_framing::{build_dynamic_frame, stream_generator, Direction, RatchetHandle, SaltGenerator, SilentConfig};
+use crate::fec::FECEncoder;
+use crate::morphing::{MorphingProfile, ShapingRng, XorShift};
+use crate::whisper::whisper::Payload;
+use crate::whisper::{FecWhisper, Priority, Whisper};
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Seed of the simulated session (the analysis only looks at the wire image)
+const SIMULATION_SEED: [u8; 32] = [0x42; 32];
+
+/// First stream used for messages (client-initiated bidirectional streams, 0 is reserved)
+const FIRST_MESSAGE_STREAM_ID: u64 = 4;
+
+/// One application message of a workload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkloadMessage {
+    /// Time the application hands the message over, from the start of the trace
+    pub at: Duration,
+    /// Length of the text content
+    pub len: usize,
+    /// Sent as a critical message (FEC-encoded over several streams)
+    pub critical: bool,
+}
+
+/// One frame as it appears on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedFrame {
+    pub at: Duration,
+    pub stream_id: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Chat-like workload: exponential gaps (300ms mean), mostly short messages,
+/// some long ones, and one critical message in ten
+pub fn synthetic_workload(count: usize, seed: u64) -> Vec<WorkloadMessage> {
+    let mut rng = XorShift::new(seed);
+    let mut at = Duration::ZERO;
+    (0..count)
+        .map(|_| {
+            at += Duration::from_secs_f64(-rng.unit().ln() * 0.3);
+            let critical = rng.range(0, 10) == 0;
+            let len = match rng.range(0, 100) {
+                _ if critical => rng.range(16, 256),
+                0..70 => rng.range(1, 200),
+                70..95 => rng.range(200, 1500),
+                _ => rng.range(1500, 8000),
+            };
+            WorkloadMessage { at, len, critical }
+        })
+        .collect()
+}
+
+/// Parse a recorded workload: one message per line, `<at_ms> <len> [critical]`
+pub fn parse_workload(text: &str) -> Result<Vec<WorkloadMessage>, String> {
+    let mut workload = Vec::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let content = raw_line.split('#').next().unwrap_or("").trim();
+        if content.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        let message = match fields.as_slice() {
+            [at, len] | [at, len, "critical"] => {
+                let at = at.parse().map_err(|_| format!("line {}: invalid time", index + 1))?;
+                let len = len.parse().map_err(|_| format!("line {}: invalid length", index + 1))?;
+                WorkloadMessage { at: Duration::from_millis(at), len, critical: fields.len() == 3 }
+            }
+            _ => return Err(format!("line {}: expected `<at_ms> <len> [critical]`", index + 1)),
+        };
+        workload.push(message);
+    }
+    Ok(workload)
+}
+
+/// Whisper messages a workload message turns into, as the client sends them
+fn encode_message(message: &WorkloadMessage, encoder: &FECEncoder) -> Result<Vec<Vec<u8>>, String> {
+    let content = "x".repeat(message.len);
+    let id = uuid::Uuid::new_v4().as_bytes().to_vec();
+    if !message.critical {
+        let whisper = Whisper {
+            id,
+            timestamp_ns: message.at.as_nanos() as u64,
+            priority: Priority::Normal as i32,
+            payload: Some(Payload::Content(content)),
+        };
+        return Ok(vec![whisper.encode_to_vec()]);
+    }
+
+    let (frames, _) = encoder.encode(content.as_bytes())?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            Whisper {
+                id: id.clone(),
+                timestamp_ns: 0,
+                priority: Priority::Urgent as i32,
+                payload: Some(Payload::FecPayload(FecWhisper { fec_frame: Some(frame) })),
+            }
+            .encode_to_vec()
+        })
+        .collect())
+}
+
+/// Run a workload through the sending stack and record every frame it puts on the wire.
+///
+/// Every Whisper message gets its own stream, with the generators the client sets up
+/// (`stream_generator`: chain keys and a DH ratchet per stream). With cover traffic
+/// or a `morphing` profile (`PacedSender::from_config`) the trace also contains the chaff of idle
+/// slots, up to the slot that sends the last queued message. Jitter and morphing
+/// draws come from `seed`, so the same inputs always give the same trace.
+pub fn simulate(
+    workload: &[WorkloadMessage],
+    config: SilentConfig,
+    morphing: Option<Arc<MorphingProfile>>,
+    seed: u64,
+) -> Result<Vec<TracedFrame>, String> {
+    let mut workload = workload.to_vec();
+    workload.sort_by_key(|message| message.at);
+
+    let encoder = FECEncoder::new(4, 2)?;
+    let mut generators: HashMap<u64, SaltGenerator> = HashMap::new();
+    let mut ratchets: HashMap<u64, RatchetHandle> = HashMap::new();
+    let mut next_stream_id = FIRST_MESSAGE_STREAM_ID;
+    let mut trace = Vec::new();
+
+    let Some(mut pacer) = PacedSender::from_config(&config, morphing) else {
+        // Unpaced: every message is framed as soon as the application hands it over
+        for message in &workload {
+            for payload in encode_message(message, &encoder)? {
+                let stream_id = next_stream_id;
+                next_stream_id += 4;
+                let generator = generators.entry(stream_id).or_insert_with(|| {
+                    stream_generator(&mut ratchets, &SIMULATION_SEED, stream_id, Direction::ClientToServer)
+                });
+                let bytes = build_dynamic_frame(generator, &payload, config).map_err(|e| e.to_string())?;
+                trace.push(TracedFrame { at: message.at, stream_id, bytes });
+            }
+        }
+        return Ok(trace);
+    };
+
+    // Paced: simulated clock jumping between message arrivals and slots
+    pacer.set_cover_stream(COVER_STREAM_ID);
+    pacer.seed_shaping(seed);
+    let start = Instant::now();
+    pacer.start_at(start);
+    let mut now = start;
+    let mut pending = workload.iter().peekable();
+    loop {
+        while let Some(message) = pending.next_if(|message| start + message.at <= now) {
+            for payload in encode_message(message, &encoder)? {
+                pacer.enqueue(next_stream_id, payload, true);
+                next_stream_id += 4;
+            }
+        }
+        while let Some(slot) = pacer.next_slot(now) {
+            let generator = generators.entry(slot.stream_id).or_insert_with(|| {
+                stream_generator(&mut ratchets, &SIMULATION_SEED, slot.stream_id, Direction::ClientToServer)
+            });
+            let bytes = slot.build(generator, config).map_err(|e| e.to_string())?;
+            trace.push(TracedFrame { at: now - start, stream_id: slot.stream_id, bytes });
+        }
+        if pending.peek().is_none() && pacer.queued() == 0 {
+            return Ok(trace);
+        }
+        let next_slot = now + pacer.timeout(now);
+        now = match pending.peek() {
+            Some(message) => next_slot.min(start + message.at),
+            None => next_slot,
+        };
+    }
+}
+
+/// Frame counts per size bucket: (bucket start, count), smallest first
+pub fn size_histogram(frames: &[TracedFrame], bucket: usize) -> Vec<(usize, usize)> {
+    let bucket = bucket.max(1);
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for frame in frames {
+        *counts.entry(frame.bytes.len() / bucket * bucket).or_default() += 1;
+    }
+    let mut histogram: Vec<_> = counts.into_iter().collect();
+    histogram.sort_unstable();
+    histogram
+}
+
+/// Shannon entropy (bits) of the byte values at each of the first `offsets` offsets,
+/// over all frames long enough. Random bytes approach `log2(min(frames, 256))`.
+pub fn offset_entropy(frames: &[TracedFrame], offsets: usize) -> Vec<f64> {
+    (0..offsets)
+        .map(|offset| {
+            let mut counts = [0usize; 256];
+            let mut total = 0usize;
+            for byte in frames.iter().filter_map(|frame| frame.bytes.get(offset)) {
+                counts[*byte as usize] += 1;
+                total += 1;
+            }
+            counts
+                .iter()
+                .filter(|count| **count > 0)
+                .map(|count| {
+                    let p = *count as f64 / total as f64;
+                    -p * p.log2()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Autocorrelation of the inter-frame gaps at lags 1..=max_lag.
+/// Constant gaps (no variance) give 0 at every lag.
+pub fn gap_autocorrelation(frames: &[TracedFrame], max_lag: usize) -> Vec<f64> {
+    let gaps: Vec<f64> = frames
+        .windows(2)
+        .map(|pair| pair[1].at.saturating_sub(pair[0].at).as_secs_f64())
+        .collect();
+    let mean = gaps.iter().sum::<f64>() / gaps.len().max(1) as f64;
+    let variance: f64 = gaps.iter().map(|gap| (gap - mean).powi(2)).sum();
+    (1..=max_lag)
+        .map(|lag| {
+            if variance == 0.0 || lag >= gaps.len() {
+                return 0.0;
+            }
+            let covariance: f64 = gaps.windows(lag + 1)
+                .map(|pair| (pair[0] - mean) * (pair[lag] - mean))
+                .sum();
+            covariance / variance
+        })
+        .collect()
+}
+
+/// Frames following `profile`, for comparison with a trace: `header_len` is added to
+/// every drawn body size (see `fixed_header_len`). The draws come from `seed`; use a
+/// different one than for `simulate`, or a morphed trace would copy the reference.
+pub fn reference_trace(profile: &MorphingProfile, count: usize, header_len: usize, seed: u64) -> Vec<TracedFrame> {
+    let mut rng = ShapingRng::seeded(seed);
+    let mut at = Duration::ZERO;
+    (0..count)
+        .map(|_| {
+            at += profile.sample_interval(&mut rng);
+            let len = header_len + profile.sample_size(&mut rng);
+            TracedFrame { at, stream_id: COVER_STREAM_ID, bytes: vec![0; len] }
+        })
+        .collect()
+}
+
+/// Header bytes before the encrypted body of a (non-rekey) frame under `config`
+pub fn fixed_header_len(config: SilentConfig) -> usize {
+    4 + 1 + if config.enable_sequence_hint { 2 } else { 0 } + if config.enable_double_ratchet { 1 } else { 0 }
+}
+
+/// Classifier features of one frame: log-scale buckets of its size and of the gap before it
+fn frame_features(frames: &[TracedFrame]) -> Vec<(usize, usize)> {
+    let log_bucket = |value: u64| (u64::BITS - value.leading_zeros()) as usize;
+    frames
+        .windows(2)
+        .map(|pair| {
+            let gap_us = pair[1].at.saturating_sub(pair[0].at).as_micros() as u64;
+            (log_bucket(pair[1].bytes.len() as u64), log_bucket(gap_us))
+        })
+        .collect()
+}
+
+/// Accuracy of a naive Bayes classifier telling windows of `window` frames of `trace`
+/// from windows of `reference`, trained on every other window and tested on the rest.
+///
+/// Around 0.5 the two are indistinguishable by size and timing; None if either trace
+/// has fewer than four windows.
+pub fn classifier_accuracy(trace: &[TracedFrame], reference: &[TracedFrame], window: usize) -> Option<f64> {
+    let window = window.max(1);
+    let features = [frame_features(trace), frame_features(reference)];
+    let windows = features.iter().map(|f| f.len() / window).min()?;
+    if windows < 4 {
+        return None;
+    }
+
+    // Training: Laplace-smoothed log-likelihoods of size and gap buckets per class
+    const BUCKETS: usize = 65;
+    let mut models = Vec::new();
+    for class_features in &features {
+        let mut sizes = [1.0f64; BUCKETS];
+        let mut gaps = [1.0f64; BUCKETS];
+        for chunk in class_features.chunks(window).take(windows).step_by(2) {
+            for &(size, gap) in chunk {
+                sizes[size] += 1.0;
+                gaps[gap] += 1.0;
+            }
+        }
+        let normalize = |counts: [f64; BUCKETS]| {
+            let total: f64 = counts.iter().sum();
+            counts.map(|count| (count / total).ln())
+        };
+        models.push((normalize(sizes), normalize(gaps)));
+    }
+
+    // Testing on the other windows
+    let mut correct = 0usize;
+    let mut total = 0usize;
+    for (class, class_features) in features.iter().enumerate() {
+        for chunk in class_features.chunks(window).take(windows).skip(1).step_by(2) {
+            let scores: Vec<f64> = models
+                .iter()
+                .map(|(sizes, gaps)| chunk.iter().map(|&(size, gap)| sizes[size] + gaps[gap]).sum())
+                .collect();
+            let predicted = if scores[0] >= scores[1] { 0 } else { 1 };
+            correct += (predicted == class) as usize;
+            total += 1;
+        }
+    }
+    Some(correct as f64 / total as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_framing::{parse_dynamic_frame, PaddingPolicy};
+
+    #[test]
+    fn test_simulation_and_statistics() {
+        let workload = synthetic_workload(200, 7);
+        assert!(workload.windows(2).all(|pair| pair[0].at <= pair[1].at));
+        assert_eq!(workload, synthetic_workload(200, 7));
+
+        let config = SilentConfig { padding: PaddingPolicy::PowerOfTwo, ..SilentConfig::default() };
+        let trace = simulate(&workload, config, None, 1).unwrap();
+        let critical = workload.iter().filter(|message| message.critical).count();
+        assert_eq!(trace.len(), workload.len() + critical * 5);
+
+        // Power-of-two buckets: every body is a power of two
+        let header_len = fixed_header_len(config);
+        assert!(trace.iter().all(|frame| (frame.bytes.len() - header_len).is_power_of_two()));
+        let histogram = size_histogram(&trace, 64);
+        assert_eq!(histogram.iter().map(|(_, count)| count).sum::<usize>(), trace.len());
+
+        // Obfuscated headers and ciphertext look random at every offset
+        let entropy = offset_entropy(&trace, 16);
+        assert!(entropy.iter().all(|bits| *bits > 6.5), "{:?}", entropy);
+
+        // These are the client's frames: the server's receiving generators open them
+        let mut ratchets = HashMap::new();
+        let mut receiver = stream_generator(&mut ratchets, &SIMULATION_SEED, trace[0].stream_id, Direction::ClientToServer);
+        assert!(parse_dynamic_frame(&mut receiver, &trace[0].bytes, config).is_ok());
+    }
+
+    #[test]
+    fn test_classifier_separates_unshaped_traffic() {
+        let profile = Arc::new(MorphingProfile::parse("size 512 1\nsize 1200 1\ninterval 20 1\ninterval 60 1").unwrap());
+        let workload = synthetic_workload(300, 11);
+        let config = SilentConfig::default();
+        let reference = reference_trace(&profile, 1000, fixed_header_len(config), 2);
+
+        let unshaped = simulate(&workload, config, None, 1).unwrap();
+        assert!(classifier_accuracy(&unshaped, &reference, 20).unwrap() > 0.9);
+
+        // Morphed traffic follows the profile, so the classifier is close to guessing
+        let morphed = simulate(&workload, config, Some(profile.clone()), 1).unwrap();
+        let accuracy = classifier_accuracy(&morphed, &reference, 20).unwrap();
+        assert!(accuracy < 0.75, "accuracy {}", accuracy);
+
+        // Same seeds, same sizes and timing (all the classifier sees): the thresholds cannot flake
+        let shape = |trace: &[TracedFrame]| -> Vec<(Duration, usize)> {
+            trace.iter().map(|frame| (frame.at, frame.bytes.len())).collect()
+        };
+        let again = simulate(&workload, config, Some(profile.clone()), 1).unwrap();
+        assert_eq!(shape(&morphed), shape(&again));
+        assert_eq!(reference, reference_trace(&profile, 1000, fixed_header_len(config), 2));
+    }
+
+    #[test]
+    fn test_workload_parsing_and_autocorrelation() {
+        let workload = parse_workload("# at len\n0 10\n250 4000 critical\n").unwrap();
+        assert_eq!(workload[1], WorkloadMessage { at: Duration::from_millis(250), len: 4000, critical: true });
+        assert!(parse_workload("10").is_err());
+
+        // Alternating gaps are perfectly anti-correlated at lag 1
+        let frames: Vec<TracedFrame> = [0, 10, 30, 40, 60, 70, 90]
+            .iter()
+            .map(|ms| TracedFrame { at: Duration::from_millis(*ms), stream_id: 4, bytes: Vec::new() })
+            .collect();
+        let correlation = gap_autocorrelation(&frames, 2);
+        assert!(correlation[0] < -0.7 && correlation[1] > 0.5, "{:?}", correlation);
+    }
+}
@@ -0,0 +1,156 @@
+//! fengni 流量可区分性离线分析工具
+//!
+//! 将录制的或合成的消息负载送入真实的发送栈（Whisper 编码、FEC、动态分帧、填充、
+//! 掩护流量/流量变形），输出帧大小直方图、各偏移字节熵、时间间隔自相关，
+//! 以及相对参考配置文件的分类器准确率。无需网络，可直接用于 CI 回归测试：
+//! 指定 `--max-accuracy` 时，准确率超过阈值则以退出码 1 结束。
+
+use silent_speaker::analysis::{
+    classifier_accuracy, fixed_header_len, gap_autocorrelation, offset_entropy, parse_workload,
+    reference_trace, simulate, size_histogram, synthetic_workload,
+};
+use silent_speaker::cover_traffic::CoverTraffic;
+use silent_speaker::dynamic_framing::{PaddingPolicy, SilentConfig};
+use silent_speaker::morphing::MorphingProfile;
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+/// 分析选项
+struct Options {
+    workload: Option<String>,
+    messages: usize,
+    seed: u64,
+    config: SilentConfig,
+    morphing: Option<Arc<MorphingProfile>>,
+    reference: Option<String>,
+    window: usize,
+    max_accuracy: Option<f64>,
+}
+
+const USAGE: &str = "用法: fengni-analyzer [选项]
+  --workload <文件>          录制的负载（每行 `<时间ms> <长度> [critical]`）
+  --messages <数量>          合成负载的消息数（默认 500）
+  --seed <种子>              随机种子：合成负载、抖动/变形采样与参考流量（默认 1）
+  --padding <策略>           none | random:<最大值> | pow2 | mtu:<MTU>
+  --cell-size <字节>         单元模式
+  --cover <参数>             掩护流量 `帧每秒[:单元字节数[:抖动ms]]`
+  --morphing <文件>          流量变形配置文件
+  --reference <文件>         分类器使用的参考配置文件
+  --window <帧数>            分类器窗口大小（默认 20）
+  --max-accuracy <阈值>      准确率超过阈值时以退出码 1 结束";
+
+fn parse_padding(value: &str) -> Option<PaddingPolicy> {
+    match value.split_once(':') {
+        None if value == "none" => Some(PaddingPolicy::None),
+        None if value == "pow2" => Some(PaddingPolicy::PowerOfTwo),
+        Some(("random", max)) => max.parse().ok().map(|max| PaddingPolicy::Random { max }),
+        Some(("mtu", mtu)) => mtu.parse().ok().map(PaddingPolicy::for_mtu),
+        _ => None,
+    }
+}
+
+fn load_profile(path: &str) -> Result<MorphingProfile, String> {
+    MorphingProfile::load(path)
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        workload: None,
+        messages: 500,
+        seed: 1,
+        config: SilentConfig::default(),
+        morphing: None,
+        reference: None,
+        window: 20,
+        max_accuracy: None,
+    };
+
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{} 缺少参数", flag))?;
+        let invalid = || format!("无效的 {} 参数: {}", flag, value);
+        match flag.as_str() {
+            "--workload" => options.workload = Some(value),
+            "--messages" => options.messages = value.parse().map_err(|_| invalid())?,
+            "--seed" => options.seed = value.parse().map_err(|_| invalid())?,
+            "--padding" => options.config.padding = parse_padding(&value).ok_or_else(invalid)?,
+            "--cell-size" => options.config.cell_size = Some(value.parse().map_err(|_| invalid())?),
+            "--cover" => options.config.cover_traffic = Some(CoverTraffic::parse(&value).ok_or_else(invalid)?),
+            "--morphing" => options.morphing = Some(Arc::new(load_profile(&value)?)),
+            "--reference" => options.reference = Some(value),
+            "--window" => options.window = value.parse().map_err(|_| invalid())?,
+            "--max-accuracy" => options.max_accuracy = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("未知选项: {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let workload = match &options.workload {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            parse_workload(&text)?
+        }
+        None => synthetic_workload(options.messages, options.seed),
+    };
+    // 负载、发送整形与参考流量各用独立的种子，结果可复现
+    let trace = simulate(&workload, options.config, options.morphing.clone(), options.seed.wrapping_add(1))?;
+    let total_bytes: usize = trace.iter().map(|frame| frame.bytes.len()).sum();
+    println!("消息数: {}, 帧数: {}, 总字节数: {}", workload.len(), trace.len(), total_bytes);
+
+    println!("\n帧大小直方图（64 字节分桶）:");
+    for (bucket, count) in size_histogram(&trace, 64) {
+        println!("  {:>6}-{:<6} {:>6}", bucket, bucket + 63, count);
+    }
+
+    // 帧数不足 256 时，随机字节的熵上限为 log2(帧数)
+    let ideal = (trace.len().min(256) as f64).log2();
+    println!("\n各偏移字节熵（比特，理想值 {:.2}）:", ideal);
+    for (offset, bits) in offset_entropy(&trace, 16).iter().enumerate() {
+        println!("  偏移 {:>2}: {:.3}", offset, bits);
+    }
+
+    println!("\n帧间隔自相关:");
+    for (lag, correlation) in gap_autocorrelation(&trace, 5).iter().enumerate() {
+        println!("  滞后 {}: {:+.3}", lag + 1, correlation);
+    }
+
+    let Some(path) = &options.reference else {
+        return Ok(true);
+    };
+    let profile = load_profile(path)?;
+    let reference = reference_trace(&profile, trace.len().max(1000), fixed_header_len(options.config), options.seed.wrapping_add(2));
+    let Some(accuracy) = classifier_accuracy(&trace, &reference, options.window) else {
+        println!("\n帧数不足，无法评估分类器");
+        return Ok(options.max_accuracy.is_none());
+    };
+    println!("\n分类器准确率（参考: {}）: {:.3}（0.5 表示无法区分）", profile.name(), accuracy);
+
+    match options.max_accuracy {
+        Some(max) if accuracy > max => {
+            println!("准确率超过阈值 {:.3}", max);
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(options) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("分析失败: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
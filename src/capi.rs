@@ -1,6 +1,6 @@
 use std::os::raw::{c_uchar};
 use std::ptr;
-use crate::dynamic_framing::{SaltGenerator, build_dynamic_frame, DynamicStreamParser, SilentConfig};
+use crate::dynamic_framing::{SaltGenerator, Direction, DEFAULT_MAX_SKIPPED_KEYS, build_dynamic_frame, DynamicStreamParser, CipherSuite, PaddingPolicy, SilentConfig, MIN_CELL_SIZE};
 
 /// Opaque handle for SilentConfig
 pub struct SilentConfigHandle(SilentConfig);
@@ -20,6 +20,83 @@ pub extern "C" fn silent_config_destroy(handle: *mut SilentConfigHandle) {
     }
 }
 
+/// Set the length-hiding padding policy of a configuration.
+/// policy: 0 = none, 1 = random up to `param` bytes, 2 = power-of-two buckets,
+///         3 = fixed size filling a QUIC packet of `param` bytes (MTU)
+/// Returns 0 on success, -1 on invalid arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_config_set_padding(
+    handle: *mut SilentConfigHandle,
+    policy: u8,
+    param: usize
+) -> i32 {
+    if handle.is_null() { return -1; }
+    let padding = match policy {
+        0 => PaddingPolicy::None,
+        1 => PaddingPolicy::Random { max: param },
+        2 => PaddingPolicy::PowerOfTwo,
+        3 => PaddingPolicy::for_mtu(param),
+        _ => return -1,
+    };
+    unsafe { (*handle).0.padding = padding; }
+    0
+}
+
+/// Select the AEAD of a configuration by its wire identifier.
+/// suite_id: 1 = ChaCha20-Poly1305, 2 = AES-256-GCM
+/// Returns 0 on success, -1 on invalid arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_config_set_cipher_suite(
+    handle: *mut SilentConfigHandle,
+    suite_id: u8
+) -> i32 {
+    if handle.is_null() { return -1; }
+    let Some(suite) = CipherSuite::from_id(suite_id) else { return -1; };
+    unsafe { (*handle).0.cipher_suite = suite; }
+    0
+}
+
+/// Enable (1) or disable (0) ChaCha20 header protection of a configuration.
+/// Returns 0 on success, -1 on invalid arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_config_set_header_protection(
+    handle: *mut SilentConfigHandle,
+    enabled: u8
+) -> i32 {
+    if handle.is_null() { return -1; }
+    unsafe { (*handle).0.enable_header_protection = enabled != 0; }
+    0
+}
+
+/// Enable cell mode with cells of `cell_size` bytes, or disable it with 0.
+/// Returns 0 on success, -1 on invalid arguments (cells smaller than MIN_CELL_SIZE).
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_config_set_cell_size(
+    handle: *mut SilentConfigHandle,
+    cell_size: usize
+) -> i32 {
+    if handle.is_null() { return -1; }
+    let cell_size = match cell_size {
+        0 => None,
+        size if size >= MIN_CELL_SIZE => Some(size),
+        _ => return -1,
+    };
+    unsafe { (*handle).0.cell_size = cell_size; }
+    0
+}
+
+/// Bytes a stream parser may scan for the next valid frame after a parse failure
+/// (0 drops the buffered data instead).
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_config_set_resync_scan_budget(
+    handle: *mut SilentConfigHandle,
+    budget: usize
+) -> i32 {
+    if handle.is_null() { return -1; }
+    unsafe { (*handle).0.resync_scan_budget = budget; }
+    0
+}
+
 /// Opaque handle for SaltGenerator
 pub struct SaltGeneratorHandle(SaltGenerator);
 
@@ -32,10 +109,48 @@ pub extern "C" fn silent_generator_create(
     stream_id: u64
 ) -> *mut SaltGeneratorHandle {
     if seed.is_null() { return ptr::null_mut(); }
-    let mut seed_arr = [0u8; 32];
-    unsafe { ptr::copy_nonoverlapping(seed, seed_arr.as_mut_ptr(), 32); }
+    // Borrowed in place: the caller's buffer stays the only copy of the seed
+    let seed = unsafe { &*(seed as *const [u8; 32]) };
     
-    Box::into_raw(Box::new(SaltGeneratorHandle(SaltGenerator::new_diversified(seed_arr, stream_id))))
+    Box::into_raw(Box::new(SaltGeneratorHandle(SaltGenerator::new_diversified(seed, stream_id))))
+}
+
+/// Create a salt generator bound to one direction of a stream.
+/// seed: 32 bytes array
+/// stream_id: u64
+/// direction: 0 = client-to-server, 1 = server-to-client
+/// Returns NULL on invalid arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_generator_create_directional(
+    seed: *const c_uchar,
+    stream_id: u64,
+    direction: u8
+) -> *mut SaltGeneratorHandle {
+    if seed.is_null() { return ptr::null_mut(); }
+    let direction = match direction {
+        0 => Direction::ClientToServer,
+        1 => Direction::ServerToClient,
+        _ => return ptr::null_mut(),
+    };
+    let seed = unsafe { &*(seed as *const [u8; 32]) };
+    
+    Box::into_raw(Box::new(SaltGeneratorHandle(SaltGenerator::new_directional(seed, stream_id, direction))))
+}
+
+/// Switch a generator to forward-secure chain-key mode.
+/// Must be called on both peers before the first frame.
+/// max_skipped: bound on cached keys of skipped frames (0 = library default)
+/// Returns 0 on success, -1 on NULL handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_generator_enable_chain_keys(
+    handle: *mut SaltGeneratorHandle,
+    max_skipped: usize
+) -> i32 {
+    if handle.is_null() { return -1; }
+    let generator = unsafe { &mut (*handle).0 };
+    let max_skipped = if max_skipped == 0 { DEFAULT_MAX_SKIPPED_KEYS } else { max_skipped };
+    generator.enable_chain_keys(max_skipped);
+    0
 }
 
 /// Destroy a generator handle.
@@ -157,3 +272,137 @@ pub extern "C" fn silent_parse_next(
         Err(_) => -5,
     }
 }
+
+/// Read and write ends of a byte stream (one socket, or a pair of pipes)
+#[cfg(unix)]
+struct FdStream {
+    read: std::fs::File,
+    write: std::fs::File,
+}
+
+#[cfg(unix)]
+impl std::io::Read for FdStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read.read(buf)
+    }
+}
+
+#[cfg(unix)]
+impl std::io::Write for FdStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write.flush()
+    }
+}
+
+/// Opaque handle for a SilentStream over file descriptors
+#[cfg(unix)]
+pub struct SilentStreamHandle {
+    stream: crate::dynamic_framing::SilentStream<FdStream>,
+    /// Message that did not fit into the caller's buffer, returned by the next recv
+    held: Option<Vec<u8>>,
+}
+
+/// Run dynamic frames over file descriptors (socket, FIFO, pipe pair).
+/// read_fd / write_fd: may be the same descriptor (e.g. a socket); the stream takes
+///                     ownership and closes them in silent_stream_destroy
+/// sender / receiver: generator handles for each direction (consumed)
+/// Returns NULL on invalid arguments, or if a shared read/write descriptor cannot be
+/// duplicated; nothing is consumed or closed then, and the caller still owns
+/// the descriptors and generator handles.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_stream_create_fds(
+    read_fd: i32,
+    write_fd: i32,
+    sender: *mut SaltGeneratorHandle,
+    receiver: *mut SaltGeneratorHandle,
+    config: *const SilentConfigHandle
+) -> *mut SilentStreamHandle {
+    use std::os::fd::{BorrowedFd, FromRawFd};
+
+    if read_fd < 0 || write_fd < 0 || sender.is_null() || receiver.is_null() || config.is_null() {
+        return ptr::null_mut();
+    }
+    // Everything that can fail happens before taking ownership of anything
+    let write = if write_fd == read_fd {
+        match unsafe { BorrowedFd::borrow_raw(read_fd) }.try_clone_to_owned() {
+            Ok(write) => std::fs::File::from(write),
+            Err(_) => return ptr::null_mut(),
+        }
+    } else {
+        unsafe { std::fs::File::from_raw_fd(write_fd) }
+    };
+    let read = unsafe { std::fs::File::from_raw_fd(read_fd) };
+    let sender = unsafe { Box::from_raw(sender).0 };
+    let receiver = unsafe { Box::from_raw(receiver).0 };
+    let conf = unsafe { (*config).0 };
+
+    let stream = crate::dynamic_framing::SilentStream::new(FdStream { read, write }, sender, receiver, conf);
+    Box::into_raw(Box::new(SilentStreamHandle { stream, held: None }))
+}
+
+/// Destroy a stream handle and close its descriptors.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_stream_destroy(handle: *mut SilentStreamHandle) {
+    if !handle.is_null() {
+        unsafe { drop(Box::from_raw(handle)); }
+    }
+}
+
+/// Send one message as a dynamic frame (blocking).
+/// Returns 0 on success, -1 on invalid arguments, -3 on framing or I/O errors.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_stream_send(
+    handle: *mut SilentStreamHandle,
+    data: *const c_uchar,
+    len: usize
+) -> i32 {
+    if handle.is_null() || data.is_null() { return -1; }
+    let stream = unsafe { &mut (*handle).stream };
+    let data_slice = unsafe { std::slice::from_raw_parts(data, len) };
+
+    match stream.send_message(data_slice) {
+        Ok(()) => 0,
+        Err(_) => -3,
+    }
+}
+
+/// Receive the next message (blocking).
+/// Returns 1 if a message was received, 0 once the peer closed the stream,
+/// -1 on invalid arguments (NULL handle, out_buf or out_written), -2 if out_buf is too small (out_written receives the size needed; the message
+/// is kept for the next call), -5 on framing or I/O errors.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub extern "C" fn silent_stream_recv(
+    handle: *mut SilentStreamHandle,
+    out_buf: *mut c_uchar,
+    out_max_len: usize,
+    out_written: *mut usize
+) -> i32 {
+    if handle.is_null() || out_buf.is_null() || out_written.is_null() {
+        return -1;
+    }
+    let handle = unsafe { &mut *handle };
+
+    let message = match handle.held.take() {
+        Some(message) => message,
+        None => match handle.stream.recv_message() {
+            Ok(Some(message)) => message,
+            Ok(None) => return 0,
+            Err(_) => return -5,
+        },
+    };
+    unsafe { *out_written = message.len(); }
+    if message.len() > out_max_len {
+        handle.held = Some(message);
+        return -2;
+    }
+    unsafe { ptr::copy_nonoverlapping(message.as_ptr(), out_buf, message.len()); }
+    1
+}
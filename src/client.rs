@@ -7,20 +7,34 @@ use prost::Message;
 use silent_speaker::whisper::{Whisper, Priority};
 use silent_speaker::whisper::whisper::Payload;
 use silent_speaker::critical_sender::CriticalSender;
-use silent_speaker::dynamic_framing::{SaltGenerator, build_dynamic_frame, DynamicStreamParser, SilentConfig};
+use silent_speaker::dynamic_framing::{SaltGenerator, Direction, Role, RatchetHandle, stream_generator, datagram_generators, build_dynamic_frame, build_typed_frame, DynamicStreamParser, DynamicFramingError, SilentConfig, Secret, FrameType, build_datagram_frame, parse_datagram_frame};
+use silent_speaker::cover_traffic::{CoverTraffic, PacedSender, COVER_STREAM_ID};
+use silent_speaker::morphing::MorphingProfile;
+use silent_speaker::coalescing::{Coalescer, CoalescingPolicy};
+use silent_speaker::handshake::{ClientHandshake, HandshakeAuth, HandshakeError, CLIENT_HANDSHAKE_STREAM_ID, SERVER_HANDSHAKE_STREAM_ID, PSK_ENV_VAR};
+use silent_speaker::resumption::{ResumeAttempt, ResumptionTicket, SavedSession, SessionState, CLIENT_RESUME_STREAM_ID, SERVER_RESUME_STREAM_ID};
 use silent_speaker::framing::FramingError; // Keep for error handling if needed, or remove if unused
 use silent_speaker::stream::UnifiedStreamManager;
 use silent_speaker::fec::FECEncoder;
 use silent_speaker::whisper::{FecWhisper, FecFrame};
 
 use std::collections::{HashMap, VecDeque, HashSet};
+use std::sync::Arc;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
-use silent_speaker::SESSION_BASE_SEED; // From lib.rs
 
 fn main() {
     // 日志系统初始化
     init();
+
+    // 加载握手认证密钥（预共享密钥）
+    let handshake_auth = match HandshakeAuth::from_env() {
+        Ok(auth) => auth,
+        Err(e) => {
+            error!("无法加载预共享密钥 (环境变量 {}): {}", PSK_ENV_VAR, e);
+            return;
+        }
+    };
     
     // 新增：创建FEC发送器和统一流管理器
     let mut critical_sender = CriticalSender::new(4, 2, 100)
@@ -34,13 +48,49 @@ fn main() {
     // Dynamic Framing    // 动态分帧状态
     let mut stream_generators: HashMap<u64, SaltGenerator> = HashMap::new();
     let mut stream_parsers: HashMap<u64, DynamicStreamParser> = HashMap::new(); // For receiving ACKs
+    let mut stream_ratchets: HashMap<u64, RatchetHandle> = HashMap::new(); // 每条流的DH棘轮（收发共享）
 
     // Phase 4: 统一流管理器和FEC编码器
     let mut stream_manager = UnifiedStreamManager::new(100); // Max 100 streams
     let mut fec_encoder = FECEncoder::new(4, 2).expect("FEC编码器初始化失败"); // 4 data + 2 parity
     
     // Phase 5 Config
-    let silent_config = SilentConfig::default(); // Robust Mode enabled by default
+    // 分帧配置（序号提示、双棘轮、头部保护、加密套件），通过环境变量覆盖默认值；
+    // 握手时与服务端协商，握手完成后替换为双方一致的配置
+    let mut silent_config = SilentConfig::from_env().unwrap_or_default(); // Robust Mode enabled by default
+    
+    // 掩护流量（恒定速率发送），通过环境变量启用
+    silent_config.cover_traffic = CoverTraffic::from_env();
+    if let Some(cover) = silent_config.cover_traffic {
+        info!(
+            "已启用掩护流量: {} 帧/秒, 单元 {} 字节, 抖动 {:?}",
+            cover.frames_per_second,
+            cover.cell_size,
+            cover.jitter
+        );
+    }
+
+    // 流量变形：按配置文件中的分布整形帧大小与发送间隔（优先于掩护流量）
+    // 变形只影响发送端，配置文件不属于协商的 SilentConfig
+    let morphing = match MorphingProfile::from_env() {
+        Some(Ok(profile)) => {
+            info!("已加载流量变形配置: {}", profile.name());
+            Some(Arc::new(profile))
+        }
+        Some(Err(e)) => {
+            warn!("流量变形配置加载失败: {}", e);
+            None
+        }
+        None => None,
+    };
+    let mut pacer = PacedSender::from_config(&silent_config, morphing);
+
+    // 消息合并：多条普通消息合并为一个批量帧（一个流）发送，通过环境变量启用
+    let mut coalescer = CoalescingPolicy::from_env().map(Coalescer::new);
+    if let Some(coalescer) = coalescer.as_ref() {
+        let policy = coalescer.policy();
+        info!("已启用消息合并: 批次上限 {} 字节, 最长等待 {:?}", policy.max_batch_size, policy.max_delay);
+    }
     
     // 注册预留流 (0 used for handshake/control potentially?)
     stream_manager.reserve_stream(0);
@@ -99,9 +149,25 @@ fn main() {
     config.set_initial_max_data(10_000_000);
     config.set_initial_max_stream_data_bidi_local(1_000_000);
     config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000); // 接收服务端握手控制流
     config.set_initial_max_streams_bidi(100);
     config.set_initial_max_streams_uni(100);
     config.set_disable_active_migration(true);
+    config.enable_dgram(true, 1000, 1000); // 数据报模式动态帧（低延迟消息）
+    config.enable_early_data(); // 恢复会话时以0-RTT数据发送ResumeHello与首批消息
+
+    // 会话恢复：读取上次保存的会话（读取后即删除文件，同一份状态只能恢复一次）
+    let resumption_file = SavedSession::path_from_env();
+    let mut saved_session = match resumption_file.as_deref().filter(|path| path.exists()) {
+        Some(path) => match SavedSession::take(path) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                warn!("读取已保存的会话失败: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
 
     // Generate a random source connection ID for the connection.
     let mut scid = [0; quiche::MAX_CONN_ID_LEN];
@@ -117,6 +183,13 @@ fn main() {
         quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)
             .unwrap();
 
+    // 恢复QUIC会话，握手完成前即可发送0-RTT数据
+    if let Some(quic_session) = saved_session.as_ref().and_then(|saved| saved.quic_session.as_deref()) {
+        if let Err(e) = conn.set_session(quic_session) {
+            warn!("恢复QUIC会话失败: {:?}", e);
+        }
+    }
+
     info!(
         "连接到 {:} 从 {:} 使用scid {}",
         peer_addr,
@@ -140,8 +213,25 @@ fn main() {
     let req_start = std::time::Instant::now();
     let mut req_sent = false;
 
+    // 会话握手状态：握手完成后才能构建动态帧
+    let mut handshake: Option<ClientHandshake> = None;
+    let mut session_seed: Option<Secret> = None;
+    // 数据报模式的（发送, 接收）生成器，握手完成后创建
+    let mut datagrams: Option<(SaltGenerator, SaltGenerator)> = None;
+    // 会话恢复：等待服务端应答的恢复请求，以及连接关闭时用于保存会话状态的票据
+    let mut resume: Option<ResumeAttempt> = None;
+    let mut ticket: Option<ResumptionTicket> = None;
+    // 本地分帧配置，会话恢复被拒绝后重新握手时使用
+    let local_config = silent_config;
+
     loop {
-        poll.poll(&mut events, conn.timeout()).unwrap();
+        // 启用掩护流量时，还需要在下一个发送时隙醒来
+        // 消息合并时，还需要在当前批次到期时醒来
+        let now = std::time::Instant::now();
+        let slot_timeout = pacer.as_ref().map(|p| p.timeout(now));
+        let batch_timeout = coalescer.as_ref().and_then(|c| c.timeout(now));
+        let timeout = conn.timeout().into_iter().chain(slot_timeout).chain(batch_timeout).min();
+        poll.poll(&mut events, timeout).unwrap();
 
         // Read incoming UDP packets from the socket and feed them to quiche,
         // until there are no more packets to read.
@@ -198,62 +288,35 @@ fn main() {
             break;
         }
 
-        if conn.is_established() && !req_sent {
-            info!("正在发送消息 {}", url.path());
-
-    // ============ 修改开始：使用统一流管理器发送普通消息 ============
-    let mut whisper = Whisper::default();
-    whisper.id = uuid::Uuid::new_v4().as_bytes().to_vec();
-    whisper.payload = Some(Payload::Content("测试普通消息(动态帧+调度)".to_string()));
-    whisper.timestamp_ns = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_nanos() as u64;
-    whisper.priority = Priority::Normal as i32;
-
-    let whisper_bytes = whisper.encode_to_vec();
-
-    // 1. 分配流
-    if let Some((stream_id, data_to_send)) = stream_manager.allocate_stream_for_normal_message(whisper_bytes, Priority::Normal) {
-        // 2. 获取生成器
-        let generator = stream_generators.entry(stream_id).or_insert_with(|| {
-            SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id)
-        });
-
-        // 3. 构建动态帧
-        match build_dynamic_frame(generator, &data_to_send, silent_config) {
-            Ok(framed_data) => {
-                // 4. 发送
-                match conn.stream_send(stream_id, &framed_data, true) {
-                    Ok(_) => {
-                        info!("普通消息已发送 (流ID: {})", stream_id);
-                        stream_manager.mark_frame_sent(stream_id); // 立即标记因为stream_send是非阻塞的writer
-                        // 注意：实际上stream_send只是写入buffer，不代表ACK。
-                        // Scheduler的mark_frame_sent通常意味着"流已由该帧占用完成"。
-                        // 对于StreamPool，release_stream应该在确认收到或者流关闭时调用？
-                        // quiche中 fin=true 会关闭流的写端。
-                        // 需要等待 fin ack 吗？ StreamPool用于限制并发流数量。
-                        // 简单起见，我们在发送后释放，或者等待 receiving ack?
-                        // StreamManager logic calls release_stream in mark_frame_sent.
-                    },
-                    Err(e) => error!("发送失败: {:?}", e),
+        // 会话恢复：0-RTT阶段即可发送ResumeHello，并用恢复的状态继续各流
+        if conn.is_established() || conn.is_in_early_data() {
+            if let Some(saved) = saved_session.take() {
+                match resume_session(&mut conn, saved, local_config) {
+                    Ok((attempt, mut state, config)) => {
+                        state.attach_ratchets(&mut stream_ratchets);
+                        info!(
+                            "已发送ResumeHello，恢复 {} 条发送流, {} 条接收流",
+                            state.senders.len(),
+                            state.parsers.len()
+                        );
+                        silent_config = config;
+                        stream_generators = state.senders;
+                        stream_parsers = state.parsers;
+                        datagrams = state.datagrams;
+                        session_seed = Some(state.base_seed);
+                        if let Some(pacer) = pacer.as_mut() {
+                            pacer.set_cover_stream(COVER_STREAM_ID);
+                        }
+                        resume = Some(attempt);
+                    }
+                    Err(e) => warn!("无法恢复会话，改为完整握手: {}", e),
                 }
-            },
-            Err(e) => error!("分帧失败: {}", e),
+            }
         }
-    } else {
-        warn!("无法分配流发送普通消息 (可能是流耗尽)");
-    }
-    // ============ 修改结束 ============
-    
-    // 新增：发送关键信令（FEC保护）- 使用分帧版本
-    match send_critical_message_integrated(&mut conn, &mut fec_encoder, &mut stream_manager, &mut stream_generators, "这是一条关键信令(动态帧)！") {
-        Ok(_) => info!("关键信令发送成功"),
-        Err(e) => error!("关键信令发送失败: {}", e),
-    }
 
-        req_sent = true;
-    }
+        if conn.is_established() && handshake.is_none() && session_seed.is_none() {
+            handshake = start_handshake(&mut conn, &handshake_auth, silent_config);
+        }
 
         // Process all readable streams.
         for s in conn.readable() {
@@ -269,10 +332,83 @@ fn main() {
                     fin
                 );
 
+                // 会话恢复流：服务端接受或拒绝恢复请求
+                if s == SERVER_RESUME_STREAM_ID {
+                    let Some(attempt) = resume.as_mut() else {
+                        warn!("未发起会话恢复却收到应答");
+                        continue;
+                    };
+                    match attempt.on_data(stream_buf) {
+                        Ok(Some(resumed)) => {
+                            info!("服务端已恢复会话");
+                            ticket = Some(resumed);
+                            resume = None;
+                        }
+                        Ok(None) => debug!("会话恢复应答不完整，等待更多数据"),
+                        Err(e) => {
+                            // 用恢复状态发出的消息随之作废：清空会话状态，完整握手后重新发送
+                            warn!("会话恢复失败，改为完整握手: {}", e);
+                            resume = None;
+                            stream_generators.clear();
+                            stream_parsers.clear();
+                            stream_ratchets.clear();
+                            datagrams = None;
+                            session_seed = None;
+                            silent_config = local_config;
+                            req_sent = false;
+                            handshake = start_handshake(&mut conn, &handshake_auth, silent_config);
+                        }
+                    }
+                    continue;
+                }
+
+                // 握手控制流：处理ServerHello
+                if s == SERVER_HANDSHAKE_STREAM_ID {
+                    let Some(state) = handshake.as_mut() else {
+                        warn!("未发起握手却收到ServerHello");
+                        continue;
+                    };
+                    match state.on_data(&handshake_auth, stream_buf) {
+                        Ok(Some(seed)) => {
+                            if let Some(config) = state.negotiated_config() {
+                                silent_config = config;
+                            }
+                            info!(
+                                "会话握手完成，协商分帧配置: 双棘轮 {} (间隔 {}), 头部保护 {}",
+                                silent_config.enable_double_ratchet,
+                                silent_config.ratchet_interval,
+                                silent_config.enable_header_protection
+                            );
+                            datagrams = Some(datagram_generators(&seed, Role::Client));
+                            ticket = Some(ResumptionTicket::from_base_seed(&seed));
+                            session_seed = Some(seed);
+                            // 预留的流0用于承载掩护帧
+                            if let Some(pacer) = pacer.as_mut() {
+                                pacer.set_cover_stream(COVER_STREAM_ID);
+                            }
+                        }
+                        Ok(None) => debug!("ServerHello不完整，等待更多数据"),
+                        Err(e @ HandshakeError::IncompatibleConfig(_)) => {
+                            error!("与服务端的分帧配置不兼容: {}", e);
+                            conn.close(false, 0x1, b"incompatible config").ok();
+                        }
+                        Err(e) => {
+                            error!("会话握手失败: {}", e);
+                            conn.close(false, 0x1, b"handshake failed").ok();
+                        }
+                    }
+                    continue;
+                }
+
+                let Some(seed) = session_seed.as_deref() else {
+                    warn!("流 {} 在握手完成前收到数据，已丢弃", s);
+                    continue;
+                };
+
                 // ============ 修改开始：尝试解析分帧消息（ACK） ============
                 // 获取解析器
                 let parser = stream_parsers.entry(s).or_insert_with(|| {
-                     let generator = SaltGenerator::new_diversified(SESSION_BASE_SEED, s);
+                     let generator = stream_generator(&mut stream_ratchets, seed, s, Direction::ServerToClient);
                      DynamicStreamParser::new(generator)
                 });
                 
@@ -295,6 +431,12 @@ fn main() {
                              }
                          },
                          Ok(None) => break, // Need more data
+                         Err(DynamicFramingError::UnknownEpoch { epoch, .. }) => {
+                             warn!("ACK帧属于未知密钥纪元 {}，已跳过", epoch);
+                         }
+                         Err(DynamicFramingError::Replay { seq, .. }) => {
+                             warn!("收到重放的ACK帧 (序号 {})，已丢弃", seq);
+                         }
                          Err(e) => {
                              error!("ACK动态帧解析失败: {}", e);
                              break;
@@ -315,6 +457,114 @@ fn main() {
             }
         }
 
+        // 数据报模式：处理服务端通过QUIC数据报回复的ACK
+        if let Some((_, receiver)) = datagrams.as_mut() {
+            while let Ok(len) = conn.dgram_recv(&mut buf) {
+                match parse_datagram_frame(receiver, &buf[..len], silent_config) {
+                    Ok((FrameType::Data, payload)) => match Whisper::decode(&payload[..]) {
+                        Ok(Whisper { payload: Some(Payload::Content(txt)), .. }) => info!("收到服务端数据报ACK: {}", txt),
+                        Ok(_) => info!("收到服务端非文本数据报ACK"),
+                        Err(_) => warn!("收到无法解析的Protobuf数据报"),
+                    },
+                    Ok((frame_type, _)) => debug!("忽略 {:?} 类型的数据报", frame_type),
+                    Err(e) => warn!("数据报解析失败: {}", e),
+                }
+            }
+        }
+
+        // 握手完成后（可能就在本轮读取中）立即发送测试消息
+        if let Some(seed) = session_seed.as_deref().filter(|_| !req_sent) {
+            info!("正在发送消息 {}", url.path());
+
+            // ============ 修改开始：使用统一流管理器发送普通消息 ============
+            let mut whisper = Whisper::default();
+            whisper.id = uuid::Uuid::new_v4().as_bytes().to_vec();
+            whisper.payload = Some(Payload::Content("测试普通消息(动态帧+调度)".to_string()));
+            whisper.timestamp_ns = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+            whisper.priority = Priority::Normal as i32;
+
+            let whisper_bytes = whisper.encode_to_vec();
+
+            // 启用消息合并时，消息先进入当前批次，批次满或到期后作为一个批量帧发送
+            // （掩护流量的恒定单元已隐藏消息数量与大小，此时不再合并）
+            if let Some(coalescer) = coalescer.as_mut().filter(|_| pacer.is_none()) {
+                if let Some(batch) = coalescer.push(&whisper_bytes, std::time::Instant::now()) {
+                    send_batch(&mut conn, &mut stream_manager, &mut stream_generators, &mut stream_ratchets, seed, silent_config, batch);
+                }
+                info!("普通消息已加入合并批次 (当前 {} 条)", coalescer.pending());
+            } else if let Some((stream_id, data_to_send)) = stream_manager.allocate_stream_for_normal_message(whisper_bytes, Priority::Normal) {
+                // 1. 已分配流（未合并时每条消息一个流）
+                if let Some(pacer) = pacer.as_mut() {
+                    // 恒定速率模式：消息排队，在下一个时隙中构建动态帧并发送
+                    pacer.enqueue(stream_id, data_to_send, true);
+                    info!("普通消息已排队等待发送时隙 (流ID: {})", stream_id);
+                    stream_manager.mark_frame_sent(stream_id);
+                } else {
+                    // 2. 获取生成器
+                    let generator = stream_generators.entry(stream_id).or_insert_with(|| {
+                        stream_generator(&mut stream_ratchets, seed, stream_id, Direction::ClientToServer)
+                    });
+
+                    // 3. 构建动态帧
+                    match build_dynamic_frame(generator, &data_to_send, silent_config) {
+                        Ok(framed_data) => {
+                            // 4. 发送
+                            match conn.stream_send(stream_id, &framed_data, true) {
+                                Ok(_) => {
+                                    info!("普通消息已发送 (流ID: {})", stream_id);
+                                    stream_manager.mark_frame_sent(stream_id); // 立即标记因为stream_send是非阻塞的writer
+                                    // 注意：实际上stream_send只是写入buffer，不代表ACK。
+                                    // Scheduler的mark_frame_sent通常意味着"流已由该帧占用完成"。
+                                    // 对于StreamPool，release_stream应该在确认收到或者流关闭时调用？
+                                    // quiche中 fin=true 会关闭流的写端。
+                                    // 需要等待 fin ack 吗？ StreamPool用于限制并发流数量。
+                                    // 简单起见，我们在发送后释放，或者等待 receiving ack?
+                                    // StreamManager logic calls release_stream in mark_frame_sent.
+                                },
+                                Err(e) => error!("发送失败: {:?}", e),
+                            }
+                        },
+                        Err(e) => error!("分帧失败: {}", e),
+                    }
+                }
+            } else {
+                warn!("无法分配流发送普通消息 (可能是流耗尽)");
+            }
+            // ============ 修改结束 ============
+
+            // 新增：发送关键信令（FEC保护）- 使用分帧版本
+            match send_critical_message_integrated(&mut conn, &mut fec_encoder, &mut stream_manager, &mut stream_generators, &mut stream_ratchets, seed, silent_config, pacer.as_mut(), "这是一条关键信令(动态帧)！") {
+                Ok(_) => info!("关键信令发送成功"),
+                Err(e) => error!("关键信令发送失败: {}", e),
+            }
+
+            // 新增：以数据报模式发送低延迟消息（不受流上丢包造成的队头阻塞影响）
+            // 启用掩护流量时不发送，以免数据报的发送时机暴露真实消息
+            if let (None, Some((sender, _))) = (pacer.as_ref(), datagrams.as_mut()) {
+                match send_datagram_message(&mut conn, sender, silent_config, "测试低延迟消息(数据报)") {
+                    Ok(()) => info!("数据报消息已发送"),
+                    Err(e) => error!("数据报消息发送失败: {}", e),
+                }
+            }
+
+            req_sent = true;
+        }
+
+        // 恒定速率发送：填充到期的时隙
+        if let (Some(pacer), Some(seed)) = (pacer.as_mut(), session_seed.as_deref()) {
+            send_paced_slots(&mut conn, pacer, &mut stream_generators, &mut stream_ratchets, seed, silent_config);
+        }
+
+        // 消息合并：发送已满或已到期的批次
+        if let (Some(coalescer), Some(seed)) = (coalescer.as_mut(), session_seed.as_deref()) {
+            while let Some(batch) = coalescer.poll(std::time::Instant::now()) {
+                send_batch(&mut conn, &mut stream_manager, &mut stream_generators, &mut stream_ratchets, seed, silent_config, batch);
+            }
+        }
+
         // Generate outgoing QUIC packets and send them on the UDP socket, until
         // quiche reports that there are no more packets to be sent.
         loop {
@@ -351,14 +601,167 @@ fn main() {
             break;
         }
     }
+
+    // 保存会话状态，下次运行时凭票据恢复（与QUIC会话一起，可发送0-RTT数据）
+    if let (Some(path), Some(ticket), Some(base_seed)) = (resumption_file.as_deref(), ticket, session_seed) {
+        let state = SessionState {
+            base_seed,
+            config: silent_config.encode_wire(),
+            senders: stream_generators,
+            parsers: stream_parsers,
+            datagrams,
+        };
+        let saved = state.seal(&ticket).map(|sealed| SavedSession {
+            ticket,
+            sealed,
+            quic_session: conn.session().map(<[u8]>::to_vec),
+        });
+        match saved.and_then(|saved| saved.store(path)) {
+            Ok(()) => info!("会话状态已保存到 {}", path.display()),
+            Err(e) => error!("保存会话状态失败: {}", e),
+        }
+    }
+}
+
+/// 发起完整的会话握手：在握手控制流上发送ClientHello
+///
+/// 创建握手失败时关闭连接并返回None。
+fn start_handshake(conn: &mut quiche::Connection, auth: &HandshakeAuth, config: SilentConfig) -> Option<ClientHandshake> {
+    match ClientHandshake::new(auth, config) {
+        Ok((state, client_hello)) => {
+            match conn.stream_send(CLIENT_HANDSHAKE_STREAM_ID, &client_hello, false) {
+                Ok(_) => info!("已发送ClientHello"),
+                Err(e) => error!("发送ClientHello失败: {:?}", e),
+            }
+            Some(state)
+        }
+        Err(e) => {
+            error!("创建会话握手失败: {}", e);
+            conn.close(false, 0x1, b"handshake failed").ok();
+            None
+        }
+    }
+}
+
+/// 用上次保存的会话发起会话恢复：在会话恢复流上发送ResumeHello
+///
+/// 返回等待服务端应答的恢复请求、恢复的会话状态和当时协商的分帧配置。
+/// 保存的状态此时已被打开，失败后只能完整握手。
+fn resume_session(
+    conn: &mut quiche::Connection,
+    saved: SavedSession,
+    local_config: SilentConfig,
+) -> Result<(ResumeAttempt, SessionState, SilentConfig), String> {
+    let (attempt, state) = saved.resume().map_err(|e| e.to_string())?;
+    let config = state.negotiated_config(local_config).map_err(|e| e.to_string())?;
+    conn.stream_send(CLIENT_RESUME_STREAM_ID, attempt.hello(), false)
+        .map_err(|e| format!("发送ResumeHello失败: {:?}", e))?;
+    Ok((attempt, state, config))
+}
+
+/// 以数据报模式发送一条文本消息
+///
+/// 数据报不可靠且无序，但不会等待流上丢失数据包的重传。
+/// 对端未启用DATAGRAM扩展或帧超过可写长度时返回错误。
+fn send_datagram_message(
+    conn: &mut quiche::Connection,
+    generator: &mut SaltGenerator,
+    config: SilentConfig,
+    message: &str,
+) -> Result<(), String> {
+    let max_len = conn.dgram_max_writable_len().ok_or("对端未启用DATAGRAM扩展")?;
+
+    let mut whisper = Whisper::default();
+    whisper.id = uuid::Uuid::new_v4().as_bytes().to_vec();
+    whisper.payload = Some(Payload::Content(message.to_string()));
+    whisper.timestamp_ns = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    whisper.priority = Priority::High as i32;
+
+    let frame = build_datagram_frame(generator, FrameType::Data, &whisper.encode_to_vec(), config)
+        .map_err(|e| format!("构建数据报帧失败: {}", e))?;
+    if frame.len() > max_len {
+        return Err(format!("数据报帧过大: {} > {}", frame.len(), max_len));
+    }
+    conn.dgram_send(&frame).map_err(|e| format!("{:?}", e))
+}
+
+/// 将一个合并批次作为批量帧（FrameType::Batch）在新分配的流上发送
+///
+/// 整批消息只占用一个流和一个帧，服务端解析器会将其拆分为单条消息。
+fn send_batch(
+    conn: &mut quiche::Connection,
+    manager: &mut UnifiedStreamManager,
+    generators: &mut HashMap<u64, SaltGenerator>,
+    ratchets: &mut HashMap<u64, RatchetHandle>,
+    session_seed: &[u8; 32],
+    config: SilentConfig,
+    batch: Vec<u8>,
+) {
+    let Some((stream_id, batch)) = manager.allocate_stream_for_normal_message(batch, Priority::Normal) else {
+        warn!("无法分配流发送合并批次 (可能是流耗尽)");
+        return;
+    };
+    let generator = generators.entry(stream_id).or_insert_with(|| {
+        stream_generator(ratchets, session_seed, stream_id, Direction::ClientToServer)
+    });
+    match build_typed_frame(generator, FrameType::Batch, &batch, config) {
+        Ok(frame) => match conn.stream_send(stream_id, &frame, true) {
+            Ok(_) => {
+                info!("合并批次已发送 (流ID: {}, {} 字节)", stream_id, batch.len());
+                manager.mark_frame_sent(stream_id);
+            }
+            Err(e) => error!("合并批次发送失败: {:?}", e),
+        },
+        Err(e) => error!("合并批次分帧失败: {}", e),
+    }
+}
+
+/// 在到期的发送时隙中发送排队消息，空闲时隙在掩护流上发送掩护帧
+///
+/// 帧大小由发送器决定（恒定单元或流量变形分布），观察者无法区分真实消息与掩护帧。
+fn send_paced_slots(
+    conn: &mut quiche::Connection,
+    pacer: &mut PacedSender,
+    generators: &mut HashMap<u64, SaltGenerator>,
+    ratchets: &mut HashMap<u64, RatchetHandle>,
+    session_seed: &[u8; 32],
+    config: SilentConfig,
+) {
+    if !conn.is_established() {
+        return;
+    }
+
+    let now = std::time::Instant::now();
+    while let Some(slot) = pacer.next_slot(now) {
+        let generator = generators.entry(slot.stream_id).or_insert_with(|| {
+            stream_generator(ratchets, session_seed, slot.stream_id, Direction::ClientToServer)
+        });
+        match slot.build(generator, config) {
+            Ok(frame) => {
+                if let Err(e) = conn.stream_send(slot.stream_id, &frame, slot.fin) {
+                    error!("流 {} 时隙发送失败: {:?}", slot.stream_id, e);
+                }
+            }
+            Err(e) => error!("流 {} 构建时隙帧失败: {}", slot.stream_id, e),
+        }
+    }
 }
 
 // Integrated Critical Message Sending
+// With cover traffic enabled (`pacer`), the FEC frames are queued for the next slots instead.
+#[allow(clippy::too_many_arguments)]
 fn send_critical_message_integrated(
     conn: &mut quiche::Connection,
     encoder: &mut FECEncoder,
     manager: &mut UnifiedStreamManager,
     generators: &mut HashMap<u64, SaltGenerator>,
+    ratchets: &mut HashMap<u64, RatchetHandle>,
+    session_seed: &[u8; 32],
+    config: SilentConfig,
+    mut pacer: Option<&mut PacedSender>,
     message: &str,
 ) -> Result<(), String> {
     
@@ -392,13 +795,21 @@ fn send_critical_message_integrated(
         // Serialize
         let bytes = whisper.encode_to_vec();
         
+        // Paced mode: the dynamic frame is built when its slot comes up
+        if let Some(pacer) = pacer.as_deref_mut() {
+            pacer.enqueue(stream_id, bytes, true);
+            debug!("FEC帧已排队: 流ID={}", stream_id);
+            manager.mark_frame_sent(stream_id);
+            continue;
+        }
+        
         // Get Generator
         let generator = generators.entry(stream_id).or_insert_with(|| {
-             SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id)
+             stream_generator(ratchets, session_seed, stream_id, Direction::ClientToServer)
         });
         
         // Dynamic Frame
-        let framed_bytes = build_dynamic_frame(generator, &bytes, SilentConfig::default())
+        let framed_bytes = build_dynamic_frame(generator, &bytes, config)
              .map_err(|e| format!("Framing Error: {}", e))?;
              
         // Send via QUIC
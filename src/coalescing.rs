@@ -0,0 +1,205 @@
+//! Message Coalescing Module
+//!
+//! Packs several encoded messages (e.g. `Whisper`s) into one `FrameType::Batch` frame
+//! instead of giving each its own frame (and, in the client, its own stream). That saves
+//! the per-frame overhead and hides how many messages were sent.
+//!
+//! Batch payload: [Length (4 bytes)] [Message], repeated
+//!
+//! `Coalescer` only collects messages: it hands out a batch once it reaches
+//! `CoalescingPolicy::max_batch_size` or its oldest message has waited `max_delay`.
+//! The event loop builds the frame (`build_typed_frame` with `FrameType::Batch`), and
+//! `DynamicStreamParser` unpacks batches on the receiving side, so receivers see the
+//! messages one by one whatever way they were sent.
+
+use crate::dynamic_framing::DynamicFramingError;
+use std::time::{Duration, Instant};
+
+/// Environment variable enabling message coalescing in the client:
+/// `max_batch_size[:max_delay_ms]`
+pub const COALESCING_ENV_VAR: &str = "SILENT_SPEAKER_COALESCING";
+
+/// Default time the first message of a batch may wait for others
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(10);
+
+/// Size of the length prefix of every message in a batch
+const BATCH_LENGTH_LEN: usize = 4;
+
+/// When `Coalescer` hands out a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalescingPolicy {
+    /// Batch payload size (length prefixes included) that triggers a flush
+    pub max_batch_size: usize,
+    /// Longest a message waits for others before its batch is flushed
+    pub max_delay: Duration,
+}
+
+impl CoalescingPolicy {
+    /// Parse `max_batch_size[:max_delay_ms]`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split(':');
+        let max_batch_size = parts.next()?.parse().ok().filter(|size| *size > 0)?;
+        let max_delay = match parts.next() {
+            Some(ms) => Duration::from_millis(ms.parse().ok()?),
+            None => DEFAULT_MAX_DELAY,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { max_batch_size, max_delay })
+    }
+
+    /// Settings from `COALESCING_ENV_VAR` (None if unset or invalid)
+    pub fn from_env() -> Option<Self> {
+        std::env::var(COALESCING_ENV_VAR).ok().and_then(|value| Self::parse(&value))
+    }
+}
+
+/// Append one message to a batch payload
+pub fn push_batch_entry(batch: &mut Vec<u8>, message: &[u8]) {
+    batch.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    batch.extend_from_slice(message);
+}
+
+/// Split a batch payload into its messages
+pub fn split_batch(mut payload: &[u8]) -> Result<Vec<&[u8]>, DynamicFramingError> {
+    let mut messages = Vec::new();
+    while !payload.is_empty() {
+        if payload.len() < BATCH_LENGTH_LEN {
+            return Err(DynamicFramingError::InvalidLength(payload.len()));
+        }
+        let (length, rest) = payload.split_at(BATCH_LENGTH_LEN);
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        if length > rest.len() {
+            return Err(DynamicFramingError::InvalidLength(length));
+        }
+        let (message, rest) = rest.split_at(length);
+        messages.push(message);
+        payload = rest;
+    }
+    Ok(messages)
+}
+
+/// Collects messages into batch payloads (see the module documentation)
+pub struct Coalescer {
+    policy: CoalescingPolicy,
+    batch: Vec<u8>,
+    count: usize,
+    /// When the first message of the current batch was pushed
+    oldest: Option<Instant>,
+}
+
+impl Coalescer {
+    pub fn new(policy: CoalescingPolicy) -> Self {
+        Self { policy, batch: Vec::new(), count: 0, oldest: None }
+    }
+
+    pub fn policy(&self) -> CoalescingPolicy {
+        self.policy
+    }
+
+    /// Add a message to the current batch.
+    ///
+    /// If the message does not fit into `max_batch_size` next to the messages already
+    /// waiting, those are returned as a batch first and the message starts the next one.
+    /// Call `poll` afterwards: the new batch may be full already.
+    pub fn push(&mut self, message: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let full = !self.batch.is_empty()
+            && self.batch.len() + BATCH_LENGTH_LEN + message.len() > self.policy.max_batch_size;
+        let flushed = if full { self.flush() } else { None };
+        if self.batch.is_empty() {
+            self.oldest = Some(now);
+        }
+        push_batch_entry(&mut self.batch, message);
+        self.count += 1;
+        flushed
+    }
+
+    /// Take the current batch if it is full or has waited `max_delay` at `now`
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let full = self.batch.len() >= self.policy.max_batch_size;
+        let due = self.oldest.is_some_and(|oldest| now.duration_since(oldest) >= self.policy.max_delay);
+        if full || due { self.flush() } else { None }
+    }
+
+    /// Time until the current batch is due (for the event loop's poll timeout)
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.oldest.map(|oldest| (oldest + self.policy.max_delay).saturating_duration_since(now))
+    }
+
+    /// Take the current batch, if any, whatever its size and age
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.batch.is_empty() {
+            return None;
+        }
+        self.count = 0;
+        self.oldest = None;
+        Some(std::mem::take(&mut self.batch))
+    }
+
+    /// Number of messages in the current batch
+    pub fn pending(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_framing::{build_typed_frame, DynamicStreamParser, FrameType, SaltGenerator, SilentConfig};
+
+    fn policy(max_batch_size: usize) -> CoalescingPolicy {
+        CoalescingPolicy { max_batch_size, max_delay: Duration::from_millis(10) }
+    }
+
+    #[test]
+    fn test_size_and_time_thresholds() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(policy(32));
+        assert_eq!(coalescer.push(b"first", start), None);
+        assert_eq!(coalescer.push(b"second", start), None);
+        assert_eq!(coalescer.pending(), 2);
+        assert_eq!(coalescer.poll(start), None);
+        assert_eq!(coalescer.timeout(start), Some(Duration::from_millis(10)));
+
+        // The third message does not fit: the first two leave as one batch
+        let batch = coalescer.push(b"third message", start).unwrap();
+        assert_eq!(split_batch(&batch).unwrap(), vec![&b"first"[..], b"second"]);
+        assert_eq!(coalescer.pending(), 1);
+
+        // The rest leaves once it has waited long enough
+        assert_eq!(coalescer.poll(start + Duration::from_millis(9)), None);
+        let batch = coalescer.poll(start + Duration::from_millis(10)).unwrap();
+        assert_eq!(split_batch(&batch).unwrap(), vec![&b"third message"[..]]);
+        assert_eq!(coalescer.timeout(start), None);
+
+        // A message larger than the threshold is a full batch on its own
+        assert_eq!(coalescer.push(&[7; 40], start), None);
+        assert_eq!(split_batch(&coalescer.poll(start).unwrap()).unwrap(), vec![&[7u8; 40][..]]);
+
+        assert!(split_batch(&[0, 0, 0, 9, 1, 2]).is_err());
+        assert_eq!(CoalescingPolicy::parse("1200:5"), Some(CoalescingPolicy { max_batch_size: 1200, max_delay: Duration::from_millis(5) }));
+        assert_eq!(CoalescingPolicy::parse("0"), None);
+    }
+
+    #[test]
+    fn test_parser_unpacks_batches() {
+        let config = SilentConfig::default();
+        let mut sender = SaltGenerator::new(&[24u8; 32]);
+        let mut coalescer = Coalescer::new(policy(1024));
+        for message in [&b"one"[..], b"", b"three"] {
+            coalescer.push(message, Instant::now());
+        }
+        let mut wire = build_typed_frame(&mut sender, FrameType::Batch, &coalescer.flush().unwrap(), config).unwrap();
+        wire.extend(build_typed_frame(&mut sender, FrameType::Data, b"single", config).unwrap());
+
+        // One frame on the wire, three messages out of the parser, then the next frame
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&[24u8; 32]));
+        parser.append_data(&wire).unwrap();
+        let mut received = Vec::new();
+        while let Some(message) = parser.try_parse_next(config).unwrap() {
+            received.push(message);
+        }
+        assert_eq!(received, vec![b"one".to_vec(), Vec::new(), b"three".to_vec(), b"single".to_vec()]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Tokio Codec Module (feature `tokio-codec`)
+//!
+//! `WhisperCodec` implements `tokio_util::codec::{Encoder, Decoder}` on top of the
+//! dynamic framing layer, so async services can use `Framed` / `FramedRead` /
+//! `FramedWrite` on any `AsyncRead + AsyncWrite` transport and exchange `Whisper`
+//! messages directly.
+//!
+//! Decoding works on the codec's read buffer in place (`DynamicStreamParser::parse_next_from`):
+//! incomplete frames stay in the buffer and the receiving generator only advances
+//! once a frame has been authenticated, so nothing has to be rolled back.
+
+use crate::dynamic_framing::{build_dynamic_frame, DynamicFramingError, DynamicStreamParser, FrameType, SaltGenerator, SilentConfig};
+use crate::whisper::Whisper;
+use bytes::{Buf, BytesMut};
+use prost::Message;
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Framing error: {0}")]
+    Framing(#[from] DynamicFramingError),
+
+    #[error("Invalid Whisper message: {0}")]
+    Decode(#[from] prost::DecodeError),
+}
+
+/// Dynamic framing codec for `Whisper` messages on one stream
+pub struct WhisperCodec {
+    generator: SaltGenerator,
+    parser: DynamicStreamParser,
+    config: SilentConfig,
+}
+
+impl WhisperCodec {
+    /// `sender` frames outgoing messages, `receiver` parses the peer's frames
+    /// (see `SaltGenerator::new_directional`); both peers must use the same `config`
+    pub fn new(sender: SaltGenerator, receiver: SaltGenerator, config: SilentConfig) -> Self {
+        Self {
+            generator: sender,
+            parser: DynamicStreamParser::new(receiver),
+            config,
+        }
+    }
+
+    /// Handle control frames (see `DynamicStreamParser::set_control_handler`)
+    pub fn set_control_handler(&mut self, handler: impl FnMut(FrameType, &[u8]) + Send + 'static) {
+        self.parser.set_control_handler(handler);
+    }
+
+    pub fn config(&self) -> SilentConfig {
+        self.config
+    }
+}
+
+impl Decoder for WhisperCodec {
+    type Item = Whisper;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Whisper>, CodecError> {
+        loop {
+            let (consumed, result) = self.parser.parse_next_from(&mut src[..], self.config);
+            src.advance(consumed);
+            match result {
+                Ok(Some(payload)) => return Ok(Some(Whisper::decode(payload.as_slice())?)),
+                Ok(None) => return Ok(None),
+                // An error ends a framed stream, so frames that can simply be
+                // dropped (duplicates, epochs we cannot open) are skipped here
+                Err(e @ (DynamicFramingError::Replay { .. } | DynamicFramingError::UnknownEpoch { .. })) => {
+                    warn!("Dropping frame: {}", e);
+                }
+                // The frame stays in `src` and is decoded again along with the next bytes
+                Err(DynamicFramingError::ResyncRateLimited) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Encoder<Whisper> for WhisperCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Whisper, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let frame = build_dynamic_frame(&mut self.generator, &item.encode_to_vec(), self.config)?;
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_framing::{build_typed_frame, Direction};
+    use crate::whisper::whisper::Payload;
+
+    fn whisper(text: &str) -> Whisper {
+        Whisper { id: vec![7; 16], payload: Some(Payload::Content(text.to_string())), ..Whisper::default() }
+    }
+
+    fn codecs(config: SilentConfig) -> (WhisperCodec, WhisperCodec) {
+        let seed = [21u8; 32];
+        let generator = |direction| SaltGenerator::new_directional(&seed, 4, direction);
+        (
+            WhisperCodec::new(generator(Direction::ClientToServer), generator(Direction::ServerToClient), config),
+            WhisperCodec::new(generator(Direction::ServerToClient), generator(Direction::ClientToServer), config),
+        )
+    }
+
+    #[test]
+    fn test_codec_roundtrip_byte_by_byte() {
+        let (mut client, mut server) = codecs(SilentConfig::default());
+        let mut wire = BytesMut::new();
+        client.encode(whisper("hello"), &mut wire).unwrap();
+        client.encode(whisper("world"), &mut wire).unwrap();
+
+        // Incomplete frames stay buffered until the rest arrives
+        let mut received = Vec::new();
+        let mut src = BytesMut::new();
+        for byte in wire.iter() {
+            src.extend_from_slice(&[*byte]);
+            while let Some(message) = server.decode(&mut src).unwrap() {
+                received.push(message);
+            }
+        }
+        assert_eq!(received, vec![whisper("hello"), whisper("world")]);
+        assert!(src.is_empty());
+
+        // And in the other direction
+        let mut reply = BytesMut::new();
+        server.encode(whisper("ack"), &mut reply).unwrap();
+        assert_eq!(client.decode(&mut reply).unwrap(), Some(whisper("ack")));
+    }
+
+    #[test]
+    fn test_codec_skips_chaff_and_replays() {
+        let config = SilentConfig::default();
+        let (mut client, mut server) = codecs(config);
+        let mut wire = BytesMut::new();
+        let chaff = build_typed_frame(&mut client.generator, FrameType::Chaff, b"", config).unwrap();
+        wire.extend_from_slice(&chaff);
+        client.encode(whisper("real"), &mut wire).unwrap();
+        wire.extend_from_slice(&chaff);
+
+        assert_eq!(server.decode(&mut wire).unwrap(), Some(whisper("real")));
+        assert_eq!(server.decode(&mut wire).unwrap(), None);
+        assert!(wire.is_empty());
+
+        // Garbage is scanned past, up to the next frame...
+        let mut garbage = BytesMut::from(&[0xAAu8; 64][..]);
+        client.encode(whisper("after garbage"), &mut garbage).unwrap();
+        assert_eq!(server.decode(&mut garbage).unwrap(), Some(whisper("after garbage")));
+
+        // ...unless there is more of it than the scan budget
+        let (_, mut strict) = codecs(SilentConfig { resync_scan_budget: 16, ..config });
+        let mut garbage = BytesMut::from(&[0xAAu8; 64][..]);
+        assert!(strict.decode(&mut garbage).is_err());
+    }
+}
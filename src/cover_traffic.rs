@@ -0,0 +1,320 @@
+//! Cover Traffic Module
+//!
+//! Constant-rate sending for traffic-analysis resistance:
+//! - Frames leave at a fixed rate (plus optional jitter), one per slot
+//! - Every frame is a cell of the same size; longer messages span several slots
+//!   (continuation flag, see `SilentConfig::cell_size`)
+//! - Real messages are queued into the slots; idle slots carry chaff frames
+//!   (`FrameType::Chaff`), which the receiver drops silently
+//!
+//! The same scheduler drives traffic morphing (`PacedSender::morphing`), where the
+//! size and timing of every slot are drawn from a `MorphingProfile` instead.
+//!
+//! `PacedSender` only decides *what* goes out in each slot. The event loop
+//! owns the QUIC connection and the per-stream generators, builds the frame
+//! for each `Slot` and hands it to `quiche::Connection::stream_send`.
+
+use crate::dynamic_framing::{self, DynamicFramingError, FrameType, SaltGenerator, SilentConfig, MIN_CELL_SIZE};
+use crate::morphing::{MorphingProfile, ShapingRng};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Environment variable enabling cover traffic in the binaries:
+/// `frames_per_second[:cell_size[:jitter_ms]]`
+pub const COVER_TRAFFIC_ENV_VAR: &str = "SILENT_SPEAKER_COVER_TRAFFIC";
+
+/// Stream carrying chaff when no message is queued.
+/// Stream 0 is reserved by the client's stream manager, so it never carries messages.
+pub const COVER_STREAM_ID: u64 = 0;
+
+/// Default encrypted body size of every frame in constant-rate mode
+pub const DEFAULT_CELL_SIZE: usize = 1024;
+
+/// Constant-rate sending parameters (see `SilentConfig::cover_traffic`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverTraffic {
+    /// Slots per second
+    pub frames_per_second: u32,
+    /// Encrypted body size of every frame (larger messages are split across slots)
+    pub cell_size: usize,
+    /// Maximum random deviation of each slot from the nominal interval
+    pub jitter: Duration,
+}
+
+impl CoverTraffic {
+    /// Parse `frames_per_second[:cell_size[:jitter_ms]]`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split(':');
+        let frames_per_second = parts.next()?.parse().ok().filter(|rate| *rate > 0)?;
+        let cell_size = match parts.next() {
+            Some(size) => size.parse().ok().filter(|size| *size >= MIN_CELL_SIZE)?,
+            None => DEFAULT_CELL_SIZE,
+        };
+        let jitter = match parts.next() {
+            Some(ms) => Duration::from_millis(ms.parse().ok()?),
+            None => Duration::ZERO,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { frames_per_second, cell_size, jitter })
+    }
+
+    /// Settings from `COVER_TRAFFIC_ENV_VAR` (None if unset or invalid)
+    pub fn from_env() -> Option<Self> {
+        std::env::var(COVER_TRAFFIC_ENV_VAR).ok().and_then(|value| Self::parse(&value))
+    }
+
+    /// Nominal time between two slots
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.frames_per_second.max(1)
+    }
+}
+
+/// What to send in one slot: a single frame whose encrypted body is `body_size` bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    pub stream_id: u64,
+    /// `FrameType::Data` for a queued message, `FrameType::Chaff` when nothing is queued
+    pub frame_type: FrameType,
+    /// The message, or the part of it that fits into this slot
+    pub payload: Vec<u8>,
+    /// Finish the stream with this frame
+    pub fin: bool,
+    /// The message continues in a later slot
+    pub more: bool,
+    pub body_size: usize,
+}
+
+impl Slot {
+    /// Build the frame of this slot with the sending generator of `stream_id`
+    pub fn build(&self, generator: &mut SaltGenerator, config: SilentConfig) -> Result<Vec<u8>, DynamicFramingError> {
+        dynamic_framing::build_cell(generator, self.frame_type, self.more, &self.payload, self.body_size, config)
+    }
+}
+
+/// Where the size and timing of the slots come from
+#[derive(Debug, Clone)]
+enum Shape {
+    Constant(CoverTraffic),
+    Morphing(Arc<MorphingProfile>),
+}
+
+/// A message waiting for a slot
+struct QueuedMessage {
+    stream_id: u64,
+    payload: Vec<u8>,
+    fin: bool,
+}
+
+/// Slot scheduler sitting between the stream manager and `stream_send`
+pub struct PacedSender {
+    shape: Shape,
+    queue: VecDeque<QueuedMessage>,
+    next_slot: Instant,
+    cover_stream: Option<u64>,
+    rng: ShapingRng,
+}
+
+impl PacedSender {
+    /// Create a constant-rate sender whose first slot is due immediately
+    pub fn new(settings: CoverTraffic) -> Self {
+        Self::with_shape(Shape::Constant(settings))
+    }
+
+    /// Create a sender whose slots follow `profile` (traffic morphing)
+    pub fn morphing(profile: Arc<MorphingProfile>) -> Self {
+        Self::with_shape(Shape::Morphing(profile))
+    }
+
+    /// Sender for the shaping selected, if any: a `morphing` profile takes
+    /// precedence over `SilentConfig::cover_traffic`
+    pub fn from_config(config: &SilentConfig, morphing: Option<Arc<MorphingProfile>>) -> Option<Self> {
+        match (morphing, config.cover_traffic) {
+            (Some(profile), _) => Some(Self::morphing(profile)),
+            (None, Some(settings)) => Some(Self::new(settings)),
+            (None, None) => None,
+        }
+    }
+
+    fn with_shape(shape: Shape) -> Self {
+        Self {
+            shape,
+            queue: VecDeque::new(),
+            next_slot: Instant::now(),
+            cover_stream: None,
+            rng: ShapingRng::system(),
+        }
+    }
+
+    /// Draw jitter and morphing samples from a seeded generator instead of the
+    /// system RNG, so that a simulation is reproducible (never for live traffic)
+    pub fn seed_shaping(&mut self, seed: u64) {
+        self.rng = ShapingRng::seeded(seed);
+    }
+
+    /// Make the first slot due at `at` instead of at creation (simulated clocks)
+    pub fn start_at(&mut self, at: Instant) {
+        self.next_slot = at;
+    }
+
+    /// Stream used for chaff; until it is set, idle slots stay silent
+    pub fn set_cover_stream(&mut self, stream_id: u64) {
+        self.cover_stream = Some(stream_id);
+    }
+
+    pub fn cover_stream(&self) -> Option<u64> {
+        self.cover_stream
+    }
+
+    /// Queue a message for the next free slots
+    pub fn enqueue(&mut self, stream_id: u64, payload: Vec<u8>, fin: bool) {
+        self.queue.push_back(QueuedMessage { stream_id, payload, fin });
+    }
+
+    /// Number of messages waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Time until the next slot (for the event loop's poll timeout)
+    pub fn timeout(&self, now: Instant) -> Duration {
+        self.next_slot.saturating_duration_since(now)
+    }
+
+    /// Take the slot that is due at `now`, if any.
+    ///
+    /// Call it until it returns None. Slots missed while the event loop was busy
+    /// are dropped rather than sent in a burst, so the rate never exceeds the setting.
+    pub fn next_slot(&mut self, now: Instant) -> Option<Slot> {
+        if now < self.next_slot {
+            return None;
+        }
+        let (interval, body_size) = match &self.shape {
+            &Shape::Constant(settings) => (self.jittered(settings.interval(), settings.jitter), settings.cell_size),
+            Shape::Morphing(profile) => (profile.sample_interval(&mut self.rng), profile.sample_size(&mut self.rng)),
+        };
+        if now.duration_since(self.next_slot) > interval {
+            self.next_slot = now;
+        }
+        self.next_slot += interval;
+
+        // Whatever does not fit stays at the front of the queue for the next slot
+        let capacity = dynamic_framing::cell_capacity(body_size);
+        let (stream_id, payload, fin, more) = match self.queue.front_mut() {
+            Some(message) if message.payload.len() > capacity => {
+                let rest = message.payload.split_off(capacity);
+                (message.stream_id, std::mem::replace(&mut message.payload, rest), false, true)
+            }
+            Some(_) => {
+                let message = self.queue.pop_front()?;
+                (message.stream_id, message.payload, message.fin, false)
+            }
+            None => {
+                let stream_id = self.cover_stream?;
+                return Some(Slot { stream_id, frame_type: FrameType::Chaff, payload: Vec::new(), fin: false, more: false, body_size });
+            }
+        };
+        Some(Slot { stream_id, frame_type: FrameType::Data, payload, fin, more, body_size })
+    }
+
+    /// `interval` moved by a uniformly random amount in [-jitter, +jitter]
+    fn jittered(&mut self, interval: Duration, jitter: Duration) -> Duration {
+        let jitter = jitter.min(interval);
+        if jitter.is_zero() {
+            return interval;
+        }
+        let Some(draw) = self.rng.next_u64() else {
+            return interval;
+        };
+        let span = jitter.as_nanos() as u64 * 2 + 1;
+        let offset = draw % span;
+        (interval + Duration::from_nanos(offset)).saturating_sub(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_framing::DynamicStreamParser;
+
+    fn settings(frames_per_second: u32) -> CoverTraffic {
+        CoverTraffic { frames_per_second, cell_size: 256, jitter: Duration::ZERO }
+    }
+
+    fn data(stream_id: u64, payload: &[u8], fin: bool, more: bool, body_size: usize) -> Slot {
+        Slot { stream_id, frame_type: FrameType::Data, payload: payload.to_vec(), fin, more, body_size }
+    }
+
+    #[test]
+    fn test_constant_rate_slots() {
+        let mut sender = PacedSender::new(settings(100));
+        let start = sender.next_slot;
+        sender.set_cover_stream(COVER_STREAM_ID);
+
+        // Idle slots carry chaff, one per 10ms
+        let chaff = sender.next_slot(start).unwrap();
+        assert_eq!((chaff.stream_id, chaff.frame_type, chaff.body_size), (COVER_STREAM_ID, FrameType::Chaff, 256));
+        assert_eq!(sender.next_slot(start), None);
+        assert_eq!(sender.timeout(start), Duration::from_millis(10));
+
+        // Queued messages take the next slots, in order
+        sender.enqueue(4, b"first".to_vec(), false);
+        sender.enqueue(8, b"second".to_vec(), true);
+        let slot = start + Duration::from_millis(10);
+        assert_eq!(sender.next_slot(slot), Some(data(4, b"first", false, false, 256)));
+        assert_eq!(sender.next_slot(slot), None);
+        let slot = slot + Duration::from_millis(10);
+        assert_eq!(sender.next_slot(slot), Some(data(8, b"second", true, false, 256)));
+        assert_eq!(sender.queued(), 0);
+
+        // A stalled event loop does not cause a burst afterwards
+        let late = slot + Duration::from_secs(1);
+        assert!(sender.next_slot(late).is_some());
+        assert!(sender.next_slot(late).is_none());
+    }
+
+    #[test]
+    fn test_morphing_splits_messages() {
+        let profile = MorphingProfile::parse("size 128 1\ninterval 20 1").unwrap();
+        let config = SilentConfig::default();
+        let mut sender = PacedSender::from_config(&config, Some(Arc::new(profile))).unwrap();
+        let message: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        sender.enqueue(4, message.clone(), true);
+
+        // 107 bytes fit into a 128-byte body: two continued slots, then the rest
+        let mut generator = SaltGenerator::new(&[3u8; 32]);
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&[3u8; 32]));
+        let mut now = sender.next_slot;
+        let mut slots = Vec::new();
+        while let Some(slot) = sender.next_slot(now) {
+            let frame = slot.build(&mut generator, config).unwrap();
+            assert_eq!(frame.len(), 7 + 128);
+            parser.append_data(&frame).unwrap();
+            slots.push((slot.payload.len(), slot.more, slot.fin));
+            now += Duration::from_millis(20);
+        }
+        assert_eq!(slots, vec![(107, true, false), (107, true, false), (36, false, true)]);
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), message);
+    }
+
+    #[test]
+    fn test_jitter_and_parsing() {
+        let mut jittery = settings(10);
+        jittery.jitter = Duration::from_millis(30);
+        let mut sender = PacedSender::new(jittery);
+        for _ in 0..50 {
+            let now = sender.next_slot;
+            sender.next_slot(now);
+            let gap = sender.timeout(now);
+            assert!(gap >= Duration::from_millis(70) && gap <= Duration::from_millis(130));
+        }
+
+        assert_eq!(CoverTraffic::parse("50"), Some(CoverTraffic { frames_per_second: 50, cell_size: DEFAULT_CELL_SIZE, jitter: Duration::ZERO }));
+        assert_eq!(CoverTraffic::parse("50:512:5"), Some(CoverTraffic { frames_per_second: 50, cell_size: 512, jitter: Duration::from_millis(5) }));
+        assert_eq!(CoverTraffic::parse("0"), None);
+        assert_eq!(CoverTraffic::parse("50:abc"), None);
+        assert_eq!(CoverTraffic::parse("50:32"), None);
+    }
+}
@@ -6,15 +6,126 @@
 //! - Dynamic Frame Parsing
 //!
 //! # Frame Structure
-//! [Obfuscated Length (4 bytes)] [Encrypted Body (Data + Padding + Tag)]
+//! [Obfuscated Length (4 bytes)] [Obfuscated Version (1 byte)] [Obfuscated Hint (2 bytes, robust mode)]
+//! [Obfuscated Epoch (1 byte, double ratchet)] [Ratchet Block (rekey frames)]
+//! [Encrypted Body (Type (1 byte) + Data + Padding + Padding Length (4 bytes) + Tag)]
 //!
-//! The "Obfuscated Length" is the length of the *Encrypted Body* XORed with a mask derived from the Salt.
+//! The encrypted Type byte (see `FrameType`) lets peers send chaff and control frames
+//! that look exactly like data frames on the wire. Its high bit (`FRAME_MORE_FLAG`)
+//! marks a frame whose message continues in the next frame.
+//!
+//! `FrameType::Batch` packs several messages into one frame (see `coalescing::Coalescer`);
+//! `DynamicStreamParser` returns them one by one, like separate data frames.
+//!
+//! # Cell Mode
+//! With `SilentConfig::cell_size` every payload is split into cells: frames whose
+//! encrypted body is exactly `cell_size` bytes, all but the last carrying the
+//! continuation flag. `DynamicStreamParser` reassembles the cells into whole messages,
+//! so the wire shows a run of identical frames instead of one frame per message size.
+//!
+//! The "Obfuscated Length" is the length of the *Encrypted Body* XORed with a mask.
+//! Header masks are derived per sequence from a header key that never changes
+//! (HeaderKey = HMAC(InitialSeed, label)), so headers stay readable across rekeys.
+//! The masks only hide the header; integrity comes from the AEAD, which authenticates
+//! Version || ContextID || Sequence || Header as additional data.
+//!
+//! # Key Schedule
+//! Each direction of each stream gets an independent seed:
+//! StreamSeed = HKDF-SHA256(BaseSeed, info = DirectionLabel || StreamID)
+//! so that a stream's ACKs never reuse the key/nonce sequence of its requests.
+//!
+//! # Double Ratchet
+//! Every `ratchet_interval` frames the header carries a Ratchet Block:
+//! [Kind (1B)] [Offer PubKey (32B)] [Answer PubKey (32B) + Epoch Start (8B), if Kind has ANSWER]
+//! An OFFER is our outstanding ephemeral X25519 key. An ANSWER is a fresh key whose
+//! DH with the peer's outstanding offer is mixed into the sender's seed after the frame.
+//! Only the two endpoints can compute that DH output, so a leaked seed heals after one
+//! round trip (offer in one direction, answer in the other).
+//!
+//! Each mix opens a new epoch, identified in every frame header. Receivers keep a few
+//! previous epochs for late frames, and the answer is repeated in every rekey frame
+//! until the peer offers again, so losing the frame that opened an epoch is recoverable.
+//!
+//! # Chain Keys (Forward Secrecy)
+//! By default every salt is `SHA256(Seed || Sequence)`, so the seed unlocks every frame.
+//! With `SaltGenerator::enable_chain_keys` the seed is replaced by a chain key that is
+//! stepped once per frame and overwritten:
+//! Salt(n) = HMAC(ChainKey(n), 0x01), ChainKey(n+1) = HMAC(ChainKey(n), 0x02)
+//! Keys of frames skipped during resync are kept in a bounded cache until used.
+//!
+//! # Frame Keys
+//! A salt is never used as key material directly. Each salt is an HKDF PRK:
+//! Key = HKDF-Expand(Salt, "key" || SuiteID), Nonce = HKDF-Expand(Salt, "nonce" || SuiteID)
+//! with the AEAD picked by `SilentConfig::cipher_suite` (ChaCha20-Poly1305 or AES-256-GCM).
+//! Header masks are expanded the same way from the header key and the sequence.
+//!
+//! # Replay Protection
+//! Receivers record accepted sequences in a sliding bitmap. Robust mode looks
+//! `resync_window` frames ahead and `replay_window` frames back for the sequence
+//! of a frame; duplicates and frames older than the window are rejected as `Replay`.
+//! The expected hints of both windows are kept precomputed and slid along with the
+//! receiver, and the number of searches per second is capped (`max_resyncs_per_second`).
+//!
+//! # Resynchronization
+//! A frame that fails to parse no longer costs the whole stream: `DynamicStreamParser`
+//! scans the following bytes, one offset at a time, for a header whose hint, version
+//! and length decode plausibly and whose frame authenticates, and continues from there.
+//! The scan is bounded by `SilentConfig::resync_scan_budget`; `ResyncStats` counts it.
+//!
+//! # Byte Streams
+//! Frames need nothing from QUIC but an ordered byte stream: `SilentStream` runs them
+//! over any blocking `Read + Write` transport (TCP, Unix sockets, pipes).
+//!
+//! # In-Place Buffers
+//! `build_frame_into` seals into a caller's buffer, and `seal_frame_in_place` seals a
+//! payload already written after `FRAME_HEADROOM` reserved bytes. `open_frame_in_place`
+//! decrypts over the received bytes and returns the payload as a sub-slice of them,
+//! which is also how `DynamicStreamParser` works through its buffer.
+//!
+//! # Secret Hygiene
+//! Seeds, chain keys, salts, ratchet offers and DH outputs are held as `Secret` (`Zeroizing<[u8; 32]>`)
+//! and wiped when dropped, and the `Debug` output of generators and parsers omits them.
+//! Ratchet private keys are x25519-dalek `StaticSecret`s, which wipe themselves.
+//! Key schedules owned by ring (`LessSafeKey`) cannot be wiped from here; they live
+//! only as long as the frame that needs them.
+//!
+//! # Datagram Mode
+//! `build_datagram_frame` / `parse_datagram_frame` carry one self-contained frame per
+//! QUIC DATAGRAM, for messages that should not wait behind a lost stream packet:
+//! [Masked Sequence (4 bytes)] [Masked Version (1 byte)] [Encrypted Body]
+//! The low 32 bits of the sequence are enough to open datagrams in any order; they are
+//! masked QUIC-style with ChaCha20 keyed by the header protection key over a sample of
+//! the encrypted body. The replay window and, in chain-key mode, the skipped-key cache
+//! work as for stream frames. Datagrams use their own generators (`DATAGRAM_CONTEXT_ID`).
+//!
+//! # Config Negotiation
+//! Peers that disagree on the sequence hint, the double ratchet, header protection or
+//! the cipher suite cannot open each other's frames. `SilentConfig::encode_wire` carries
+//! those settings in the session handshake and `SilentConfig::negotiate` settles them
+//! (or fails with `IncompatibleConfig`) before the first frame is sent.
+//!
+//! # Header Protection
+//! With `SilentConfig::enable_header_protection` the header is instead encrypted
+//! OpenSSH-style (chacha20-poly1305@openssh.com): the masks are the ChaCha20 keystream
+//! of a header-only key with the sequence as nonce, a key that never touches a body.
 
-use ring::aead::{self, Aad, LessSafeKey, UnboundKey};
+use crate::coalescing;
+use crate::cover_traffic::CoverTraffic;
+use crate::resumption::StateReader;
+use ring::aead::{self, quic, Aad, LessSafeKey, UnboundKey};
 use ring::digest::{self, Context, SHA256};
+use ring::hkdf;
+use ring::hmac;
 use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
 
 /// Protocol Configuration
 #[derive(Debug, Clone, Copy)]
@@ -25,13 +136,64 @@ pub struct SilentConfig {
     pub enable_sequence_hint: bool,
     
     /// Enable Paranoid Mode: Periodic Rekeying (Scheme C)
-    /// Inserts fresh entropy (e.g. Ephemeral PubKey) every `ratchet_interval` frames.
+    /// Inserts an X25519 Ratchet Block every `ratchet_interval` frames.
+    /// Healing requires both directions of the stream to share a `RatchetHandle`
+    /// (see `SaltGenerator::attach_ratchet`).
     /// Default: false (but we will enable it for demonstration if user asks)
     pub enable_double_ratchet: bool,
     
     /// Interval for Rekeying (in number of frames).
     /// Default: 1000
     pub ratchet_interval: u64,
+    
+    /// Length-hiding padding added inside the encrypted body (sender side only;
+    /// the receiver strips any padding regardless of its own policy).
+    /// Default: PowerOfTwo
+    pub padding: PaddingPolicy,
+    
+    /// AEAD used for the encrypted body. Both peers must use the same suite;
+    /// it is identified on the wire by `CipherSuite::id`.
+    /// Default: ChaCha20Poly1305
+    pub cipher_suite: CipherSuite,
+    
+    /// Encrypt the header (Length, Version, Hint, Epoch) with a ChaCha20 keystream
+    /// under a dedicated header key instead of the default HKDF masks.
+    /// Both peers must agree on this setting.
+    /// Default: false
+    pub enable_header_protection: bool,
+    
+    /// How far behind the newest accepted frame a late (reordered) frame may be.
+    /// Frames already accepted inside the window are rejected as `Replay`; older ones are not located.
+    /// Capped at `MAX_REPLAY_WINDOW`.
+    /// Default: 256
+    pub replay_window: u64,
+    
+    /// How far ahead of the expected sequence the hint search may jump (robust mode).
+    /// The hints of the whole window are kept precomputed, so it is capped at `MAX_RESYNC_WINDOW`.
+    /// Default: 1000
+    pub resync_window: u64,
+    
+    /// Maximum number of hint searches (frames not at the expected sequence) per second
+    /// and generator; beyond that frames are rejected with `ResyncRateLimited`. 0 = unlimited.
+    /// Default: 100
+    pub max_resyncs_per_second: u32,
+    
+    /// Constant-rate sending with chaff in idle slots (sender side only; the event
+    /// loop drives a `cover_traffic::PacedSender` and builds cells with `CoverTraffic::frame_config`).
+    /// Default: None
+    pub cover_traffic: Option<CoverTraffic>,
+    
+    /// Cell mode: split every payload into frames whose encrypted body is exactly
+    /// this many bytes (overrides `padding`). At least `MIN_CELL_SIZE`.
+    /// The receiver reassembles cells whatever its own setting.
+    /// Default: None
+    pub cell_size: Option<usize>,
+    
+    /// After a frame fails to parse, stream parsers scan up to this many bytes ahead
+    /// for the next frame that authenticates instead of dropping everything buffered
+    /// (receiver side only). 0 = drop the buffer.
+    /// Default: 64 KiB
+    pub resync_scan_budget: usize,
 }
 
 impl Default for SilentConfig {
@@ -40,6 +202,282 @@ impl Default for SilentConfig {
             enable_sequence_hint: true,
             enable_double_ratchet: false, 
             ratchet_interval: 1000,
+            padding: PaddingPolicy::PowerOfTwo,
+            cipher_suite: CipherSuite::ChaCha20Poly1305,
+            enable_header_protection: false,
+            replay_window: DEFAULT_MAX_SKIPPED_KEYS as u64,
+            resync_window: 1000,
+            max_resyncs_per_second: 100,
+            cover_traffic: None,
+            cell_size: None,
+            resync_scan_budget: 64 * 1024,
+        }
+    }
+}
+
+/// Environment variable overriding the wire-relevant settings of `SilentConfig::default()`:
+/// comma-separated `no-hint`, `ratchet[:interval]`, `header-protection`, `aes`
+pub const CONFIG_ENV_VAR: &str = "SILENT_SPEAKER_FRAMING";
+
+/// Version of the `SilentConfig::encode_wire` format
+pub const CONFIG_ENCODING_VERSION: u8 = 1;
+
+/// [Encoding Version (1B)] [Frame Format Version (1B)] [Flags (1B)] [Cipher Suite (1B)] [Ratchet Interval (8B)]
+pub const CONFIG_WIRE_LEN: usize = 4 + 8;
+
+const CONFIG_FLAG_SEQUENCE_HINT: u8 = 0x01;
+const CONFIG_FLAG_DOUBLE_RATCHET: u8 = 0x02;
+const CONFIG_FLAG_HEADER_PROTECTION: u8 = 0x04;
+
+impl SilentConfig {
+    /// Parse the `CONFIG_ENV_VAR` syntax, starting from the defaults
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut config = Self::default();
+        for option in value.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            match option.split_once(':') {
+                None if option == "no-hint" => config.enable_sequence_hint = false,
+                None if option == "ratchet" => config.enable_double_ratchet = true,
+                None if option == "header-protection" => config.enable_header_protection = true,
+                None if option == "aes" => config.cipher_suite = CipherSuite::Aes256Gcm,
+                Some(("ratchet", interval)) => {
+                    config.enable_double_ratchet = true;
+                    config.ratchet_interval = interval.parse().ok().filter(|interval| *interval > 0)?;
+                }
+                _ => return None,
+            }
+        }
+        Some(config)
+    }
+
+    /// Settings from `CONFIG_ENV_VAR` (None if unset or invalid)
+    pub fn from_env() -> Option<Self> {
+        std::env::var(CONFIG_ENV_VAR).ok().and_then(|value| Self::parse(&value))
+    }
+
+    /// Compact encoding of the settings both peers must agree on, exchanged
+    /// during the session handshake. Sender- and receiver-only settings
+    /// (padding, windows, cover traffic, ...) stay local and are not sent.
+    pub fn encode_wire(&self) -> [u8; CONFIG_WIRE_LEN] {
+        let mut flags = 0;
+        if self.enable_sequence_hint {
+            flags |= CONFIG_FLAG_SEQUENCE_HINT;
+        }
+        if self.enable_double_ratchet {
+            flags |= CONFIG_FLAG_DOUBLE_RATCHET;
+        }
+        if self.enable_header_protection {
+            flags |= CONFIG_FLAG_HEADER_PROTECTION;
+        }
+        let mut wire = [0u8; CONFIG_WIRE_LEN];
+        wire[..4].copy_from_slice(&[CONFIG_ENCODING_VERSION, FRAME_FORMAT_VERSION, flags, self.cipher_suite.id()]);
+        wire[4..].copy_from_slice(&self.ratchet_interval.to_be_bytes());
+        wire
+    }
+
+    /// Agree on the shared settings with a peer that sent `peer_wire` (see `encode_wire`).
+    ///
+    /// The result is the same on both sides: each security feature is on if either
+    /// peer asked for it, and the shorter ratchet interval wins. Different frame
+    /// format versions or cipher suites cannot be reconciled and fail with
+    /// `IncompatibleConfig`. Local-only settings are kept from `self`.
+    pub fn negotiate(&self, peer_wire: &[u8]) -> Result<SilentConfig, DynamicFramingError> {
+        let incompatible = |reason: String| Err(DynamicFramingError::IncompatibleConfig(reason));
+        if peer_wire.len() != CONFIG_WIRE_LEN {
+            return incompatible(format!("config encoding of {} bytes", peer_wire.len()));
+        }
+        let [encoding, version, flags, suite] = [peer_wire[0], peer_wire[1], peer_wire[2], peer_wire[3]];
+        if encoding != CONFIG_ENCODING_VERSION {
+            return incompatible(format!("config encoding version {} (ours is {})", encoding, CONFIG_ENCODING_VERSION));
+        }
+        if version != FRAME_FORMAT_VERSION {
+            return incompatible(format!("frame format version {} (ours is {})", version, FRAME_FORMAT_VERSION));
+        }
+        if flags & !(CONFIG_FLAG_SEQUENCE_HINT | CONFIG_FLAG_DOUBLE_RATCHET | CONFIG_FLAG_HEADER_PROTECTION) != 0 {
+            return incompatible(format!("unknown feature flags {:#04x}", flags));
+        }
+        let peer_suite = match CipherSuite::from_id(suite) {
+            Some(peer_suite) if peer_suite == self.cipher_suite => peer_suite,
+            Some(peer_suite) => return incompatible(format!("cipher suite {:?} (ours is {:?})", peer_suite, self.cipher_suite)),
+            None => return incompatible(format!("unknown cipher suite {:#04x}", suite)),
+        };
+        let peer_interval = u64::from_be_bytes(peer_wire[4..].try_into().unwrap());
+        let peer_ratchet = flags & CONFIG_FLAG_DOUBLE_RATCHET != 0;
+        let enable_double_ratchet = self.enable_double_ratchet || peer_ratchet;
+        // Only the interval of a peer that enabled the ratchet counts
+        let ratchet_interval = match (self.enable_double_ratchet, peer_ratchet) {
+            (true, false) => self.ratchet_interval,
+            (false, true) => peer_interval,
+            _ => self.ratchet_interval.min(peer_interval),
+        };
+        if enable_double_ratchet && ratchet_interval == 0 {
+            return incompatible("ratchet interval 0".to_string());
+        }
+
+        Ok(SilentConfig {
+            enable_sequence_hint: self.enable_sequence_hint || flags & CONFIG_FLAG_SEQUENCE_HINT != 0,
+            enable_double_ratchet,
+            ratchet_interval,
+            cipher_suite: peer_suite,
+            enable_header_protection: self.enable_header_protection || flags & CONFIG_FLAG_HEADER_PROTECTION != 0,
+            ..*self
+        })
+    }
+}
+
+/// Size of the padding length trailer at the end of every plaintext body
+const PADDING_TRAILER_LEN: usize = 4;
+
+/// AEAD tag size (the same for every `CipherSuite`)
+const TAG_LEN: usize = 16;
+
+/// Smallest bucket of `PaddingPolicy::PowerOfTwo`
+const MIN_PADDING_BUCKET: usize = 64;
+
+/// Bytes of a QUIC packet not available to our frame: short header, packet
+/// number, AEAD tag and STREAM frame header (conservative estimate)
+const QUIC_PACKET_OVERHEAD: usize = 64;
+
+/// Fixed part of our frame header: Length + Version + Hint + Epoch
+const MAX_FIXED_HEADER_LEN: usize = 4 + 1 + 2 + 1;
+
+/// Wire format version, carried (obfuscated) in every header and bound into the AAD.
+/// 1: unauthenticated header (no version byte); 2: header authenticated as AAD;
+/// 3: HKDF-expanded frame keys with a selectable `CipherSuite`; 4: encrypted inner frame type;
+/// 5: continuation flag in the frame type (cell mode); 6: batch frames
+pub const FRAME_FORMAT_VERSION: u8 = 6;
+
+/// Size of the inner frame type at the start of every plaintext body
+const FRAME_TYPE_LEN: usize = 1;
+
+/// Bit of the inner type byte set on every cell but the last of a message
+const FRAME_MORE_FLAG: u8 = 0x80;
+
+/// Smallest `SilentConfig::cell_size`
+pub const MIN_CELL_SIZE: usize = MIN_PADDING_BUCKET;
+
+/// Inner type of a dynamic frame, encrypted together with its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameType {
+    /// Application data
+    Data,
+    /// Padding / chaff: cover traffic, discarded by the receiver
+    Chaff,
+    /// Control: the peer asks for a rekey
+    Rekey,
+    /// Control: liveness probe
+    Ping,
+    /// Application data: several messages, each [Length (4B)] [Message] (see `coalescing`)
+    Batch,
+}
+
+impl FrameType {
+    /// Wire identifier
+    pub fn id(&self) -> u8 {
+        match self {
+            FrameType::Data => 0x00,
+            FrameType::Chaff => 0x01,
+            FrameType::Rekey => 0x02,
+            FrameType::Ping => 0x03,
+            FrameType::Batch => 0x04,
+        }
+    }
+    
+    /// Frame type for a wire identifier (None if unknown)
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x00 => Some(FrameType::Data),
+            0x01 => Some(FrameType::Chaff),
+            0x02 => Some(FrameType::Rekey),
+            0x03 => Some(FrameType::Ping),
+            0x04 => Some(FrameType::Batch),
+            _ => None,
+        }
+    }
+    
+    /// Control frames are handed to `DynamicStreamParser::set_control_handler`
+    pub fn is_control(&self) -> bool {
+        matches!(self, FrameType::Rekey | FrameType::Ping)
+    }
+}
+
+/// AEAD protecting the encrypted body of every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    /// ChaCha20-Poly1305 (fast without AES hardware)
+    ChaCha20Poly1305,
+    /// AES-256-GCM (fast with AES-NI / ARMv8 crypto extensions)
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    /// Wire identifier, also mixed into the key derivation
+    pub fn id(&self) -> u8 {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 0x01,
+            CipherSuite::Aes256Gcm => 0x02,
+        }
+    }
+    
+    /// Suite for a wire identifier (None if unknown)
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x01 => Some(CipherSuite::ChaCha20Poly1305),
+            0x02 => Some(CipherSuite::Aes256Gcm),
+            _ => None,
+        }
+    }
+    
+    fn algorithm(&self) -> &'static aead::Algorithm {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => &aead::CHACHA20_POLY1305,
+            CipherSuite::Aes256Gcm => &aead::AES_256_GCM,
+        }
+    }
+}
+
+/// How much padding `build_dynamic_frame` adds to hide the payload length.
+///
+/// Sizes refer to the encrypted body (what the obfuscated length field describes):
+/// Data + Padding + PaddingLen (4B) + Tag (16B).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// No padding: the body length reveals the payload length
+    None,
+    /// Uniformly random padding of 0..=max bytes
+    Random { max: usize },
+    /// Round the body up to the next power of two (at least 64 bytes)
+    PowerOfTwo,
+    /// Round the body up to a multiple of a fixed size (see `PaddingPolicy::for_mtu`)
+    FixedSize(usize),
+}
+
+impl PaddingPolicy {
+    /// Fixed-size padding so that one (non-rekey) frame fills one QUIC packet of `mtu` bytes
+    pub fn for_mtu(mtu: usize) -> Self {
+        let size = mtu.saturating_sub(QUIC_PACKET_OVERHEAD + MAX_FIXED_HEADER_LEN);
+        PaddingPolicy::FixedSize(size.max(MIN_PADDING_BUCKET))
+    }
+    
+    /// Padding to add to a body of `body_len` bytes (trailer and tag included)
+    fn padding_len(&self, body_len: usize) -> Result<usize, DynamicFramingError> {
+        match *self {
+            PaddingPolicy::None => Ok(0),
+            PaddingPolicy::Random { max } => {
+                if max == 0 {
+                    return Ok(0);
+                }
+                let mut bytes = [0u8; 8];
+                SystemRandom::new().fill(&mut bytes)
+                    .map_err(|_| DynamicFramingError::EncryptionError)?;
+                Ok((u64::from_be_bytes(bytes) % (max as u64 + 1)) as usize)
+            }
+            PaddingPolicy::PowerOfTwo => {
+                Ok(body_len.next_power_of_two().max(MIN_PADDING_BUCKET) - body_len)
+            }
+            PaddingPolicy::FixedSize(size) => {
+                let size = size.max(1);
+                Ok(body_len.div_ceil(size) * size - body_len)
+            }
         }
     }
 }
@@ -55,467 +493,2360 @@ pub enum DynamicFramingError {
     #[error("Invalid data length: {0}")]
     InvalidLength(usize),
     
-    #[error("Incomplete data")]
-    IncompleteData,
+    #[error("Incomplete data")]
+    IncompleteData,
+    
+    /// The frame was written in a format version this parser does not speak
+    /// (or, without the sequence hint, the generator is out of sync).
+    #[error("Unsupported frame format version {0}")]
+    UnsupportedVersion(u8),
+    
+    /// The frame was already accepted, or is older than the replay window.
+    /// `frame_len` bytes can be skipped to reach the next frame.
+    #[error("Replayed or too old frame (sequence {seq})")]
+    Replay { seq: u64, frame_len: usize },
+    
+    /// An authenticated frame carried an inner type this version does not know.
+    #[error("Unknown frame type {0}")]
+    UnknownFrameType(u8),
+    
+    /// Too many frames needed a hint search within the last second.
+    /// `DynamicStreamParser` keeps the frame buffered, to be parsed again later.
+    #[error("Too many sequence resyncs")]
+    ResyncRateLimited,
+    
+    /// The frame belongs to a rekey epoch we do not have (yet).
+    /// `frame_len` bytes can be skipped to reach the next frame.
+    #[error("Frame from unknown epoch {epoch}")]
+    UnknownEpoch { epoch: u8, frame_len: usize },
+    
+    /// A stream parser scanned `skipped` bytes without finding a valid frame
+    /// (see `SilentConfig::resync_scan_budget`) and dropped them.
+    #[error("No valid frame within {skipped} bytes")]
+    ResyncFailed { skipped: usize },
+    
+    /// The peer's `SilentConfig` cannot be reconciled with ours (see `SilentConfig::negotiate`).
+    #[error("Incompatible peer config: {0}")]
+    IncompatibleConfig(String),
+}
+
+/// Direction of travel of the frames produced/consumed by a generator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Client -> Server ("c2s")
+    ClientToServer,
+    /// Server -> Client ("s2c")
+    ServerToClient,
+}
+
+impl Direction {
+    /// HKDF label used to separate the two directions
+    pub fn label(&self) -> &'static [u8] {
+        match self {
+            Direction::ClientToServer => b"c2s",
+            Direction::ServerToClient => b"s2c",
+        }
+    }
+
+    /// The opposite direction on the same stream
+    pub fn reverse(&self) -> Direction {
+        match self {
+            Direction::ClientToServer => Direction::ServerToClient,
+            Direction::ServerToClient => Direction::ClientToServer,
+        }
+    }
+}
+
+/// Which end of the connection a peer is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl Role {
+    /// Direction of the frames this peer sends
+    pub fn sending(&self) -> Direction {
+        match self {
+            Role::Client => Direction::ClientToServer,
+            Role::Server => Direction::ServerToClient,
+        }
+    }
+
+    /// Direction of the frames this peer receives
+    pub fn receiving(&self) -> Direction {
+        self.sending().reverse()
+    }
+}
+
+/// HKDF salt for the per-stream key schedule
+const STREAM_KEY_SALT: &[u8] = b"fengni v1 stream key schedule";
+
+/// Ratchet Block kind bits
+const RATCHET_OFFER: u8 = 0x01;
+const RATCHET_ANSWER: u8 = 0x02;
+
+/// Ratchet Block sizes: Kind + Offer, and Kind + Offer + Answer + EpochStart
+const RATCHET_OFFER_LEN: usize = 1 + 32;
+const RATCHET_OFFER_ANSWER_LEN: usize = 1 + 32 + 32 + 8;
+
+/// X25519 ratchet state of one stream endpoint.
+///
+/// The sending generator emits offers/answers and the receiving generator
+/// consumes the peer's, so both must share the same state through a `RatchetHandle`.
+pub struct DhRatchet {
+    /// Our outstanding offer: private and public key (dropped when the peer answers it).
+    /// Unlike ring's single-use keys, a `StaticSecret` lets an answer be tried
+    /// (`preview_block`) without using the offer up before the frame is authenticated.
+    own_offer: Option<(StaticSecret, [u8; 32])>,
+    /// The peer's outstanding offer we have not answered yet
+    peer_offer: Option<[u8; 32]>,
+    /// Last peer offer we answered (the peer may repeat it until our answer arrives)
+    answered: Option<[u8; 32]>,
+    /// Our last answer and the epoch it opened, repeated until the peer makes a new offer
+    last_answer: Option<([u8; 32], u64)>,
+    /// Last answer we received (repeats of it are ignored)
+    received_answer: Option<[u8; 32]>,
+}
+
+/// Shared handle to a `DhRatchet`
+pub type RatchetHandle = Arc<Mutex<DhRatchet>>;
+
+impl std::fmt::Debug for DhRatchet {
+    /// Public keys only say which exchange is pending; the private key is never printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DhRatchet")
+            .field("offer_pending", &self.own_offer.is_some())
+            .field("peer_offer_pending", &self.peer_offer.is_some())
+            .field("last_answer_epoch", &self.last_answer.map(|(_, epoch)| epoch))
+            .finish_non_exhaustive()
+    }
+}
+
+impl DhRatchet {
+    pub fn new() -> Self {
+        Self {
+            own_offer: None,
+            peer_offer: None,
+            answered: None,
+            last_answer: None,
+            received_answer: None,
+        }
+    }
+    
+    /// Create a ratchet ready to be attached to both generators of a stream
+    pub fn new_shared() -> RatchetHandle {
+        Arc::new(Mutex::new(Self::new()))
+    }
+    
+    /// Build the Ratchet Block for an outgoing rekey frame.
+    /// `next_seq` is the first sequence of the epoch a new answer would open.
+    /// Returns the block (EpochStart unmasked) and, if we answered a new peer offer,
+    /// the DH output to mix into the sending seed after this frame.
+    fn prepare_block(&mut self, next_seq: u64) -> Result<(Vec<u8>, Option<Secret>), DynamicFramingError> {
+        let rng = SystemRandom::new();
+        
+        // Keep one offer outstanding until the peer answers it
+        if self.own_offer.is_none() {
+            let private_key = random_private_key(&rng)?;
+            let public_key = PublicKey::from(&private_key).to_bytes();
+            self.own_offer = Some((private_key, public_key));
+        }
+        let offer = self.own_offer.as_ref().unwrap().1;
+        
+        let mut secret = None;
+        if let Some(peer_offer) = self.peer_offer.take() {
+            // Answer the peer's offer with a fresh key
+            let private_key = random_private_key(&rng)?;
+            let answer = PublicKey::from(&private_key).to_bytes();
+            secret = Some(dh(&private_key, &peer_offer).ok_or(DynamicFramingError::EncryptionError)?);
+            self.answered = Some(peer_offer);
+            self.last_answer = Some((answer, next_seq));
+        }
+        
+        // A peer that lost the frame carrying our answer can still open the epoch
+        // from any later rekey frame, so keep repeating it.
+        let Some((answer, epoch_start)) = self.last_answer else {
+            let mut block = Vec::with_capacity(RATCHET_OFFER_LEN);
+            block.push(RATCHET_OFFER);
+            block.extend_from_slice(&offer);
+            return Ok((block, None));
+        };
+        
+        let mut block = Vec::with_capacity(RATCHET_OFFER_ANSWER_LEN);
+        block.push(RATCHET_OFFER | RATCHET_ANSWER);
+        block.extend_from_slice(&offer);
+        block.extend_from_slice(&answer);
+        block.extend_from_slice(&epoch_start.to_be_bytes());
+        Ok((block, secret))
+    }
+    
+    /// DH output of a Ratchet Block (EpochStart unmasked) that answers our outstanding
+    /// offer, without changing any state: used to open a frame that is not authenticated yet.
+    fn preview_block(&self, block: &[u8]) -> Result<Option<Secret>, DynamicFramingError> {
+        if block[0] & RATCHET_ANSWER == 0 {
+            return Ok(None);
+        }
+        let answer: [u8; 32] = block[33..65].try_into().unwrap();
+        if self.received_answer == Some(answer) {
+            return Ok(None);
+        }
+        let (private_key, _) = self.own_offer.as_ref().ok_or(DynamicFramingError::DecryptionError)?;
+        dh(private_key, &answer).map(Some).ok_or(DynamicFramingError::DecryptionError)
+    }
+    
+    /// Consume a Ratchet Block (EpochStart unmasked) from an authenticated rekey frame.
+    /// Returns the DH output if the block answers our outstanding offer.
+    fn accept_block(&mut self, block: &[u8]) -> Result<Option<Secret>, DynamicFramingError> {
+        // A repeated offer we already answered is ignored.
+        // A new one means the peer got our last answer: stop repeating it.
+        let offer: [u8; 32] = block[1..33].try_into().unwrap();
+        if self.answered != Some(offer) {
+            self.peer_offer = Some(offer);
+            self.last_answer = None;
+        }
+        
+        let secret = self.preview_block(block)?;
+        if secret.is_some() {
+            self.own_offer = None;
+            self.received_answer = Some(block[33..65].try_into().unwrap());
+        }
+        Ok(secret)
+    }
+}
+
+impl Default for DhRatchet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fresh X25519 private key from the system RNG
+fn random_private_key(rng: &SystemRandom) -> Result<StaticSecret, DynamicFramingError> {
+    let mut scalar = Secret::default();
+    rng.fill(&mut scalar[..]).map_err(|_| DynamicFramingError::EncryptionError)?;
+    Ok(StaticSecret::from(*scalar))
+}
+
+/// X25519 output, or None for a low-order peer key (whose output anyone can predict)
+fn dh(private_key: &StaticSecret, peer_public_key: &[u8; 32]) -> Option<Secret> {
+    let shared = private_key.diffie_hellman(&PublicKey::from(*peer_public_key));
+    if !shared.was_contributory() {
+        return None;
+    }
+    let mut secret = Secret::default();
+    secret.copy_from_slice(shared.as_bytes());
+    Some(secret)
+}
+
+/// Size of the Ratchet Block announced by its kind byte
+fn ratchet_block_len(kind: u8) -> Result<usize, DynamicFramingError> {
+    match kind {
+        RATCHET_OFFER => Ok(RATCHET_OFFER_LEN),
+        k if k == RATCHET_OFFER | RATCHET_ANSWER => Ok(RATCHET_OFFER_ANSWER_LEN),
+        _ => Err(DynamicFramingError::DecryptionError),
+    }
+}
+
+/// Whether the frame with this sequence carries a Ratchet Block
+fn is_rekey_slot(config: SilentConfig, sequence: u64) -> bool {
+    config.enable_double_ratchet && sequence > 0 && (sequence % config.ratchet_interval == 0)
+}
+
+/// Label used to turn a seed into the first chain key
+const CHAIN_KEY_LABEL: &[u8] = b"fengni v1 chain key";
+
+/// Label used to derive the header key from the initial seed
+const HEADER_KEY_LABEL: &[u8] = b"fengni v1 header key";
+
+/// HKDF labels expanding a salt into the AEAD key and nonce of one frame
+const FRAME_KEY_LABEL: &[u8] = b"fengni v1 key";
+const FRAME_NONCE_LABEL: &[u8] = b"fengni v1 nonce";
+
+/// HKDF label expanding the header key into the header masks of one sequence
+const HEADER_MASK_LABEL: &[u8] = b"fengni v1 header mask";
+
+/// HKDF label deriving the ChaCha20 header-protection key from the header key
+const HEADER_PROTECTION_LABEL: &[u8] = b"fengni v1 header protection";
+
+/// Output length for HKDF expansions that are not AEAD keys
+struct OkmLen(usize);
+
+impl hkdf::KeyType for OkmLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Expand a frame salt into the AEAD key and nonce of `suite`
+fn frame_key(suite: CipherSuite, salt: &[u8; 32]) -> Result<(LessSafeKey, aead::Nonce), DynamicFramingError> {
+    let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, salt);
+    let suite_id = [suite.id()];
+    
+    let key = prk.expand(&[FRAME_KEY_LABEL, &suite_id], suite.algorithm())
+        .map(UnboundKey::from)
+        .map_err(|_| DynamicFramingError::EncryptionError)?;
+    
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    prk.expand(&[FRAME_NONCE_LABEL, &suite_id], OkmLen(aead::NONCE_LEN))
+        .and_then(|okm| okm.fill(&mut nonce))
+        .map_err(|_| DynamicFramingError::EncryptionError)?;
+    
+    Ok((LessSafeKey::new(key), aead::Nonce::assume_unique_for_key(nonce)))
+}
+
+/// Default bound on cached keys of skipped frames (also bounds the resync lookahead)
+pub const DEFAULT_MAX_SKIPPED_KEYS: usize = 256;
+
+/// Default number of previous epochs kept for late frames
+pub const DEFAULT_RETAINED_EPOCHS: usize = 2;
+
+/// 32 bytes of key material (seed, salt, chain key, DH output), wiped when dropped
+pub type Secret = Zeroizing<[u8; 32]>;
+
+/// Symmetric chain state of a generator in chain-key mode
+struct ChainState {
+    /// Chain key for sequence `index`; previous chain keys are overwritten
+    chain_key: Secret,
+    index: u64,
+    /// Salts of frames we stepped over but have not received yet, oldest first
+    skipped: VecDeque<(u64, Secret)>,
+    max_skipped: usize,
+}
+
+impl ChainState {
+    /// Returns (Salt, NextChainKey)
+    fn step(chain_key: &[u8; 32]) -> (Secret, Secret) {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, chain_key);
+        let mut salt = Secret::default();
+        salt.copy_from_slice(hmac::sign(&key, &[0x01]).as_ref());
+        let mut next = Secret::default();
+        next.copy_from_slice(hmac::sign(&key, &[0x02]).as_ref());
+        (salt, next)
+    }
+    
+    fn cached(&self, seq: u64) -> Option<Secret> {
+        self.skipped.iter().find(|(s, _)| *s == seq).map(|(_, salt)| salt.clone())
+    }
+    
+    /// Salt of `seq` without advancing the chain, stepping at most `max_ahead` frames past it
+    fn peek(&self, seq: u64, max_ahead: u64) -> Option<Secret> {
+        if seq < self.index {
+            return self.cached(seq);
+        }
+        if seq - self.index > max_ahead {
+            return None;
+        }
+        
+        let mut chain_key = self.chain_key.clone();
+        for _ in self.index..seq {
+            chain_key = Self::step(&chain_key).1;
+        }
+        Some(Self::step(&chain_key).0)
+    }
+    
+    /// Advance the chain past `seq`, caching the salts stepped over, and forget `seq`.
+    fn consume(&mut self, seq: u64) -> Option<Secret> {
+        if seq < self.index {
+            let pos = self.skipped.iter().position(|(s, _)| *s == seq)?;
+            return self.skipped.remove(pos).map(|(_, salt)| salt);
+        }
+        
+        let mut result = None;
+        while self.index <= seq {
+            let (salt, next) = Self::step(&self.chain_key);
+            if self.index == seq {
+                result = Some(salt);
+            } else {
+                self.skipped.push_back((self.index, salt));
+            }
+            self.chain_key = next;
+            self.index += 1;
+        }
+        
+        while self.skipped.len() > self.max_skipped {
+            self.skipped.pop_front();
+        }
+        result
+    }
+}
+
+/// Largest supported `SilentConfig::replay_window` (size of the replay bitmap in bits)
+pub const MAX_REPLAY_WINDOW: u64 = 1024;
+
+/// Sliding bitmap of accepted sequences (RFC 6479 style: bit `seq % MAX_REPLAY_WINDOW`)
+#[derive(Debug, Clone)]
+struct ReplayWindow {
+    /// Highest accepted sequence + 1 (0 = nothing accepted yet)
+    top: u64,
+    bits: [u64; (MAX_REPLAY_WINDOW / 64) as usize],
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self { top: 0, bits: [0; (MAX_REPLAY_WINDOW / 64) as usize] }
+    }
+    
+    fn bit(seq: u64) -> (usize, u64) {
+        let index = seq % MAX_REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+    
+    /// Whether `seq` may still be accepted with a backward window of `window` frames
+    fn check(&self, seq: u64, window: u64) -> bool {
+        if seq >= self.top {
+            return true;
+        }
+        if self.top - seq > window.min(MAX_REPLAY_WINDOW) {
+            return false;
+        }
+        let (word, mask) = Self::bit(seq);
+        self.bits[word] & mask == 0
+    }
+    
+    /// Record `seq` as accepted, sliding the window forward if needed
+    fn mark(&mut self, seq: u64) {
+        if seq >= self.top {
+            if seq - self.top >= MAX_REPLAY_WINDOW {
+                self.bits = [0; (MAX_REPLAY_WINDOW / 64) as usize];
+            } else {
+                for skipped in self.top..seq {
+                    let (word, mask) = Self::bit(skipped);
+                    self.bits[word] &= !mask;
+                }
+            }
+            self.top = seq + 1;
+        }
+        let (word, mask) = Self::bit(seq);
+        self.bits[word] |= mask;
+    }
+    
+    /// [Top (8B)] [Bitmap (128B)]
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.top.to_be_bytes());
+        for word in &self.bits {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+    
+    fn decode(reader: &mut StateReader) -> Option<Self> {
+        let mut window = Self::new();
+        window.top = reader.u64()?;
+        for word in &mut window.bits {
+            *word = reader.u64()?;
+        }
+        Some(window)
+    }
+}
+
+/// Largest supported `SilentConfig::resync_window`
+pub const MAX_RESYNC_WINDOW: u64 = 16384;
+
+/// Expected wire hints (`Sequence ^ HintMask`) for a range of sequences around the
+/// receive position, maintained incrementally so a hint search costs no key derivation
+#[derive(Debug, Clone)]
+struct HintCache {
+    /// Sequence of `hints[0]`
+    start: u64,
+    /// Header protection setting the hints were derived with
+    protected: bool,
+    hints: VecDeque<u16>,
+}
+
+impl HintCache {
+    fn new() -> Self {
+        Self { start: 0, protected: false, hints: VecDeque::new() }
+    }
+    
+    fn get(&self, seq: u64) -> Option<u16> {
+        let index = seq.checked_sub(self.start)?;
+        self.hints.get(usize::try_from(index).ok()?).copied()
+    }
+}
+
+/// Counts hint searches per one-second window
+#[derive(Debug, Clone, Copy)]
+struct ResyncLimiter {
+    window_start: Option<Instant>,
+    count: u32,
+}
+
+impl ResyncLimiter {
+    /// Record one resync; false if the limit for the current second is used up
+    fn allow(&mut self, limit: u32) -> bool {
+        if limit == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        match self.window_start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.window_start = Some(now);
+                self.count = 0;
+            }
+        }
+        if self.count >= limit {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+/// Keys of one rekey epoch (the span between two ratchet DH outputs)
+struct EpochState {
+    id: u32,
+    /// First sequence encrypted under this epoch
+    start: u64,
+    /// First sequence of the next epoch, once it has been opened
+    end: Option<u64>,
+    seed: Secret,
+    chain: Option<ChainState>,
+}
+
+impl EpochState {
+    /// Salt = SHA256(Seed + Sequence_BE_Bytes), or the chain step in chain-key mode
+    /// (at most `max_skipped` frames ahead of the chain)
+    fn salt_for(&self, seq: u64) -> Option<Secret> {
+        let max_ahead = self.chain.as_ref().map_or(0, |chain| chain.max_skipped as u64);
+        self.salt_within(seq, max_ahead)
+    }
+    
+    /// `salt_for`, letting the chain step up to `max_ahead` frames instead
+    fn salt_within(&self, seq: u64, max_ahead: u64) -> Option<Secret> {
+        if self.end.is_some_and(|end| seq >= end) {
+            return None;
+        }
+        if let Some(chain) = &self.chain {
+            return chain.peek(seq, max_ahead);
+        }
+        
+        let mut context = Context::new(&SHA256);
+        context.update(&self.seed[..]);
+        context.update(&seq.to_be_bytes());
+        
+        let digest = context.finish();
+        let mut salt = Secret::default();
+        salt.copy_from_slice(digest.as_ref());
+        Some(salt)
+    }
+    
+    fn consume(&mut self, seq: u64) -> Option<Secret> {
+        match &mut self.chain {
+            Some(chain) if self.end.is_none_or(|end| seq < end) => chain.consume(seq),
+            Some(_) => None,
+            None => self.salt_for(seq),
+        }
+    }
+    
+    /// Derive the epoch that starts at `start` with `entropy` mixed in
+    /// NewSeed = SHA256(OldSeed + Entropy), where in chain-key mode OldSeed is the chain key at `start`
+    fn successor(&self, entropy: &[u8], start: u64) -> Option<EpochState> {
+        let mix = |seed: &[u8; 32]| {
+            let mut context = Context::new(&SHA256);
+            context.update(seed);
+            context.update(entropy);
+            let mut mixed = Secret::default();
+            mixed.copy_from_slice(context.finish().as_ref());
+            mixed
+        };
+        
+        let chain = match &self.chain {
+            Some(chain) => {
+                if start < chain.index || start - chain.index > chain.max_skipped as u64 {
+                    return None;
+                }
+                let mut chain_key = chain.chain_key.clone();
+                for _ in chain.index..start {
+                    chain_key = ChainState::step(&chain_key).1;
+                }
+                Some(ChainState {
+                    chain_key: mix(&chain_key),
+                    index: start,
+                    skipped: VecDeque::new(),
+                    max_skipped: chain.max_skipped,
+                })
+            }
+            None => None,
+        };
+        
+        Some(EpochState {
+            id: self.id.wrapping_add(1),
+            start,
+            end: None,
+            seed: if chain.is_some() { Secret::default() } else { mix(&self.seed) },
+            chain,
+        })
+    }
+    
+    /// Stop at `end`: keep keys of frames before it for late arrivals, erase the rest
+    fn close(&mut self, end: u64) {
+        self.end = Some(end);
+        if let Some(chain) = &mut self.chain {
+            while chain.index < end {
+                let (salt, next) = ChainState::step(&chain.chain_key);
+                chain.skipped.push_back((chain.index, salt));
+                chain.chain_key = next;
+                chain.index += 1;
+            }
+            while chain.skipped.len() > chain.max_skipped {
+                chain.skipped.pop_front();
+            }
+            chain.chain_key = Secret::default();
+        }
+    }
+    
+    /// [ID (4B)] [Start (8B)] [HasEnd (1B)] [End (8B)] [Seed (32B)] [HasChain (1B)]
+    /// and with a chain: [ChainKey (32B)] [Index (8B)] [MaxSkipped (4B)] [Count (4B)] [Seq (8B) + Salt (32B)]*
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&self.start.to_be_bytes());
+        out.push(self.end.is_some() as u8);
+        out.extend_from_slice(&self.end.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&self.seed[..]);
+        out.push(self.chain.is_some() as u8);
+        if let Some(chain) = &self.chain {
+            out.extend_from_slice(&chain.chain_key[..]);
+            out.extend_from_slice(&chain.index.to_be_bytes());
+            out.extend_from_slice(&(chain.max_skipped as u32).to_be_bytes());
+            out.extend_from_slice(&(chain.skipped.len() as u32).to_be_bytes());
+            for (seq, salt) in &chain.skipped {
+                out.extend_from_slice(&seq.to_be_bytes());
+                out.extend_from_slice(&salt[..]);
+            }
+        }
+    }
+    
+    fn decode(reader: &mut StateReader) -> Option<Self> {
+        let id = reader.u32()?;
+        let start = reader.u64()?;
+        let has_end = reader.u8()? != 0;
+        let end = Some(reader.u64()?).filter(|_| has_end);
+        let seed = Secret::new(reader.bytes()?);
+        let chain = match reader.u8()? {
+            0 => None,
+            _ => {
+                let chain_key = Secret::new(reader.bytes()?);
+                let index = reader.u64()?;
+                let max_skipped = reader.u32()? as usize;
+                let mut skipped = VecDeque::new();
+                for _ in 0..reader.u32()? {
+                    skipped.push_back((reader.u64()?, Secret::new(reader.bytes()?)));
+                }
+                Some(ChainState { chain_key, index, skipped, max_skipped })
+            }
+        };
+        Some(Self { id, start, end, seed, chain })
+    }
+}
+
+/// Per-frame header masks.
+/// They come from the header key, which never changes, so the header of a frame
+/// can be read whatever epoch its body was encrypted under.
+#[derive(Debug, Clone, Copy)]
+struct HeaderMasks {
+    length: u32,
+    hint: u16,
+    epoch: u8,
+    epoch_start: u64,
+    version: u8,
+}
+
+/// Manages salt rotation and synchronization
+pub struct SaltGenerator {
+    header_key: Secret,
+    /// ChaCha20 key used only for header encryption (`enable_header_protection`)
+    header_protection_key: Secret,
+    sequence: u64,
+    /// Stream (or other context) this generator belongs to, authenticated in every frame
+    context_id: u64,
+    direction: Option<Direction>,
+    ratchet: Option<RatchetHandle>,
+    /// Current epoch (used for sending) and recent ones (kept for late frames), oldest first
+    epoch: EpochState,
+    retained: VecDeque<EpochState>,
+    max_retained_epochs: usize,
+    /// Sequences accepted by `parse_dynamic_frame`
+    replay: ReplayWindow,
+    /// Precomputed hints around `sequence` (receiving side, robust mode)
+    hint_cache: HintCache,
+    resyncs: ResyncLimiter,
+}
+
+impl std::fmt::Debug for SaltGenerator {
+    /// Redacts the header keys and the epoch seeds / chain keys
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SaltGenerator")
+            .field("sequence", &self.sequence)
+            .field("context_id", &self.context_id)
+            .field("direction", &self.direction)
+            .field("epoch", &self.epoch.id)
+            .field("chain_keys", &self.uses_chain_keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SaltGenerator {
+    /// Create a new generator with a specific seed.
+    /// Seeds are borrowed by every constructor, so the caller's `Secret` stays the only copy.
+    pub fn new(seed: &[u8; 32]) -> Self {
+        let mut secret = Secret::default();
+        secret.copy_from_slice(seed);
+        Self::with_seed(secret)
+    }
+    
+    fn with_seed(seed: Secret) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &seed[..]);
+        let mut header_key = Secret::default();
+        header_key.copy_from_slice(hmac::sign(&key, HEADER_KEY_LABEL).as_ref());
+        
+        let mut header_protection_key = Secret::default();
+        hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &header_key[..])
+            .expand(&[HEADER_PROTECTION_LABEL], OkmLen(header_protection_key.len()))
+            .and_then(|okm| okm.fill(&mut header_protection_key[..]))
+            .expect("HKDF output length is fixed at 32 bytes");
+        
+        Self {
+            header_key,
+            header_protection_key,
+            sequence: 0,
+            context_id: 0,
+            direction: None,
+            ratchet: None,
+            epoch: EpochState { id: 0, start: 0, end: None, seed, chain: None },
+            retained: VecDeque::new(),
+            max_retained_epochs: DEFAULT_RETAINED_EPOCHS,
+            replay: ReplayWindow::new(),
+            hint_cache: HintCache::new(),
+            resyncs: ResyncLimiter { window_start: None, count: 0 },
+        }
+    }
+    
+    /// Create a new generator with a random seed
+    pub fn new_random() -> Self {
+        let rng = SystemRandom::new();
+        let mut seed = Secret::default();
+        rng.fill(&mut seed[..]).expect("Failed to generate random seed");
+        Self::with_seed(seed)
+    }
+    
+    /// Reset sequence to 0
+    /// (in chain-key mode erased keys stay erased; `next_salt` skips ahead to the chain)
+    pub fn reset(&mut self) {
+        self.sequence = 0;
+    }
+    
+    /// Get the current sequence number
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+    
+    /// Set the sequence number (useful for sync)
+    pub fn set_sequence(&mut self, seq: u64) {
+        self.sequence = seq;
+    }
+    
+    /// Identifier of the current rekey epoch (0 until the first ratchet DH output is mixed in)
+    pub fn epoch(&self) -> u32 {
+        self.epoch.id
+    }
+    
+    /// How many previous epochs are kept so that late frames can still be opened
+    pub fn set_retained_epochs(&mut self, count: usize) {
+        self.max_retained_epochs = count;
+        while self.retained.len() > count {
+            self.retained.pop_front();
+        }
+    }
+    
+    /// Switch to chain-key mode (forward secrecy) starting at the current sequence.
+    /// Both peers must enable it at the same point, before the first frame.
+    ///
+    /// `max_skipped` bounds both the number of cached keys of skipped frames and
+    /// how far ahead robust-mode resync may jump.
+    pub fn enable_chain_keys(&mut self, max_skipped: usize) {
+        if self.epoch.chain.is_some() {
+            return;
+        }
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.epoch.seed[..]);
+        let mut chain_key = Secret::default();
+        chain_key.copy_from_slice(hmac::sign(&key, CHAIN_KEY_LABEL).as_ref());
+        
+        // The seed would unlock every future chain key, so it must not outlive the switch
+        self.epoch.seed = Secret::default();
+        self.epoch.chain = Some(ChainState {
+            chain_key,
+            index: self.sequence,
+            skipped: VecDeque::new(),
+            max_skipped,
+        });
+    }
+    
+    /// Whether this generator runs in chain-key mode
+    pub fn uses_chain_keys(&self) -> bool {
+        self.epoch.chain.is_some()
+    }
+    
+    /// Generate the next salt and advance sequence
+    /// Salt = SHA256(Seed + Sequence_BE_Bytes), or the next chain step in chain-key mode
+    pub fn next_salt(&mut self) -> Secret {
+        if let Some(chain) = &self.epoch.chain {
+            // Erased keys cannot be reused: never go back behind the chain
+            if self.sequence < chain.index && chain.cached(self.sequence).is_none() {
+                self.sequence = chain.index;
+            }
+        }
+        let seq = self.sequence;
+        let salt = self.epoch.consume(seq).expect("sequence is cached or ahead of the chain");
+        self.sequence = self.sequence.max(seq + 1);
+        salt
+    }
+    
+    /// Generate salt for a specific sequence (in the current epoch) without advancing state
+    ///
+    /// In chain-key mode this returns None for frames whose key was already used
+    /// or evicted, and for frames more than `max_skipped` ahead of the chain.
+    pub fn get_salt_for_sequence(&self, seq: u64) -> Option<Secret> {
+        self.epoch.salt_for(seq)
+    }
+    
+    /// Header masks for a sequence: HKDF-Expand(HeaderKey, HEADER_MASK_LABEL || Sequence_BE_Bytes),
+    /// or with header protection ChaCha20(HeaderProtectionKey, Nonce = 0^4 || Sequence_BE_Bytes)
+    /// [Length (4B)] [Hint (2B)] [Epoch (1B)] [EpochStart (8B)] [Version (1B)]
+    fn header_masks(&self, seq: u64, config: SilentConfig) -> HeaderMasks {
+        let seq_bytes = seq.to_be_bytes();
+        let mut bytes = [0u8; 16];
+        if config.enable_header_protection {
+            // Encrypting zeros yields the raw keystream; the tag is not needed
+            let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &self.header_protection_key[..])
+                .expect("header protection key length is fixed at 32 bytes");
+            let mut nonce = [0u8; aead::NONCE_LEN];
+            nonce[4..].copy_from_slice(&seq_bytes);
+            let _tag = LessSafeKey::new(key)
+                .seal_in_place_separate_tag(aead::Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut bytes)
+                .expect("header protection keystream");
+        } else {
+            let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &self.header_key[..]);
+            prk.expand(&[HEADER_MASK_LABEL, &seq_bytes], OkmLen(bytes.len()))
+                .and_then(|okm| okm.fill(&mut bytes))
+                .expect("HKDF output length is fixed at 16 bytes");
+        }
+        HeaderMasks {
+            length: u32::from_be_bytes(bytes[0..4].try_into().unwrap()),
+            hint: u16::from_be_bytes(bytes[4..6].try_into().unwrap()),
+            epoch: bytes[6],
+            epoch_start: u64::from_be_bytes(bytes[7..15].try_into().unwrap()),
+            version: bytes[15],
+        }
+    }
+    
+    /// Header mask of a datagram frame: QUIC-style ChaCha20 header protection under the
+    /// header protection key, sampled from the encrypted body (the sequence is what it hides)
+    fn datagram_mask(&self, sample: &[u8]) -> [u8; DATAGRAM_HEADER_LEN] {
+        quic::HeaderProtectionKey::new(&quic::CHACHA20, &self.header_protection_key[..])
+            .and_then(|key| key.new_mask(sample))
+            .expect("header protection key and sample lengths are fixed")
+    }
+    
+    /// Known epoch (current or retained) whose low 8 bits are `id`
+    fn epoch_state(&self, id: u8) -> Option<&EpochState> {
+        std::iter::once(&self.epoch)
+            .chain(self.retained.iter().rev())
+            .find(|epoch| epoch.id as u8 == id)
+    }
+    
+    fn epoch_state_mut(&mut self, id: u8) -> Option<&mut EpochState> {
+        std::iter::once(&mut self.epoch)
+            .chain(self.retained.iter_mut().rev())
+            .find(|epoch| epoch.id as u8 == id)
+    }
+    
+    /// Slide the hint cache to cover `replay_window` frames behind and `resync_window`
+    /// frames ahead of the current sequence, deriving only the hints not cached yet
+    fn refresh_hint_cache(&mut self, config: SilentConfig) {
+        let low = self.sequence.saturating_sub(config.replay_window.min(MAX_REPLAY_WINDOW));
+        let high = self.sequence.saturating_add(config.resync_window.min(MAX_RESYNC_WINDOW));
+        
+        let cache = &self.hint_cache;
+        let reusable = cache.protected == config.enable_header_protection
+            && cache.start <= low
+            && cache.start + cache.hints.len() as u64 > low;
+        let mut cache = std::mem::replace(&mut self.hint_cache, HintCache::new());
+        if !reusable {
+            cache = HintCache { start: low, protected: config.enable_header_protection, hints: VecDeque::new() };
+        }
+        
+        while cache.start < low {
+            cache.hints.pop_front();
+            cache.start += 1;
+        }
+        cache.hints.truncate((high - low + 1) as usize);
+        while cache.start + (cache.hints.len() as u64) <= high {
+            let seq = cache.start + cache.hints.len() as u64;
+            cache.hints.push_back((seq as u16) ^ self.header_masks(seq, config).hint);
+        }
+        self.hint_cache = cache;
+    }
+    
+    /// Sequence of a frame carrying `hint`: the expected one, else the first match
+    /// looking up to `resync_window` frames ahead, then `replay_window` frames back
+    fn find_hint(&mut self, hint: u16, config: SilentConfig) -> Result<u64, DynamicFramingError> {
+        self.refresh_hint_cache(config);
+        let current = self.sequence;
+        if self.hint_cache.get(current) == Some(hint) {
+            return Ok(current);
+        }
+        
+        // Desync (loss, reordering or garbage): bound how often we pay for a search
+        if !self.resyncs.allow(config.max_resyncs_per_second) {
+            return Err(DynamicFramingError::ResyncRateLimited);
+        }
+        let ahead = current + 1..=current.saturating_add(config.resync_window.min(MAX_RESYNC_WINDOW));
+        let behind = (current.saturating_sub(config.replay_window.min(MAX_REPLAY_WINDOW))..current).rev();
+        ahead.chain(behind)
+            .find(|&seq| self.hint_cache.get(seq) == Some(hint))
+            .ok_or(DynamicFramingError::DecryptionError)
+    }
+    
+    /// Mark `seq` of epoch `epoch_id` as received: advance the sequence past it, record
+    /// it in the replay window and, in chain-key mode, erase its key. Returns the salt of `seq` if still available.
+    fn consume_salt(&mut self, epoch_id: u8, seq: u64) -> Option<Secret> {
+        let salt = self.epoch_state_mut(epoch_id)?.consume(seq)?;
+        self.sequence = self.sequence.max(seq + 1);
+        self.replay.mark(seq);
+        Some(salt)
+    }
+    
+    /// Make `next` the current epoch, retaining the previous one for late frames
+    fn install_epoch(&mut self, next: EpochState) {
+        let mut previous = std::mem::replace(&mut self.epoch, next);
+        previous.close(self.epoch.start);
+        self.retained.push_back(previous);
+        while self.retained.len() > self.max_retained_epochs {
+            self.retained.pop_front();
+        }
+    }
+    
+    /// Stream (or other context) id bound into every frame's AAD (0 for `new`)
+    pub fn context_id(&self) -> u64 {
+        self.context_id
+    }
+
+    /// Share a DH ratchet with the opposite-direction generator of the same stream.
+    /// Without it, rekey frames only carry offers and the seed never heals.
+    pub fn attach_ratchet(&mut self, ratchet: RatchetHandle) {
+        self.ratchet = Some(ratchet);
+    }
+    
+    fn ratchet_handle(&mut self) -> RatchetHandle {
+        self.ratchet.get_or_insert_with(DhRatchet::new_shared).clone()
+    }
+    
+    /// Append the resumable state (see `resumption::SessionState`):
+    /// [HeaderKey (32B)] [HeaderProtectionKey (32B)] [Sequence (8B)] [ContextID (8B)]
+    /// [Direction (1B)] [MaxRetainedEpochs (4B)] [ReplayWindow] [EpochCount (4B)] [Epochs, current last]
+    pub(crate) fn encode_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.header_key[..]);
+        out.extend_from_slice(&self.header_protection_key[..]);
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.context_id.to_be_bytes());
+        out.push(match self.direction {
+            None => 0,
+            Some(Direction::ClientToServer) => 1,
+            Some(Direction::ServerToClient) => 2,
+        });
+        out.extend_from_slice(&(self.max_retained_epochs as u32).to_be_bytes());
+        self.replay.encode(out);
+        out.extend_from_slice(&(self.retained.len() as u32 + 1).to_be_bytes());
+        for epoch in self.retained.iter().chain(std::iter::once(&self.epoch)) {
+            epoch.encode(out);
+        }
+    }
+    
+    /// Restore a generator from `encode_state` (without a ratchet: attach a fresh one)
+    pub(crate) fn decode_state(reader: &mut StateReader) -> Option<Self> {
+        let header_key = Secret::new(reader.bytes()?);
+        let header_protection_key = Secret::new(reader.bytes()?);
+        let sequence = reader.u64()?;
+        let context_id = reader.u64()?;
+        let direction = match reader.u8()? {
+            0 => None,
+            1 => Some(Direction::ClientToServer),
+            2 => Some(Direction::ServerToClient),
+            _ => return None,
+        };
+        let max_retained_epochs = reader.u32()? as usize;
+        let replay = ReplayWindow::decode(reader)?;
+        let mut retained = VecDeque::new();
+        for _ in 0..reader.u32()? {
+            retained.push_back(EpochState::decode(reader)?);
+        }
+        let epoch = retained.pop_back()?;
+        
+        Some(Self {
+            header_key,
+            header_protection_key,
+            sequence,
+            context_id,
+            direction,
+            ratchet: None,
+            epoch,
+            retained,
+            max_retained_epochs,
+            replay,
+            hint_cache: HintCache::new(),
+            resyncs: ResyncLimiter { window_start: None, count: 0 },
+        })
+    }
+    
+    /// Create a new generator for one direction of one stream (or other context)
+    /// NewSeed = HKDF-Expand(HKDF-Extract(STREAM_KEY_SALT, BaseSeed), DirectionLabel || ContextID)
+    ///
+    /// Both peers must build the sending and receiving generators of a stream
+    /// with opposite directions; this is what keeps requests and ACKs on the
+    /// same stream from sharing a key/nonce sequence.
+    pub fn new_directional(base_seed: &[u8; 32], context_id: u64, direction: Direction) -> Self {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, STREAM_KEY_SALT).extract(base_seed);
+        let context_bytes = context_id.to_be_bytes();
+        let info = [direction.label(), &context_bytes[..]];
+        
+        let mut new_seed = Secret::default();
+        prk.expand(&info, hkdf::HKDF_SHA256)
+            .and_then(|okm| okm.fill(&mut new_seed[..]))
+            .expect("HKDF output length is fixed at 32 bytes");
+        
+        let mut generator = Self::with_seed(new_seed);
+        generator.direction = Some(direction);
+        generator.context_id = context_id;
+        generator
+    }
+    
+    /// Create a new generator with a seed diversified by a stream ID (or other context)
+    /// NewSeed = SHA256(BaseSeed + ContextID)
+    ///
+    /// Note: The result is the same for both directions. If both peers send on the
+    /// stream, use `new_directional` instead to avoid nonce reuse.
+    pub fn new_diversified(base_seed: &[u8; 32], context_id: u64) -> Self {
+        let mut context = Context::new(&SHA256);
+        context.update(base_seed);
+        context.update(&context_id.to_be_bytes());
+        
+        let digest = context.finish();
+        let mut new_seed = Secret::default();
+        new_seed.copy_from_slice(digest.as_ref());
+        
+        let mut generator = Self::with_seed(new_seed);
+        generator.context_id = context_id;
+        generator
+    }
+    
+    
+    /// Mix fresh entropy (a ratchet DH output) into the current seed (Rekeying)
+    /// NewSeed = SHA256(OldSeed + Entropy)
+    ///
+    /// The sequence keeps counting across rekeys (hints, the replay window and
+    /// the AAD all use it); only the keys change. This opens a new epoch starting
+    /// at the current sequence; the previous epoch is retained
+    /// (see `set_retained_epochs`) for frames still in flight.
+    pub fn mix_entropy(&mut self, entropy: &[u8]) {
+        // In chain-key mode the new epoch branches off the chain where it stands
+        let start = self.epoch.chain.as_ref().map_or(self.sequence, |chain| chain.index);
+        let next = self.epoch.successor(entropy, start)
+            .expect("the chain is always at or before its own index");
+        self.install_epoch(next);
+    }
+}
+
+/// Stream data parser for Dynamic Frames
+/// Receives the payload of control frames (`FrameType::is_control`)
+pub type ControlHandler = Box<dyn FnMut(FrameType, &[u8]) + Send>;
+
+/// Resynchronization telemetry of a `DynamicStreamParser`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResyncStats {
+    /// Scans started after a frame failed to parse
+    pub scans: u64,
+    /// Scans that found a valid frame
+    pub recovered: u64,
+    /// Scans that used up `SilentConfig::resync_scan_budget`
+    pub failed: u64,
+    /// Bytes skipped by all scans
+    pub bytes_skipped: u64,
+}
+
+pub struct DynamicStreamParser {
+    buffer: Vec<u8>,
+    /// Start of the unparsed data in `buffer`; parsed frames are only compacted
+    /// away once they make up half of it, so each byte is moved at most once
+    start: usize,
+    max_buffer_size: usize,
+    generator: SaltGenerator,
+    control_handler: Option<ControlHandler>,
+    /// Cells received so far of a message that continues (cell mode)
+    partial: Option<(FrameType, Vec<u8>)>,
+    /// Bytes skipped so far by the ongoing resync scan, if any
+    scan: Option<usize>,
+    resync_stats: ResyncStats,
+    /// Messages of a batch frame not returned yet
+    unpacked: VecDeque<Vec<u8>>,
+    /// Copy of the frame being authenticated (see `open_checked`)
+    scratch: Vec<u8>,
+}
+
+impl std::fmt::Debug for DynamicStreamParser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicStreamParser")
+            .field("buffered", &(self.buffer.len() - self.start))
+            .field("generator", &self.generator)
+            .field("resync_stats", &self.resync_stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DynamicStreamParser {
+    pub fn new(generator: SaltGenerator) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            max_buffer_size: 10 * 1024 * 1024, // 10MB
+            generator,
+            control_handler: None,
+            partial: None,
+            scan: None,
+            resync_stats: ResyncStats::default(),
+            unpacked: VecDeque::new(),
+            scratch: Vec::new(),
+        }
+    }
+    
+    /// Handle control frames (REKEY, PING); without a handler they are dropped like chaff
+    pub fn set_control_handler(&mut self, handler: impl FnMut(FrameType, &[u8]) + Send + 'static) {
+        self.control_handler = Some(Box::new(handler));
+    }
+
+    pub fn append_data(&mut self, data: &[u8]) -> Result<(), DynamicFramingError> {
+        let buffered = self.buffer_size() + data.len();
+        if buffered > self.max_buffer_size {
+            self.buffer.clear();
+            self.start = 0;
+            return Err(DynamicFramingError::InvalidLength(buffered));
+        }
+        if self.start > 0 && self.start >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Try to parse the next DATA message.
+    /// Chaff frames are dropped silently and control frames go to the control handler.
+    /// Cells of a message (see `SilentConfig::cell_size`) are reassembled first.
+    /// After a frame fails to parse, the following bytes are scanned for the next
+    /// valid frame (see `SilentConfig::resync_scan_budget` and `resync_stats`).
+    /// Returns:
+    /// - Ok(Some(payload)): Successfully parsed a message.
+    /// - Ok(None): Incomplete data.
+    /// - Err: Error (decryption, etc).
+    pub fn try_parse_next(&mut self, config: SilentConfig) -> Result<Option<Vec<u8>>, DynamicFramingError> {
+        // Frames are decrypted where they lie in the buffer
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let (consumed, result) = self.parse_next_from(&mut buffer[self.start..], config);
+        self.buffer = buffer;
+        self.start += consumed;
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+        }
+        result
+    }
+    
+    /// `try_parse_next` on a buffer owned by the caller (e.g. a codec's read buffer).
+    ///
+    /// Returns how many bytes at the start of `data` were used up, by parsed frames or
+    /// by frames dropped on error, together with the result. The caller discards those
+    /// bytes and keeps the rest for the next call. Payloads are decrypted into place, so
+    /// the used-up bytes no longer hold the frames that were received.
+    pub fn parse_next_from(&mut self, data: &mut [u8], config: SilentConfig) -> (usize, Result<Option<Vec<u8>>, DynamicFramingError>) {
+        // `parse_typed_frame` only advances the generator once a frame has been
+        // authenticated, so IncompleteData leaves it untouched for the next attempt.
+        
+        // Messages left over from a batch come first, without touching `data`
+        if let Some(message) = self.unpacked.pop_front() {
+            return (0, Ok(Some(message)));
+        }
+        
+        let mut consumed = 0;
+        loop {
+            let scanned;
+            let (frame_type, more, fragment) = if self.scan.is_some() {
+                let (used, result) = self.scan_next(&data[consumed..], config);
+                consumed += used;
+                match result {
+                    Ok(Some(frame)) => {
+                        scanned = frame;
+                        (scanned.0, scanned.1, &scanned.2[..])
+                    }
+                    Ok(None) => return (consumed, Ok(None)),
+                    Err(e) => return (consumed, Err(e)),
+                }
+            } else {
+                match self.open_checked(&mut data[consumed..], config) {
+                    Ok((frame_type, more, fragment, frame_len)) => {
+                        let fragment = &data[consumed..][fragment];
+                        consumed += frame_len;
+                        (frame_type, more, fragment)
+                    }
+                    Err(e) if self.begin_scan(&e, config) => {
+                        // Look for the next frame from the byte after the one that failed
+                        consumed += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        let (dropped, result) = self.recover(e, data.len() - consumed);
+                        return (consumed + dropped, result);
+                    }
+                }
+            };
+            if frame_type == FrameType::Chaff {
+                continue;
+            }
+            let payload = match self.reassemble(frame_type, more, fragment) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => continue,
+                Err(e) => return (consumed, Err(e)),
+            };
+            if frame_type == FrameType::Data {
+                return (consumed, Ok(Some(payload)));
+            }
+            if frame_type == FrameType::Batch {
+                match coalescing::split_batch(&payload) {
+                    Ok(messages) => self.unpacked.extend(messages.into_iter().map(<[u8]>::to_vec)),
+                    Err(e) => return (consumed, Err(e)),
+                }
+                match self.unpacked.pop_front() {
+                    Some(message) => return (consumed, Ok(Some(message))),
+                    None => continue,
+                }
+            }
+            if let Some(handler) = self.control_handler.as_mut().filter(|_| frame_type.is_control()) {
+                handler(frame_type, &payload);
+            }
+        }
+    }
+    
+    /// Open the frame at the start of `data`, authenticating it on a copy first: ring
+    /// wipes the whole claimed body when the tag check fails, and with a corrupted
+    /// length that body runs over the genuine frames the resync scan has to find.
+    /// Only the payload of an authenticated frame is written back in place.
+    ///
+    /// Returns: (Frame Type, Continuation Flag, Payload Range in `data`, Total Bytes Consumed)
+    fn open_checked(&mut self, data: &mut [u8], config: SilentConfig) -> Result<(FrameType, bool, Range<usize>, usize), DynamicFramingError> {
+        let located = locate_frame(&mut self.generator, data, config)?;
+        let total_frame_size = located.total_frame_size;
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&data[..total_frame_size]);
+        let opened = open_located(&mut self.generator, located, &mut self.scratch, config);
+        if let Ok((_, _, payload)) = &opened {
+            data[payload.clone()].copy_from_slice(&self.scratch[payload.clone()]);
+        }
+        self.scratch.zeroize();
+        let (frame_type, more, payload) = opened?;
+        Ok((frame_type, more, payload, total_frame_size))
+    }
+    
+    /// Append a cell to the pending message; returns the message once its last cell arrived
+    fn reassemble(&mut self, frame_type: FrameType, more: bool, fragment: &[u8]) -> Result<Option<Vec<u8>>, DynamicFramingError> {
+        let message = match self.partial.take() {
+            Some((pending_type, mut message)) if pending_type == frame_type => {
+                message.extend_from_slice(fragment);
+                message
+            }
+            // A cell of another type cannot continue the pending message, which is dropped
+            _ => fragment.to_vec(),
+        };
+        if message.len() > self.max_buffer_size {
+            return Err(DynamicFramingError::InvalidLength(message.len()));
+        }
+        if more {
+            self.partial = Some((frame_type, message));
+            return Ok(None);
+        }
+        Ok(Some(message))
+    }
+    
+    /// Start a resync scan if `error` lost track of the frame boundaries
+    fn begin_scan(&mut self, error: &DynamicFramingError, config: SilentConfig) -> bool {
+        // Frames that can be skipped whole keep us in sync, and a rate limited
+        // search is not worth bypassing with a scan
+        let fatal = !matches!(error,
+            DynamicFramingError::IncompleteData
+            | DynamicFramingError::Replay { .. }
+            | DynamicFramingError::UnknownEpoch { .. }
+            | DynamicFramingError::ResyncRateLimited);
+        if !fatal || config.resync_scan_budget == 0 {
+            return false;
+        }
+        self.scan = Some(1);
+        self.partial = None;
+        self.resync_stats.scans += 1;
+        true
+    }
+    
+    /// Continue the resync scan over `data`: the first offset holding a frame that
+    /// authenticates ends it. Frames are opened from a copy here, since a failed
+    /// open in place would overwrite bytes that may hold the next frame.
+    ///
+    /// Returns how many bytes were used up (skipped bytes and the frame), and the
+    /// frame, None if more data is needed, or `ResyncFailed` once the budget is spent.
+    #[allow(clippy::type_complexity)]
+    fn scan_next(&mut self, data: &[u8], config: SilentConfig) -> (usize, Result<Option<(FrameType, bool, Vec<u8>)>, DynamicFramingError>) {
+        let mut skipped = self.scan.unwrap_or(0);
+        // Candidates are not real desyncs of the peer: do not charge the search limiter
+        let scan_config = SilentConfig { max_resyncs_per_second: 0, ..config };
+        
+        for offset in 0..data.len() {
+            if skipped > config.resync_scan_budget {
+                let dropped = skipped + data.len() - offset;
+                self.scan = None;
+                self.resync_stats.failed += 1;
+                self.resync_stats.bytes_skipped += dropped as u64;
+                return (data.len(), Err(DynamicFramingError::ResyncFailed { skipped: dropped }));
+            }
+            match parse_frame(&mut self.generator, &data[offset..], scan_config) {
+                Ok((frame_type, more, payload, frame_len)) => {
+                    self.scan = None;
+                    self.resync_stats.recovered += 1;
+                    self.resync_stats.bytes_skipped += skipped as u64;
+                    return (offset + frame_len, Ok(Some((frame_type, more, payload))));
+                }
+                Err(DynamicFramingError::IncompleteData) => {
+                    // A plausible header (or too few bytes to tell): wait for the rest
+                    self.scan = Some(skipped);
+                    return (offset, Ok(None));
+                }
+                Err(_) => skipped += 1,
+            }
+        }
+        self.scan = Some(skipped);
+        (data.len(), Ok(None))
+    }
+    
+    /// Receiving generator (e.g. to export it for session resumption)
+    pub fn generator(&self) -> &SaltGenerator {
+        &self.generator
+    }
+    
+    /// Share a DH ratchet with the sending generator of the same stream (see `SaltGenerator::attach_ratchet`)
+    pub fn attach_ratchet(&mut self, ratchet: RatchetHandle) {
+        self.generator.attach_ratchet(ratchet);
+    }
+    
+    /// Append the resumable state (see `resumption::SessionState`):
+    /// [Generator] [MaxBufferSize (8B)] [Unparsed Length (4B)] [Unparsed Bytes]
+    /// [Partial Flag (1B)] ([Partial Type (1B)] [Length (4B)] [Cells])
+    /// [Scan Flag (1B)] ([Skipped (8B)]) [Unpacked Count (4B)] ([Length (4B)] [Message])*
+    pub(crate) fn encode_state(&self, out: &mut Vec<u8>) {
+        let put = |out: &mut Vec<u8>, bytes: &[u8]| {
+            out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            out.extend_from_slice(bytes);
+        };
+        self.generator.encode_state(out);
+        out.extend_from_slice(&(self.max_buffer_size as u64).to_be_bytes());
+        put(out, &self.buffer[self.start..]);
+        match &self.partial {
+            Some((frame_type, cells)) => {
+                out.extend_from_slice(&[1, frame_type.id()]);
+                put(out, cells);
+            }
+            None => out.push(0),
+        }
+        match self.scan {
+            Some(skipped) => {
+                out.push(1);
+                out.extend_from_slice(&(skipped as u64).to_be_bytes());
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&(self.unpacked.len() as u32).to_be_bytes());
+        for message in &self.unpacked {
+            put(out, message);
+        }
+    }
+    
+    /// Restore a parser from `encode_state` (without a ratchet or control handler)
+    pub(crate) fn decode_state(reader: &mut StateReader) -> Option<Self> {
+        let mut parser = Self::new(SaltGenerator::decode_state(reader)?);
+        parser.max_buffer_size = reader.u64()? as usize;
+        parser.buffer = reader.length_prefixed()?.to_vec();
+        parser.partial = match reader.u8()? {
+            0 => None,
+            1 => Some((FrameType::from_id(reader.u8()?)?, reader.length_prefixed()?.to_vec())),
+            _ => return None,
+        };
+        parser.scan = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u64()? as usize),
+            _ => return None,
+        };
+        for _ in 0..reader.u32()? {
+            parser.unpacked.push_back(reader.length_prefixed()?.to_vec());
+        }
+        Some(parser)
+    }
+    
+    /// Resynchronization telemetry (see `ResyncStats`)
+    pub fn resync_stats(&self) -> ResyncStats {
+        self.resync_stats
+    }
+    
+    /// Resynchronize after a failed parse: how many of the `remaining` bytes to drop
+    fn recover(&mut self, error: DynamicFramingError, remaining: usize) -> (usize, Result<Option<Vec<u8>>, DynamicFramingError>) {
+        match error {
+            DynamicFramingError::IncompleteData => {
+                (0, Ok(None))
+            }
+            e @ DynamicFramingError::Replay { frame_len, .. } => {
+                // Still in sync on frame boundaries: drop just this duplicate
+                (frame_len, Err(e))
+            }
+            e @ DynamicFramingError::UnknownEpoch { frame_len, .. } => {
+                // Drop just this frame, and the message it may have been a cell of
+                self.partial = None;
+                (frame_len, Err(e))
+            }
+            e @ DynamicFramingError::ResyncRateLimited => {
+                // Nothing is dropped: the frame is parsed again on the next call,
+                // once the limiter allows another hint search
+                (0, Err(e))
+            }
+            e => {
+                // Frame boundaries are lost and resync scans are disabled
+                // (`resync_scan_budget` is 0): nothing buffered can be parsed any more
+                self.partial = None;
+                (remaining, Err(e))
+            }
+        }
+    }
+    
+    pub fn buffer_size(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+    
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.start = 0;
+        self.partial = None;
+        self.scan = None;
+        self.unpacked.clear();
+    }
+}
+
+/// Size of the reads `SilentStream` issues on its transport
+const STREAM_READ_CHUNK: usize = 16 * 1024;
+
+/// Dynamic frames over a blocking byte stream (TCP, Unix socket, pipe, ...)
+///
+/// Every `write` (or `send_message`) becomes one DATA frame, built with the sending
+/// generator; incoming frames are parsed with an independent receiving generator
+/// (see `SaltGenerator::new_directional`). `read` hands out message bytes as a
+/// continuous stream; `recv_message` keeps the message boundaries.
+pub struct SilentStream<T: Read + Write> {
+    inner: T,
+    sender: SaltGenerator,
+    parser: DynamicStreamParser,
+    config: SilentConfig,
+    /// Message bytes received but not yet returned by `read`
+    pending: Vec<u8>,
+    pending_offset: usize,
+}
+
+impl<T: Read + Write> SilentStream<T> {
+    pub fn new(inner: T, sender: SaltGenerator, receiver: SaltGenerator, config: SilentConfig) -> Self {
+        Self {
+            inner,
+            sender,
+            parser: DynamicStreamParser::new(receiver),
+            config,
+            pending: Vec::new(),
+            pending_offset: 0,
+        }
+    }
+    
+    /// Handle control frames (see `DynamicStreamParser::set_control_handler`)
+    pub fn set_control_handler(&mut self, handler: impl FnMut(FrameType, &[u8]) + Send + 'static) {
+        self.parser.set_control_handler(handler);
+    }
+    
+    /// Send one message as a DATA frame (or a run of cells in cell mode)
+    pub fn send_message(&mut self, payload: &[u8]) -> io::Result<()> {
+        let frame = build_dynamic_frame(&mut self.sender, payload, self.config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.inner.write_all(&frame)
+    }
+    
+    /// Receive the next message, blocking until it is complete.
+    /// Returns None once the transport is closed at a message boundary; closing inside
+    /// a frame or between the cells of a message fails with `UnexpectedEof`.
+    /// Duplicate frames and frames of unknown epochs are skipped.
+    pub fn recv_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = [0u8; STREAM_READ_CHUNK];
+        loop {
+            match self.parser.try_parse_next(self.config) {
+                Ok(Some(payload)) => return Ok(Some(payload)),
+                Ok(None) => {}
+                Err(DynamicFramingError::Replay { .. } | DynamicFramingError::UnknownEpoch { .. }) => continue,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+            
+            let read = match self.inner.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if read == 0 {
+                if self.parser.partial.is_some() {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed inside a message"));
+                }
+                if self.parser.buffer_size() == 0 {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed inside a frame"));
+            }
+            self.parser.append_data(&chunk[..read])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+    }
+    
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Write> Read for SilentStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Empty messages carry no bytes: keep reading rather than signal EOF
+        while self.pending_offset == self.pending.len() {
+            match self.recv_message()? {
+                Some(message) => {
+                    self.pending = message;
+                    self.pending_offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let available = &self.pending[self.pending_offset..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pending_offset += len;
+        Ok(len)
+    }
+}
+
+impl<T: Read + Write> Write for SilentStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // No frame for nothing (`send_message` still sends empty messages)
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.send_message(buf)?;
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
-/// Manages salt rotation and synchronization
-pub struct SaltGenerator {
-    seed: [u8; 32],
-    sequence: u64,
+/// Build a dynamic DATA frame (see `build_typed_frame`)
+pub fn build_dynamic_frame(
+    generator: &mut SaltGenerator,
+    payload: &[u8],
+    config: SilentConfig
+) -> Result<Vec<u8>, DynamicFramingError> {
+    build_typed_frame(generator, FrameType::Data, payload, config)
 }
 
-impl SaltGenerator {
-    /// Create a new generator with a specific seed
-    pub fn new(seed: [u8; 32]) -> Self {
-        Self { seed, sequence: 0 }
-    }
+/// Build a dynamic frame of any `FrameType`
+///
+/// `generator` must be the sending generator of this direction (see `SaltGenerator::new_directional`).
+/// In cell mode (`SilentConfig::cell_size`) the result is a run of cells, one frame each.
+pub fn build_typed_frame(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    payload: &[u8],
+    config: SilentConfig
+) -> Result<Vec<u8>, DynamicFramingError> {
+    let mut frame = Vec::new();
+    build_frame_into(generator, frame_type, payload, config, &mut frame)?;
+    Ok(frame)
+}
 
-    /// Create a new generator with a random seed
-    pub fn new_random() -> Self {
-        let rng = SystemRandom::new();
-        let mut seed = [0u8; 32];
-        rng.fill(&mut seed).expect("Failed to generate random seed");
-        Self::new(seed)
+/// `build_typed_frame` appending to a caller-provided buffer: the payload is copied
+/// once, into `out`, and sealed there. Returns the number of bytes appended
+/// (nothing is appended on error).
+pub fn build_frame_into(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    payload: &[u8],
+    config: SilentConfig,
+    out: &mut Vec<u8>
+) -> Result<usize, DynamicFramingError> {
+    let start = out.len();
+    let result = match config.cell_size {
+        None => append_frame(generator, frame_type, false, payload, config, out),
+        Some(cell_size) if payload.is_empty() => append_cell(generator, frame_type, false, payload, cell_size, config, out),
+        Some(cell_size) => {
+            let mut chunks = payload.chunks(cell_capacity(cell_size)).peekable();
+            let mut result = Ok(());
+            while let Some(chunk) = chunks.next() {
+                let more = chunks.peek().is_some();
+                result = append_cell(generator, frame_type, more, chunk, cell_size, config, out);
+                if result.is_err() {
+                    break;
+                }
+            }
+            result
+        }
+    };
+    if let Err(e) = result {
+        out.truncate(start);
+        return Err(e);
     }
+    Ok(out.len() - start)
+}
 
-    /// Reset sequence to 0
-    pub fn reset(&mut self) {
-        self.sequence = 0;
-    }
+/// Room to leave in front of the payload for `seal_frame_in_place`:
+/// the largest header (that of a rekey frame) and the frame type
+pub const FRAME_HEADROOM: usize = MAX_FIXED_HEADER_LEN + RATCHET_OFFER_ANSWER_LEN + FRAME_TYPE_LEN;
 
-    /// Get the current sequence number
-    pub fn sequence(&self) -> u64 {
-        self.sequence
+/// Build a frame around a payload that is already in place (zero-copy).
+///
+/// `buf` holds `FRAME_HEADROOM` bytes of any value followed by the payload (e.g. a
+/// `Whisper` encoded straight into the buffer). Padding and tag are appended, the
+/// header is written at the end of the headroom, and the frame is `buf[start..]`
+/// for the returned `start`. In cell mode the payload must fit into one cell.
+pub fn seal_frame_in_place(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    buf: &mut Vec<u8>,
+    config: SilentConfig
+) -> Result<usize, DynamicFramingError> {
+    if buf.len() < FRAME_HEADROOM {
+        return Err(DynamicFramingError::InvalidLength(buf.len()));
     }
+    let payload_len = buf.len() - FRAME_HEADROOM;
+    let config = match config.cell_size {
+        Some(cell_size) => cell_config(cell_size, payload_len, config)?,
+        None => config,
+    };
     
-    /// Set the sequence number (useful for sync)
-    pub fn set_sequence(&mut self, seq: u64) {
-        self.sequence = seq;
-    }
+    let prepared = prepare_frame(generator, payload_len, config)?;
+    let body_start = FRAME_HEADROOM - FRAME_TYPE_LEN;
+    let header_start = body_start - prepared.header.len;
+    buf[header_start..body_start].copy_from_slice(prepared.header.as_slice());
+    prepared.seal(generator, frame_type, false, buf, body_start)?;
+    Ok(header_start)
+}
 
-    /// Generate the next salt and advance sequence
-    /// Salt = SHA256(Seed + Sequence_BE_Bytes)
-    pub fn next_salt(&mut self) -> [u8; 32] {
-        let mut context = Context::new(&SHA256);
-        context.update(&self.seed);
-        context.update(&self.sequence.to_be_bytes());
-        
-        self.sequence += 1;
-        
-        let digest = context.finish();
-        let mut salt = [0u8; 32];
-        salt.copy_from_slice(digest.as_ref());
-        salt
-    }
-    
-    /// Generate salt for a specific sequence without advancing state
-    pub fn get_salt_for_sequence(&self, seq: u64) -> [u8; 32] {
-        let mut context = Context::new(&SHA256);
-        context.update(&self.seed);
-        context.update(&seq.to_be_bytes());
-        
-        let digest = context.finish();
-        let mut salt = [0u8; 32];
-        salt.copy_from_slice(digest.as_ref());
-        salt
-    }
+/// Payload bytes that fit into a cell of `cell_size` bytes:
+/// every cell body is [Type (1B)] [Chunk] [Padding] [PaddingLen (4B)] + Tag
+pub fn cell_capacity(cell_size: usize) -> usize {
+    cell_size.saturating_sub(FRAME_TYPE_LEN + PADDING_TRAILER_LEN + TAG_LEN)
+}
 
-    /// Create a new generator with a seed diversified by a stream ID (or other context)
-    /// NewSeed = SHA256(BaseSeed + ContextID)
-    pub fn new_diversified(base_seed: [u8; 32], context_id: u64) -> Self {
-        let mut context = Context::new(&SHA256);
-        context.update(&base_seed);
-        context.update(&context_id.to_be_bytes());
-        
-        let digest = context.finish();
-        let mut new_seed = [0u8; 32];
-        new_seed.copy_from_slice(digest.as_ref());
-        
-        Self::new(new_seed)
+/// `config` padding a chunk of `chunk_len` bytes to exactly one cell
+fn cell_config(cell_size: usize, chunk_len: usize, config: SilentConfig) -> Result<SilentConfig, DynamicFramingError> {
+    if cell_size < MIN_CELL_SIZE || chunk_len > cell_capacity(cell_size) {
+        return Err(DynamicFramingError::InvalidLength(cell_size));
     }
+    Ok(SilentConfig { padding: PaddingPolicy::FixedSize(cell_size), ..config })
+}
 
-    
-    /// Mix fresh entropy into the current seed (Rekeying)
-    /// NewSeed = SHA256(OldSeed + Entropy)
-    /// Resets sequence to 0 (or keeps it? Usually rekeying resets sequence context, 
-    /// but for this specific "Stream" abstraction, keeping sequence monotonic is easier for QUIC mapping.
-    /// Let's KEEP sequence monotonic but change the seed foundation.
-    pub fn mix_entropy(&mut self, entropy: &[u8]) {
-        let mut context = Context::new(&SHA256);
-        context.update(&self.seed);
-        context.update(entropy);
-        
-        let digest = context.finish();
-        self.seed.copy_from_slice(digest.as_ref());
-        // Note: We do NOT reset sequence here to simplify upper layer logic (allocating streams),
-        // but cryptographically it effectively starts a new chain.
-    }
+/// Build one frame whose encrypted body is exactly `cell_size` bytes
+/// (`chunk` must fit, see `cell_capacity`)
+pub(crate) fn build_cell(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    more: bool,
+    chunk: &[u8],
+    cell_size: usize,
+    config: SilentConfig
+) -> Result<Vec<u8>, DynamicFramingError> {
+    let mut frame = Vec::new();
+    append_cell(generator, frame_type, more, chunk, cell_size, config, &mut frame)?;
+    Ok(frame)
 }
 
-/// Stream data parser for Dynamic Frames
-pub struct DynamicStreamParser {
-    buffer: Vec<u8>,
-    max_buffer_size: usize,
-    generator: SaltGenerator,
+fn append_cell(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    more: bool,
+    chunk: &[u8],
+    cell_size: usize,
+    config: SilentConfig,
+    out: &mut Vec<u8>
+) -> Result<(), DynamicFramingError> {
+    let config = cell_config(cell_size, chunk.len(), config)?;
+    append_frame(generator, frame_type, more, chunk, config, out)
 }
 
-impl DynamicStreamParser {
-    pub fn new(generator: SaltGenerator) -> Self {
-        Self {
-            buffer: Vec::new(),
-            max_buffer_size: 10 * 1024 * 1024, // 10MB
-            generator,
-        }
-    }
+/// Append a single frame to `out`; `more` sets the continuation flag
+fn append_frame(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    more: bool,
+    payload: &[u8],
+    config: SilentConfig,
+    out: &mut Vec<u8>
+) -> Result<(), DynamicFramingError> {
+    let prepared = prepare_frame(generator, payload.len(), config)?;
+    out.reserve(prepared.header.len + prepared.body_len);
+    out.extend_from_slice(prepared.header.as_slice());
+    let body_start = out.len();
+    out.push(0); // frame type, written by `seal`
+    out.extend_from_slice(payload);
+    prepared.seal(generator, frame_type, more, out, body_start)
+}
 
-    pub fn append_data(&mut self, data: &[u8]) -> Result<(), DynamicFramingError> {
-        if self.buffer.len() + data.len() > self.max_buffer_size {
-            self.buffer.clear();
-            return Err(DynamicFramingError::InvalidLength(self.buffer.len() + data.len()));
-        }
-        self.buffer.extend_from_slice(data);
-        Ok(())
-    }
+/// Largest header: fixed part plus an offer + answer Ratchet Block
+const MAX_HEADER_LEN: usize = MAX_FIXED_HEADER_LEN + RATCHET_OFFER_ANSWER_LEN;
 
-    /// Try to parse the next frame.
-    /// Returns:
-    /// - Ok(Some(payload)): Successfully parsed a frame.
-    /// - Ok(None): Incomplete data.
-    /// - Err: Error (decryption, etc).
-    pub fn try_parse_next(&mut self, config: SilentConfig) -> Result<Option<Vec<u8>>, DynamicFramingError> {
-        // We need to clone the generator state to try parsing, because parse_dynamic_frame modifies it.
-        // If parsing fails due to IncompleteData, we must revert the generator.
-        // Actually `parse_dynamic_frame` modifies generator.
-        // So we can backup sequence.
-        
-        let start_seq = self.generator.sequence;
-        
-        match parse_dynamic_frame(&mut self.generator, &self.buffer, config) {
-            Ok((payload, consumed)) => {
-                self.buffer.drain(0..consumed);
-                Ok(Some(payload))
-            }
-            Err(DynamicFramingError::IncompleteData) => {
-                // Revert sequence
-                self.generator.sequence = start_seq;
-                Ok(None)
-            }
-            Err(e) => {
-                // Fatal error, clear buffer? 
-                // Unlike static framing, if we fail to decrypt, it might be a sync issue or attack.
-                // We probably can't recover easily without resync (which QUIC handles by retransmit, but we are top level).
-                // Actually, if it's just garbled data, we are stuck.
-                self.buffer.clear();
-                Err(e)
-            }
-        }
+/// Largest AAD (see `frame_aad`)
+const MAX_AAD_LEN: usize = 1 + 8 + 8 + MAX_HEADER_LEN;
+
+/// Fixed-capacity byte buffer on the stack, for headers and AAD
+#[derive(Clone, Copy)]
+struct StackBuf<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> StackBuf<N> {
+    fn new() -> Self {
+        Self { bytes: [0; N], len: 0 }
     }
     
-    pub fn buffer_size(&self) -> usize {
-        self.buffer.len()
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        self.bytes[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
     }
     
-    pub fn clear(&mut self) {
-        self.buffer.clear();
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
     }
 }
 
-/// Build a dynamic frame
-/// 
+/// A frame whose header and keys are ready, waiting for its body to be sealed
+struct PreparedFrame {
+    header: StackBuf<MAX_HEADER_LEN>,
+    aad: StackBuf<MAX_AAD_LEN>,
+    key: LessSafeKey,
+    nonce: aead::Nonce,
+    padding_len: usize,
+    /// Encrypted body length, tag included
+    body_len: usize,
+    ratchet_secret: Option<Secret>,
+}
+
+/// Take the next sequence of `generator` and compute the header of a frame
+/// carrying `payload_len` bytes.
+///
 /// Process:
 /// 1. Generate Salt for current sequence.
 /// 2. Derive Key and Nonce from Salt.
-/// 3. Add random padding to data.
-/// 4. Encrypt (Data + Padding).
-/// 5. Obfuscate Length of encrypted data.
-/// 6. Return [ObfuscatedLength][EncryptedData]
-pub fn build_dynamic_frame(
-    generator: &mut SaltGenerator, 
-    payload: &[u8],
+/// 3. Size the body: frame type, data and padding (see `PaddingPolicy`).
+/// 4. Obfuscate Length of encrypted data (and Version/Hint/Epoch) with the header masks.
+/// 5. `PreparedFrame::seal` encrypts (Type + Data + Padding), authenticating the header as AAD (see `frame_aad`).
+///
+/// Frame: [ObfuscatedLength][ObfuscatedVersion][ObfuscatedHint][ObfuscatedEpoch][RatchetBlock][EncryptedData]
+fn prepare_frame(
+    generator: &mut SaltGenerator,
+    payload_len: usize,
     config: SilentConfig
-) -> Result<Vec<u8>, DynamicFramingError> {
+) -> Result<PreparedFrame, DynamicFramingError> {
+    // The epoch that encrypts THIS frame (a ratchet answer below only affects later frames)
+    let epoch_id = generator.epoch() as u8;
     let salt = generator.next_salt();
-    let sequence = generator.sequence - 1; // next_salt incremented it, so we use (seq-1) used for this salt.
-    // Wait, next_salt() -> self.sequence += 1.
-    // So salt was generated using `sequence` BEFORE increment.
-    // Let's check SaltGenerator implementation:
-    // context.update(&self.sequence.to_be_bytes()); self.sequence += 1;
-    // So salt corellates to `sequence` (the old value). 
-    // Correct.
+    // `next_salt` derives the salt of the current sequence, then advances it
+    let sequence = generator.sequence - 1;
     
     // 1. Derive Keys from Salt
-    // Key = Salt[0..32] (ChaCha20 key is 32 bytes)
-    // Nonce = Salt[0..12] (96-bit nonce)
-    // Mask = Salt[12..16] (32-bit (4 byte) mask for length)
-    
-    let key_bytes: [u8; 32] = salt; // Use salt directly as key
-    let nonce_bytes: [u8; 12] = salt[0..12].try_into().unwrap();
-    let mask_bytes: [u8; 4] = salt[12..16].try_into().unwrap();
-    let mask = u32::from_be_bytes(mask_bytes);
+    // Key and Nonce are separate HKDF expansions of the salt (see `frame_key`)
+    // Header masks come from the header key instead (see `SaltGenerator::header_masks`)
+    let masks = generator.header_masks(sequence, config);
     
     // 2. Encryption Setup
-    let unbound_key = UnboundKey::new(&aead::CHACHA20_POLY1305, &key_bytes)
-        .map_err(|_| DynamicFramingError::EncryptionError)?;
-    let key = LessSafeKey::new(unbound_key);
-    let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
+    let (key, nonce) = frame_key(config.cipher_suite, &salt)?;
     
-    // 3. Prepare Buffer (Payload + Tag Space)
-    // We don't add extra random padding for now to keep it simple, 
-    // but the architecture allows it. The Tag adds 16 bytes.
-    let mut buffer = payload.to_vec();
+    // 3. Body: [Type (1B)] [Data] [Padding (zeros)] [PaddingLen (4B)] (+ Tag)
+    // The padding length travels inside the encrypted body, so the receiver can
+    // strip it whatever policy the sender used. The Tag adds 16 bytes.
+    let data_len = FRAME_TYPE_LEN + payload_len;
+    let padding_len = config.padding.padding_len(data_len + PADDING_TRAILER_LEN + TAG_LEN)?;
+    if padding_len > u32::MAX as usize {
+        return Err(DynamicFramingError::InvalidLength(padding_len));
+    }
     
-    // 4. Encrypt in place (append tag)
-    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut buffer)
-        .map_err(|_| DynamicFramingError::EncryptionError)?;
-        
-    let encrypted_len = buffer.len();
+    // 4. Length of the encrypted body (tag included), known before sealing
+    // because the header goes into the AAD
+    let encrypted_len = data_len + padding_len + PADDING_TRAILER_LEN + TAG_LEN;
     if encrypted_len > u32::MAX as usize {
         return Err(DynamicFramingError::InvalidLength(encrypted_len));
     }
     
     // 5. Obfuscate Length
-    let obfuscated_len = (encrypted_len as u32) ^ mask;
+    let obfuscated_len = (encrypted_len as u32) ^ masks.length;
     
-    // 6. Assemble Frame
-    // Format: [ObfuscatedLength (4B)] [ObfuscatedHint (2B, Optional)] [EncryptedData]
-    // 6. Assemble Frame
-    // Format: [ObfuscatedLength (4B)] [ObfuscatedHint (2B, Optional)] [RekeyEntropy (32B, Optional)] [EncryptedData]
-    // Check if we need to insert Rekey Entropy
+    // 6. Assemble Header
+    // Format: [ObfuscatedLength (4B)] [ObfuscatedVersion (1B)] [ObfuscatedHint (2B, Optional)]
+    //         [ObfuscatedEpoch (1B, Optional)] [RatchetBlock (33/73B, Optional)] [EncryptedData]
+    // Check if we need to insert a Ratchet Block
     // Condition: Enabled && sequence > 0 && sequence % interval == 0
     // Note: We use `sequence` (the value used for THIS frame).
     // If sequence == 0, we don't rekey immediately (initial state).
-    let do_rekey = config.enable_double_ratchet && sequence > 0 && (sequence % config.ratchet_interval == 0);
-    
-    let mut capacity = 4 + encrypted_len;
-    if config.enable_sequence_hint { capacity += 2; }
-    if do_rekey { capacity += 32; }
+    let do_rekey = is_rekey_slot(config, sequence);
     
-    let mut frame = Vec::with_capacity(capacity); 
-    frame.extend_from_slice(&obfuscated_len.to_be_bytes());
+    let mut header = StackBuf::new();
+    header.extend_from_slice(&obfuscated_len.to_be_bytes());
+    header.extend_from_slice(&[FRAME_FORMAT_VERSION ^ masks.version]);
     
     if config.enable_sequence_hint {
-        // Calculate Hint: Low 16 bits of Sequence ^ High 16 bits of Mask (or some other part of Salt)
-        // Let's use Mask's high 16 bits.
-        // mask is u32 from salt[12..16].
-        // Let's just use salt[16..18] for hint mask to avoid reusing the length mask bits too much (though it's fine).
-        // Salt is 32 bytes.
-        let hint_mask_bytes: [u8; 2] = salt[16..18].try_into().unwrap();
-        let hint_mask = u16::from_be_bytes(hint_mask_bytes);
-        
+        // Calculate Hint: Low 16 bits of Sequence ^ Hint Mask
         // Sequence used was `sequence` (before increment).
-        let seq_low = (sequence as u16);
-        let hint = seq_low ^ hint_mask;
+        let seq_low = sequence as u16;
+        let hint = seq_low ^ masks.hint;
         
-        frame.extend_from_slice(&hint.to_be_bytes());
+        header.extend_from_slice(&hint.to_be_bytes());
+    }
+    
+    if config.enable_double_ratchet {
+        // Epoch ID: lets the receiver pick the right keys across rekey boundaries
+        header.extend_from_slice(&[epoch_id ^ masks.epoch]);
     }
     
+    let mut ratchet_secret = None;
     if do_rekey {
-        // Generate Fresh Entropy (Simulating Ephemeral Public Key)
-        let rng = SystemRandom::new();
-        let mut entropy = [0u8; 32];
-        rng.fill(&mut entropy).map_err(|_| DynamicFramingError::EncryptionError)?;
+        // Offer (and answer, if the peer offered) ephemeral X25519 keys
+        let ratchet = generator.ratchet_handle();
+        let (mut block, secret) = ratchet.lock().unwrap().prepare_block(sequence + 1)?;
+        mask_epoch_start(&mut block, masks);
         
         // Insert into header
-        frame.extend_from_slice(&entropy);
-        
-        // Update local generator state
-        generator.mix_entropy(&entropy);
+        header.extend_from_slice(&block);
+        ratchet_secret = secret;
     }
     
-    frame.extend_from_slice(&buffer);
-    
-    Ok(frame)
+    let aad = frame_aad(generator.context_id(), sequence, header.as_slice());
+    Ok(PreparedFrame { header, aad, key, nonce, padding_len, body_len: encrypted_len, ratchet_secret })
 }
 
-/// Parse a dynamic frame
-/// 
-/// Note: This function attempts to parse ONE frame from the beginning of `data`.
-/// It assumes the `generator` is synchronized to the correct state for this frame.
-/// 
+impl PreparedFrame {
+    /// Seal `buf[body_start..]`, which holds a slot for the frame type followed by the
+    /// payload: the type is filled in, padding and tag are appended.
+    fn seal(
+        self,
+        generator: &mut SaltGenerator,
+        frame_type: FrameType,
+        more: bool,
+        buf: &mut Vec<u8>,
+        body_start: usize
+    ) -> Result<(), DynamicFramingError> {
+        buf[body_start] = if more { frame_type.id() | FRAME_MORE_FLAG } else { frame_type.id() };
+        buf.resize(buf.len() + self.padding_len, 0);
+        buf.extend_from_slice(&(self.padding_len as u32).to_be_bytes());
+        
+        // 7. Encrypt in place, authenticating the whole header
+        let tag = self.key.seal_in_place_separate_tag(self.nonce, Aad::from(self.aad.as_slice()), &mut buf[body_start..])
+            .map_err(|_| DynamicFramingError::EncryptionError)?;
+        buf.extend_from_slice(tag.as_ref());
+        
+        // Update local generator state: THIS frame still uses the old epoch
+        if let Some(secret) = self.ratchet_secret {
+            generator.mix_entropy(&secret[..]);
+        }
+        Ok(())
+    }
+}
+
+/// Additional data bound to every frame:
+/// Version (1B) || ContextID (8B) || Sequence (8B) || Header (everything before the encrypted body)
+fn frame_aad(context_id: u64, sequence: u64, header: &[u8]) -> StackBuf<MAX_AAD_LEN> {
+    let mut aad = StackBuf::new();
+    aad.extend_from_slice(&[FRAME_FORMAT_VERSION]);
+    aad.extend_from_slice(&context_id.to_be_bytes());
+    aad.extend_from_slice(&sequence.to_be_bytes());
+    aad.extend_from_slice(header);
+    aad
+}
+
+/// XOR the EpochStart field of an answering Ratchet Block with its header mask (involution)
+fn mask_epoch_start(block: &mut [u8], masks: HeaderMasks) {
+    if block[0] & RATCHET_ANSWER != 0 {
+        let start = u64::from_be_bytes(block[65..73].try_into().unwrap()) ^ masks.epoch_start;
+        block[65..73].copy_from_slice(&start.to_be_bytes());
+    }
+}
+
+/// Parse a dynamic frame (see `parse_typed_frame`), whatever its type.
+///
+/// Chaff and control frames are returned like data; use `parse_typed_frame`
+/// or `DynamicStreamParser` to tell them apart.
+///
 /// Returns: (Decrypted Payload, Total Bytes Consumed)
 pub fn parse_dynamic_frame(
     generator: &mut SaltGenerator,
     data: &[u8],
     config: SilentConfig
 ) -> Result<(Vec<u8>, usize), DynamicFramingError> {
-    // Basic header size depends on config
-    let mut header_size = 4;
-    if config.enable_sequence_hint { header_size += 2; }
-    
-    // We need to know if we expect rekeying to calculate header size.
-    // Speculative: We need to know the sequence first!
-    // But `generator.sequence` tells us what we expect.
-    // If `generator.sequence % interval == 0`, we expect rekey.
-    // Warning: If we are desynced (Robust Mode needed), we might predict wrong header size!
-    // This is the tricky part of mixing Robust Mode with Rekeying.
-    // Robust Mode relies on fixed header offsets to find the Hint?
-    // Actually, Hint is always at offset 4. Rekeying comes AFTER Hint. Safe.
-    
-    // However, if we resync (jump sequence), we might jump over a rekey frame or land on one.
-    // For now, assume sync or simple resync.
-    
-    let current_seq = generator.sequence;
-    // Note: parse is called BEFORE next_salt implies we haven't consumed this seq yet?
-    // No, parse_dynamic_frame calls next_salt().
-    // So `generator.sequence` is the stored state (e.g. 0).
-    // The frame we are about to parse corresponds to `generator.sequence` (e.g. 0).
+    parse_typed_frame(generator, data, config).map(|(_, payload, consumed)| (payload, consumed))
+}
+
+/// Parse a dynamic frame
+///
+/// Note: This function attempts to parse ONE frame from the beginning of `data`.
+/// It assumes the `generator` is synchronized to the correct state for this frame
+/// (or close enough for the sequence hint to resync), and that it was built for
+/// the peer's sending direction.
+/// A cell of a longer message (cell mode) yields just its own part of the payload;
+/// `DynamicStreamParser` reassembles whole messages.
+///
+/// Returns: (Frame Type, Decrypted Payload, Total Bytes Consumed)
+pub fn parse_typed_frame(
+    generator: &mut SaltGenerator,
+    data: &[u8],
+    config: SilentConfig
+) -> Result<(FrameType, Vec<u8>, usize), DynamicFramingError> {
+    parse_frame(generator, data, config).map(|(frame_type, _, payload, consumed)| (frame_type, payload, consumed))
+}
+
+/// Parse a single frame (see `parse_typed_frame`)
+///
+/// Returns: (Frame Type, Continuation Flag, Decrypted Payload, Total Bytes Consumed)
+fn parse_frame(
+    generator: &mut SaltGenerator,
+    data: &[u8],
+    config: SilentConfig
+) -> Result<(FrameType, bool, Vec<u8>, usize), DynamicFramingError> {
+    let located = locate_frame(generator, data, config)?;
+    let total_frame_size = located.total_frame_size;
     
-    let do_rekey = config.enable_double_ratchet && current_seq > 0 && (current_seq % config.ratchet_interval == 0);
-    if do_rekey { header_size += 32; }
+    // Copy the frame once and decrypt the copy in place
+    let mut frame = data[..total_frame_size].to_vec();
+    let (frame_type, more, payload) = open_located(generator, located, &mut frame, config)?;
+    frame.truncate(payload.end);
+    frame.drain(..payload.start);
+    Ok((frame_type, more, frame, total_frame_size))
+}
+
+/// Parse a frame by decrypting it where it lies (zero-copy).
+///
+/// Like `parse_typed_frame`, but the returned payload borrows from `data`.
+/// The frame's bytes in `data` are overwritten, also when opening fails, so
+/// a frame cannot be parsed twice from the same buffer.
+///
+/// Returns: (Frame Type, Payload, Total Bytes Consumed)
+pub fn open_frame_in_place<'a>(
+    generator: &mut SaltGenerator,
+    data: &'a mut [u8],
+    config: SilentConfig
+) -> Result<(FrameType, &'a [u8], usize), DynamicFramingError> {
+    let (frame_type, _, payload, consumed) = open_frame(generator, data, config)?;
+    Ok((frame_type, &data[payload], consumed))
+}
+
+/// Parse a single frame in place
+///
+/// Returns: (Frame Type, Continuation Flag, Payload Range in `data`, Total Bytes Consumed)
+fn open_frame(
+    generator: &mut SaltGenerator,
+    data: &mut [u8],
+    config: SilentConfig
+) -> Result<(FrameType, bool, Range<usize>, usize), DynamicFramingError> {
+    let located = locate_frame(generator, data, config)?;
+    let total_frame_size = located.total_frame_size;
+    let (frame_type, more, payload) = open_located(generator, located, &mut data[..total_frame_size], config)?;
+    Ok((frame_type, more, payload, total_frame_size))
+}
+
+/// A complete frame found at the start of a buffer, not authenticated yet
+struct LocatedFrame {
+    frame_seq: u64,
+    epoch_id: u8,
+    do_rekey: bool,
+    /// Unmasked Ratchet Block (empty without rekey)
+    block: Vec<u8>,
+    header_size: usize,
+    total_frame_size: usize,
+    /// Epoch opened by this frame's Ratchet Block, installed once the frame is genuine
+    opened_epoch: Option<EpochState>,
+    salt: Secret,
+}
+
+/// Read the header of the frame at the start of `data` and pick its keys.
+/// Only peeks at the generator: state changes wait for `open_located`.
+fn locate_frame(
+    generator: &mut SaltGenerator,
+    data: &[u8],
+    config: SilentConfig
+) -> Result<LocatedFrame, DynamicFramingError> {
+    // Fixed header size depends on config:
+    // [Length (4B)] [Version (1B)] [Hint (2B, Optional)] [Epoch (1B, with double ratchet)]
+    let mut header_size = 4 + 1;
+    if config.enable_sequence_hint { header_size += 2; }
+    let epoch_offset = header_size;
+    if config.enable_double_ratchet { header_size += 1; }
     
     if data.len() < header_size {
         return Err(DynamicFramingError::IncompleteData);
     }
-
-    // 1. Generate Salt (Peek next state, don't advance yet if we fail? 
-    // Actually simplicity: we assume we are trying to parse the *next* expected frame.
-    // If we fail, the stream might be desynced, which is fatal for this protocol phase.
-    // So we generate the salt.
-    // 1. Generate Salt (Peek / Speculate)
-    // If Sequence Hint is enabled, we first try to recover the correct sequence.
     
-    // Helper to extract fields from salt
-    fn derive_keys(salt: [u8; 32]) -> ([u8; 32], [u8; 12], u32, u16) {
-         let key_bytes: [u8; 32] = salt;
-         let nonce_bytes: [u8; 12] = salt[0..12].try_into().unwrap();
-         let mask_bytes: [u8; 4] = salt[12..16].try_into().unwrap();
-         let mask = u32::from_be_bytes(mask_bytes);
-         let hint_mask = u16::from_be_bytes(salt[16..18].try_into().unwrap());
-         (key_bytes, nonce_bytes, mask, hint_mask)
-    }
-
+    // 1. Find the sequence of this frame.
+    // All header masks come from the header key, not from the epoch keys, so the
+    // header can be read even if a rekey happened in between.
     let current_seq = generator.sequence;
+    let mut frame_seq = current_seq;
     
     // Strategy:
     // If Hint is enabled:
     //   Calculate what Hint we expect for current_seq.
     //   Compare with received Hint.
     //   If mismatch, check if (current_seq + delta) matches received Hint.
-    //   We only look forward (e.g., up to 1000 frames) to avoid replay attacks or excessive CPU.
-    
-    let mut salt = generator.next_salt(); // Temporarily advance
-    let (_, _, mask, hint_mask) = derive_keys(salt);
-    
+    //   We look forward up to `resync_window` frames, then back up to `replay_window`
+    //   frames for late (reordered) frames. The expected hints are precomputed
+    //   (see `SaltGenerator::find_hint`) and searches are rate limited.
     if config.enable_sequence_hint {
-        let received_hint_bytes: [u8; 2] = data[4..6].try_into().unwrap();
+        let received_hint_bytes: [u8; 2] = data[5..7].try_into().unwrap();
         let received_hint = u16::from_be_bytes(received_hint_bytes);
-        
-        let expected_hint = (current_seq as u16) ^ hint_mask;
-        
+        frame_seq = generator.find_hint(received_hint, config)?;
+    }
+    
+    let masks = generator.header_masks(frame_seq, config);
+    
+    // Format version (also covered by the AAD below)
+    let version = data[4] ^ masks.version;
+    if version != FRAME_FORMAT_VERSION {
+        return Err(DynamicFramingError::UnsupportedVersion(version));
+    }
+    
+    // 2. Ratchet Block
+    // Whether it is present depends on the frame's own sequence, so a resync that
+    // lands on a rekey frame (or jumps over one) still gets the header size right.
+    let do_rekey = is_rekey_slot(config, frame_seq);
+    let ratchet_offset = header_size;
+    if do_rekey {
+        // The Ratchet Block announces its own size (offer only, or offer + answer)
+        if data.len() <= ratchet_offset {
+            return Err(DynamicFramingError::IncompleteData);
+        }
+        header_size += ratchet_block_len(data[ratchet_offset])?;
+        if data.len() < header_size {
+            return Err(DynamicFramingError::IncompleteData);
+        }
+    }
+    
+    // 3. De-obfuscate Length
+    let obfuscated_len_bytes: [u8; 4] = data[0..4].try_into().unwrap();
+    let obfuscated_len = u32::from_be_bytes(obfuscated_len_bytes);
+    let encrypted_len = (obfuscated_len ^ masks.length) as usize;
+    
+    // Max frame size sanity check (e.g. 10MB)
+    if encrypted_len > 10 * 1024 * 1024 {
+         return Err(DynamicFramingError::InvalidLength(encrypted_len));
+    }
+    
+    let total_frame_size = header_size + encrypted_len;
+    if data.len() < total_frame_size {
+        // We only peeked at the generator, so there is nothing to revert:
+        // the caller retries once more data has arrived.
+        return Err(DynamicFramingError::IncompleteData);
+    }
+    
+    // Anti-replay: a sequence is accepted at most once (recorded after authentication)
+    if !generator.replay.check(frame_seq, config.replay_window) {
+        return Err(DynamicFramingError::Replay { seq: frame_seq, frame_len: total_frame_size });
+    }
+    
+    // 4. Pick the epoch keys
+    let epoch_id = if config.enable_double_ratchet {
+        data[epoch_offset] ^ masks.epoch
+    } else {
+        generator.epoch() as u8
+    };
+    
+    let mut block = data[ratchet_offset..header_size].to_vec();
+    if do_rekey {
+        mask_epoch_start(&mut block, masks);
+    }
+    
+    let mut opened_epoch = None;
+    let salt = if let Some(epoch) = generator.epoch_state(epoch_id) {
+        epoch.salt_for(frame_seq)
+    } else if do_rekey && block[0] & RATCHET_ANSWER != 0 && epoch_id == generator.epoch().wrapping_add(1) as u8 {
+        // We missed the frame that opened this epoch. The peer repeats its answer in
+        // every rekey frame until we offer again, so open the epoch from this one;
+        // the AEAD tag tells whether the block was genuine, so only preview it here
+        // (`open_located` accepts it once the frame has been authenticated).
+        let ratchet = generator.ratchet_handle();
+        let secret = ratchet.lock().unwrap().preview_block(&block)?
+            .ok_or(DynamicFramingError::DecryptionError)?;
+        let start = u64::from_be_bytes(block[65..73].try_into().unwrap());
+        if start > frame_seq {
+            return Err(DynamicFramingError::DecryptionError);
+        }
+        let epoch = generator.epoch.successor(&secret[..], start)
+            .ok_or(DynamicFramingError::DecryptionError)?;
+        let salt = epoch.salt_for(frame_seq);
+        opened_epoch = Some(epoch);
+        salt
+    } else {
+        // Cannot be opened (yet), but its length is known so the stream can move past it
+        return Err(DynamicFramingError::UnknownEpoch { epoch: epoch_id, frame_len: total_frame_size });
+    };
+    let salt = salt.ok_or(DynamicFramingError::DecryptionError)?;
+    
+    Ok(LocatedFrame { frame_seq, epoch_id, do_rekey, block, header_size, total_frame_size, opened_epoch, salt })
+}
 
-        if received_hint != expected_hint {
-            // Desync detected! Search forward.
-            let search_window = 1000;
-            let mut found_sync = None;
-            
-            for offset in 1..=search_window {
-                let check_seq = current_seq + offset;
-                // Important: If we skip frames, we might skip rekey events!
-                // If we skip a rekey event, our Seed calculation for `check_seq` will be WRONG 
-                // because we missed the `mix_entropy` update.
-                // This implies Robust Mode + Rekeying is complex: 
-                // You cannot easily jump over a Rekey frame without the rekey payload.
-                // Limitation: If we lose a Rekey Frame, we might be permanently desynced until full reset?
-                // Or we need to try to detect if `check_seq` implies we missed a rekey.
-                // For this "Simple" implementation, we will assume we don't jump OVER a rekey frame 
-                // OR that `get_salt_for_sequence` generates based on the *current* seed (ignoring missed rekeys).
-                // This means future salts are valid only if seed hasn't changed.
-                // If seed changed (missed rekey), we can't recover.
-                // This is a trade-off. We accept it for now.
-                
-                let check_salt = generator.get_salt_for_sequence(check_seq);
-                // ... (rest of logic same)
-                let (_, _, _, check_hint_mask) = derive_keys(check_salt);
-                let check_hint = (check_seq as u16) ^ check_hint_mask;
-                
-                if received_hint == check_hint {
-                    found_sync = Some((check_seq, check_salt));
-                    break;
-                }
-            }
+/// Decrypt a located frame in place (`frame` is exactly the frame) and advance the generator.
+///
+/// Returns: (Frame Type, Continuation Flag, Payload Range in `frame`)
+fn open_located(
+    generator: &mut SaltGenerator,
+    located: LocatedFrame,
+    frame: &mut [u8],
+    config: SilentConfig
+) -> Result<(FrameType, bool, Range<usize>), DynamicFramingError> {
+    let LocatedFrame { frame_seq, epoch_id, do_rekey, block, header_size, opened_epoch, salt, .. } = located;
+    
+    // 5. Decrypt
+    let (key, nonce) = frame_key(config.cipher_suite, &salt)
+        .map_err(|_| DynamicFramingError::DecryptionError)?;
+    
+    // The header was authenticated as AAD: flipped length/hint/epoch/ratchet bits fail here
+    let (header, body) = frame.split_at_mut(header_size);
+    let aad = frame_aad(generator.context_id(), frame_seq, header);
+    let decrypted_data = key.open_in_place(nonce, Aad::from(aad.as_slice()), body)
+        .map_err(|_| DynamicFramingError::DecryptionError)?;
+    
+    let (frame_type, more, payload_end) = read_body(decrypted_data)?;
+    
+    // 6. The frame is genuine: advance past it (and erase its key in chain-key mode)
+    if let Some(epoch) = opened_epoch {
+        // The epoch was opened from a previewed Ratchet Block: commit it now
+        // (the DH output is the one the epoch was derived from)
+        generator.ratchet_handle().lock().unwrap().accept_block(&block)?;
+        generator.install_epoch(epoch);
+        generator.consume_salt(epoch_id, frame_seq);
+    } else {
+        generator.consume_salt(epoch_id, frame_seq);
+        
+        // Process the Ratchet Block only once the frame is known to be genuine,
+        // so garbage cannot consume our outstanding offer.
+        if do_rekey && epoch_id == generator.epoch() as u8 {
+            let ratchet = generator.ratchet_handle();
+            let secret = ratchet.lock().unwrap().accept_block(&block)?;
             
-            if let Some((new_seq, new_salt)) = found_sync {
-                // Adjust
-                generator.set_sequence(new_seq + 1);
-                salt = new_salt; // Update salt
-                
-                // Correction: If we jumped, we might have skipped rekey logic.
-                // But for THIS frame, if it is a rekey frame, we need to handle it below.
-                // We established `do_rekey` based on `current_seq` (old). 
-                // If we jumped `new_seq`, does this frame contain rekey data?
-                // We need to re-evaluate `do_rekey` based on `new_seq`.
-                let new_do_rekey = config.enable_double_ratchet && new_seq > 0 && (new_seq % config.ratchet_interval == 0);
-                if new_do_rekey != do_rekey {
-                    // Header size mismatch!
-                     return Err(DynamicFramingError::DecryptionError); // Complex resync failed
-                }
-            } else {
-                 generator.set_sequence(current_seq);
-                 return Err(DynamicFramingError::DecryptionError); 
+            // The sender mixes its answer in after sealing this frame, so the
+            // new epoch starts at the EpochStart it announced, not at this frame.
+            if let Some(secret) = secret {
+                let start = u64::from_be_bytes(block[65..73].try_into().unwrap());
+                let epoch = generator.epoch.successor(&secret[..], start)
+                    .ok_or(DynamicFramingError::DecryptionError)?;
+                generator.install_epoch(epoch);
             }
         }
     }
     
-    // Extract Rekey Entropy if needed
-    if do_rekey {
-        // Entropy is after Hint (if any).
-        let entropy_offset = if config.enable_sequence_hint { 6 } else { 4 };
-        let entropy: [u8; 32] = data[entropy_offset..entropy_offset+32].try_into().unwrap();
-        
-        // Mix into generator
-        generator.mix_entropy(&entropy);
-        
-        // Note: We mixed entropy. usage of `salt` (derived from OLD seed) is still valid for THIS frame?
-        // Protocol decision: 
-        // Option A: Rekey payload allows decrypting THIS frame with NEW key?
-        // Option B: Rekey payload prepares for NEXT frame. THIS frame uses OLD key.
-        // `build` did: `salt = next_salt()` (OLD seed) -> Encrypt -> `mix_entropy()` (NEW seed).
-        // So THIS frame is encrypted with OLD seed. Correct.
+    // 7. Return
+    Ok((frame_type, more, header_size + FRAME_TYPE_LEN..header_size + payload_end))
+}
+
+/// Strip the padding of a decrypted body: [Type (1B)] [Data] [Padding] [PaddingLen (4B)]
+///
+/// Returns: (Frame Type, Continuation Flag, End of the Data in `body`)
+fn read_body(body: &[u8]) -> Result<(FrameType, bool, usize), DynamicFramingError> {
+    let body_len = body.len();
+    if body_len < FRAME_TYPE_LEN + PADDING_TRAILER_LEN {
+        return Err(DynamicFramingError::InvalidLength(body_len));
+    }
+    let trailer: [u8; 4] = body[body_len - PADDING_TRAILER_LEN..].try_into().unwrap();
+    let padding_len = u32::from_be_bytes(trailer) as usize;
+    if padding_len > body_len - FRAME_TYPE_LEN - PADDING_TRAILER_LEN {
+        return Err(DynamicFramingError::InvalidLength(padding_len));
+    }
+    let type_byte = body[0];
+    let frame_type = FrameType::from_id(type_byte & !FRAME_MORE_FLAG)
+        .ok_or(DynamicFramingError::UnknownFrameType(type_byte))?;
+    Ok((frame_type, type_byte & FRAME_MORE_FLAG != 0, body_len - PADDING_TRAILER_LEN - padding_len))
+}
+
+/// Header of a datagram frame: [Sequence (4B, low 32 bits)] [Version (1B)]
+const DATAGRAM_HEADER_LEN: usize = 4 + 1;
+
+/// Bytes at the start of the encrypted body sampled for the datagram header mask
+const DATAGRAM_SAMPLE_LEN: usize = 16;
+
+/// Context ID of the generators used for datagram frames (see `SaltGenerator::new_directional`).
+/// QUIC stream IDs stay below 2^62, so it never collides with a stream's.
+pub const DATAGRAM_CONTEXT_ID: u64 = u64::MAX;
+
+/// Generator of one direction of a stream, as both peers set it up: chain-key mode,
+/// with the DH ratchet that `ratchets` holds for the stream (created on first use).
+///
+/// The receiving generator of a stream and the sending one of its replies must come
+/// from the same `ratchets` map, so offers received in one direction are answered in the other.
+pub fn stream_generator(
+    ratchets: &mut HashMap<u64, RatchetHandle>,
+    session_seed: &[u8; 32],
+    stream_id: u64,
+    direction: Direction,
+) -> SaltGenerator {
+    let mut generator = SaltGenerator::new_directional(session_seed, stream_id, direction);
+    generator.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+    let ratchet = ratchets.entry(stream_id).or_insert_with(DhRatchet::new_shared);
+    generator.attach_ratchet(ratchet.clone());
+    generator
+}
+
+/// (Sending, receiving) datagram generators of `role`, in chain-key mode like the streams'.
+/// Keys of datagrams that arrive out of order stay in the skipped-key cache.
+pub fn datagram_generators(session_seed: &[u8; 32], role: Role) -> (SaltGenerator, SaltGenerator) {
+    let generator = |direction| {
+        let mut generator = SaltGenerator::new_directional(session_seed, DATAGRAM_CONTEXT_ID, direction);
+        generator.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+        generator
+    };
+    (generator(role.sending()), generator(role.receiving()))
+}
+
+/// Build a self-contained frame for one QUIC DATAGRAM (see `parse_datagram_frame`)
+///
+/// `generator` must be the sending generator of this direction for `DATAGRAM_CONTEXT_ID`,
+/// never used for stream frames. Padding applies as for stream frames (in cell mode the
+/// payload must fit into one cell); datagram frames never carry a Ratchet Block.
+///
+/// Frame: [MaskedSequence (4B)] [MaskedVersion (1B)] [EncryptedData]
+pub fn build_datagram_frame(
+    generator: &mut SaltGenerator,
+    frame_type: FrameType,
+    payload: &[u8],
+    config: SilentConfig
+) -> Result<Vec<u8>, DynamicFramingError> {
+    let config = match config.cell_size {
+        Some(cell_size) => cell_config(cell_size, payload.len(), config)?,
+        None => config,
+    };
+    let data_len = FRAME_TYPE_LEN + payload.len();
+    let padding_len = config.padding.padding_len(data_len + PADDING_TRAILER_LEN + TAG_LEN)?;
+    if padding_len > u32::MAX as usize {
+        return Err(DynamicFramingError::InvalidLength(padding_len));
     }
     
-    let (key_bytes, nonce_bytes, mask, _) = derive_keys(salt);
+    let salt = generator.next_salt();
+    let sequence = generator.sequence - 1;
+    let (key, nonce) = frame_key(config.cipher_suite, &salt)?;
     
-    // 2. De-obfuscate Length
-    let obfuscated_len_bytes: [u8; 4] = data[0..4].try_into().unwrap();
-    let obfuscated_len = u32::from_be_bytes(obfuscated_len_bytes);
-    let encrypted_len = (obfuscated_len ^ mask) as usize;
+    // The AAD binds the full sequence and the unmasked header
+    let mut frame = Vec::with_capacity(DATAGRAM_HEADER_LEN + data_len + padding_len + PADDING_TRAILER_LEN + TAG_LEN);
+    frame.extend_from_slice(&(sequence as u32).to_be_bytes());
+    frame.push(FRAME_FORMAT_VERSION);
+    let aad = frame_aad(generator.context_id(), sequence, &frame);
+    frame.push(frame_type.id());
+    frame.extend_from_slice(payload);
+    frame.resize(frame.len() + padding_len, 0);
+    frame.extend_from_slice(&(padding_len as u32).to_be_bytes());
+    let tag = key.seal_in_place_separate_tag(nonce, Aad::from(aad.as_slice()), &mut frame[DATAGRAM_HEADER_LEN..])
+        .map_err(|_| DynamicFramingError::EncryptionError)?;
+    frame.extend_from_slice(tag.as_ref());
     
-    // 3. Check bounds
-    // Max frame size sanity check (e.g. 10MB)
-    if encrypted_len > 10 * 1024 * 1024 {
-         return Err(DynamicFramingError::InvalidLength(encrypted_len));
+    let mask = generator.datagram_mask(&frame[DATAGRAM_HEADER_LEN..DATAGRAM_HEADER_LEN + DATAGRAM_SAMPLE_LEN]);
+    frame.iter_mut().zip(mask).for_each(|(byte, mask)| *byte ^= mask);
+    Ok(frame)
+}
+
+/// Open a frame built by `build_datagram_frame`, whatever order datagrams arrive in.
+///
+/// The sequence is restored from its low 32 bits as the one closest to the receive
+/// position. Duplicates and datagrams more than `replay_window` frames late are rejected
+/// as `Replay`. In chain-key mode the keys of datagrams not received yet wait in the
+/// skipped-key cache, and a datagram may be up to `resync_window` frames ahead of the
+/// chain; jumps past `max_skipped` count against `max_resyncs_per_second`.
+///
+/// Returns: (Frame Type, Decrypted Payload)
+pub fn parse_datagram_frame(
+    generator: &mut SaltGenerator,
+    datagram: &[u8],
+    config: SilentConfig
+) -> Result<(FrameType, Vec<u8>), DynamicFramingError> {
+    if datagram.len() < DATAGRAM_HEADER_LEN + FRAME_TYPE_LEN + PADDING_TRAILER_LEN + TAG_LEN {
+        return Err(DynamicFramingError::InvalidLength(datagram.len()));
     }
+    let mut frame = datagram.to_vec();
+    let mask = generator.datagram_mask(&frame[DATAGRAM_HEADER_LEN..DATAGRAM_HEADER_LEN + DATAGRAM_SAMPLE_LEN]);
+    frame.iter_mut().zip(mask).for_each(|(byte, mask)| *byte ^= mask);
     
-    let total_frame_size = header_size + encrypted_len;
-    if data.len() < total_frame_size {
-        // We need to revert the generator state because we didn't consume the message?
-        // Or caller should ensure they have enough data? 
-        // For a stream parser, we usually need to know "length needed".
-        // But here we just calculated the length needed!
-        // So we return IncompleteData with enough info?
-        // Let's rely on the caller handling "IncompleteData" by NOT advancing generator?
-        // Wait, we ALREADY advanced generator inside next_salt().
-        // We need a way to peek or revert.
-        // Let's revert manually:
-        generator.sequence -= 1;
-        return Err(DynamicFramingError::IncompleteData);
+    let version = frame[4];
+    if version != FRAME_FORMAT_VERSION {
+        return Err(DynamicFramingError::UnsupportedVersion(version));
+    }
+    let truncated = u32::from_be_bytes(frame[..4].try_into().unwrap());
+    let frame_seq = expand_datagram_sequence(generator.sequence, truncated);
+    if !generator.replay.check(frame_seq, config.replay_window) {
+        return Err(DynamicFramingError::Replay { seq: frame_seq, frame_len: datagram.len() });
     }
     
-    // 4. Decrypt
-    let unbound_key = UnboundKey::new(&aead::CHACHA20_POLY1305, &key_bytes)
+    // Every frame a chain jumps over costs a key derivation, so far jumps are rate limited
+    let epoch_id = generator.epoch() as u8;
+    let mut max_ahead = config.resync_window.min(MAX_RESYNC_WINDOW);
+    if let Some(chain) = &generator.epoch.chain {
+        let max_skipped = chain.max_skipped as u64;
+        if frame_seq.saturating_sub(chain.index) > max_skipped && !generator.resyncs.allow(config.max_resyncs_per_second) {
+            return Err(DynamicFramingError::ResyncRateLimited);
+        }
+        max_ahead = max_ahead.max(max_skipped);
+    }
+    let salt = generator.epoch.salt_within(frame_seq, max_ahead)
+        .ok_or(DynamicFramingError::DecryptionError)?;
+    let (key, nonce) = frame_key(config.cipher_suite, &salt)
         .map_err(|_| DynamicFramingError::DecryptionError)?;
-    let key = LessSafeKey::new(unbound_key);
-    let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
     
-    // Make a copy to decrypt in place (or modify input if signature allowed, but here we take slice)
-    let mut buffer = data[header_size..total_frame_size].to_vec();
-    
-    let decrypted_data = key.open_in_place(nonce, Aad::empty(), &mut buffer)
+    let (header, body) = frame.split_at_mut(DATAGRAM_HEADER_LEN);
+    let aad = frame_aad(generator.context_id(), frame_seq, header);
+    let decrypted = key.open_in_place(nonce, Aad::from(aad.as_slice()), body)
         .map_err(|_| DynamicFramingError::DecryptionError)?;
-        
-    // 5. Return
-    Ok((decrypted_data.to_vec(), total_frame_size))
+    let (frame_type, _, payload_end) = read_body(decrypted)?;
+    
+    // The datagram is genuine: record it (and erase its key in chain-key mode)
+    generator.consume_salt(epoch_id, frame_seq);
+    frame.truncate(DATAGRAM_HEADER_LEN + payload_end);
+    frame.drain(..DATAGRAM_HEADER_LEN + FRAME_TYPE_LEN);
+    Ok((frame_type, frame))
+}
+
+/// Full sequence whose low 32 bits are `truncated`, taking the candidate closest
+/// to `expected` (packet number decoding of RFC 9000, Appendix A.3)
+fn expand_datagram_sequence(expected: u64, truncated: u32) -> u64 {
+    const WINDOW: u64 = 1 << 32;
+    let candidate = (expected & !(WINDOW - 1)) | truncated as u64;
+    if candidate.saturating_add(WINDOW / 2) <= expected && candidate <= u64::MAX - WINDOW {
+        candidate + WINDOW
+    } else if candidate > expected.saturating_add(WINDOW / 2) && candidate >= WINDOW {
+        candidate - WINDOW
+    } else {
+        candidate
+    }
 }
 
 #[cfg(test)]
@@ -524,12 +2855,12 @@ mod tests {
 
     #[test]
     fn test_salt_rotation() {
-        let mut generator = SaltGenerator::new([0u8; 32]);
+        let mut generator = SaltGenerator::new(&[0u8; 32]);
         let s1 = generator.next_salt();
         let s2 = generator.next_salt();
         assert_ne!(s1, s2);
         
-        let mut gen2 = SaltGenerator::new([0u8; 32]);
+        let mut gen2 = SaltGenerator::new(&[0u8; 32]);
         let s1_prime = gen2.next_salt();
         assert_eq!(s1, s1_prime);
     }
@@ -537,8 +2868,8 @@ mod tests {
     #[test]
     fn test_frame_roundtrip() {
         let seed = [1u8; 32];
-        let mut sender_gen = SaltGenerator::new(seed);
-        let mut receiver_gen = SaltGenerator::new(seed);
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
         
         let payload = b"Hello, Dynamic World!";
         
@@ -553,11 +2884,412 @@ mod tests {
         assert_eq!(decoded, payload);
     }
     
+    #[test]
+    fn test_cipher_suites() {
+        let seed = [8u8; 32];
+        let payload = b"suite agnostic";
+        
+        for suite in [CipherSuite::ChaCha20Poly1305, CipherSuite::Aes256Gcm] {
+            assert_eq!(CipherSuite::from_id(suite.id()), Some(suite));
+            let config = SilentConfig { cipher_suite: suite, ..SilentConfig::default() };
+            
+            let mut sender_gen = SaltGenerator::new(&seed);
+            let mut receiver_gen = SaltGenerator::new(&seed);
+            let frame = build_dynamic_frame(&mut sender_gen, payload, config).unwrap();
+            let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frame, config).unwrap();
+            assert_eq!(decoded, payload);
+        }
+        assert_eq!(CipherSuite::from_id(0), None);
+        
+        // Peers that disagree on the suite cannot read each other
+        let chacha = SilentConfig::default();
+        let aes = SilentConfig { cipher_suite: CipherSuite::Aes256Gcm, ..chacha };
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        let frame = build_dynamic_frame(&mut sender_gen, payload, chacha).unwrap();
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frame, aes).is_err());
+        
+        // The salt itself is no longer the key or the nonce
+        let salt = SaltGenerator::new(&seed).get_salt_for_sequence(0).unwrap();
+        let (_, nonce) = frame_key(CipherSuite::ChaCha20Poly1305, &salt).unwrap();
+        assert_ne!(nonce.as_ref(), &salt[0..12]);
+    }
+
+    #[test]
+    fn test_header_protection() {
+        let seed = [12u8; 32];
+        let protected = SilentConfig { enable_header_protection: true, ..SilentConfig::default() };
+        
+        // Same seed and payload: only the header encryption differs
+        let mut plain_gen = SaltGenerator::new(&seed);
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let plain = build_dynamic_frame(&mut plain_gen, b"hidden length", SilentConfig::default()).unwrap();
+        let frame = build_dynamic_frame(&mut sender_gen, b"hidden length", protected).unwrap();
+        assert_eq!(plain.len(), frame.len());
+        assert_ne!(plain[..7], frame[..7]);
+        
+        // Supported by the stream parser, across split deliveries
+        let second = build_dynamic_frame(&mut sender_gen, b"second", protected).unwrap();
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.append_data(&frame[..3]).unwrap();
+        assert!(parser.try_parse_next(protected).unwrap().is_none());
+        parser.append_data(&frame[3..]).unwrap();
+        parser.append_data(&second).unwrap();
+        assert_eq!(parser.try_parse_next(protected).unwrap().unwrap(), b"hidden length");
+        assert_eq!(parser.try_parse_next(protected).unwrap().unwrap(), b"second");
+        
+        // A receiver without header protection cannot even find the frame
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frame, SilentConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let seed = [13u8; 32];
+        let mut config = SilentConfig::default();
+        config.replay_window = 4;
+        
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let frames: Vec<_> = (0..8)
+            .map(|i| build_dynamic_frame(&mut sender_gen, format!("frame {}", i).as_bytes(), config).unwrap())
+            .collect();
+        
+        // Reordered delivery inside the window is fine, in either direction
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        for i in [2, 0, 1, 5, 3] {
+            let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[i], config).unwrap();
+            assert_eq!(decoded, format!("frame {}", i).as_bytes());
+        }
+        
+        // Duplicates are rejected before decryption
+        for i in [2, 3, 5] {
+            let result = parse_dynamic_frame(&mut receiver_gen, &frames[i], config);
+            assert!(matches!(result, Err(DynamicFramingError::Replay { seq, .. }) if seq == i as u64));
+        }
+        // Frames behind the window are not even looked up
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frames[0], config).is_err());
+        
+        // Jumping ahead slides the window; frame 4 (7 - 4 < 4) is still accepted late
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[7], config).unwrap();
+        assert_eq!(decoded, b"frame 7");
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[4], config).unwrap();
+        assert_eq!(decoded, b"frame 4");
+        
+        // The stream parser skips a replayed frame and keeps going
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let first = build_dynamic_frame(&mut sender_gen, b"first", config).unwrap();
+        let second = build_dynamic_frame(&mut sender_gen, b"second", config).unwrap();
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        for frame in [&first, &first, &second] {
+            parser.append_data(frame).unwrap();
+        }
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"first");
+        assert!(matches!(parser.try_parse_next(config), Err(DynamicFramingError::Replay { seq: 0, .. })));
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_resync_cache_and_rate_limit() {
+        let seed = [14u8; 32];
+        let mut config = SilentConfig::default();
+        config.resync_window = 16;
+        config.replay_window = 8;
+        config.max_resyncs_per_second = 2;
+        
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let frames: Vec<_> = (0..40)
+            .map(|_| build_dynamic_frame(&mut sender_gen, b"x", config).unwrap())
+            .collect();
+        
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        parse_dynamic_frame(&mut receiver_gen, &frames[0], config).unwrap();
+        // The cache covered the expected sequence 0 and 16 frames ahead (nothing behind yet)
+        assert_eq!(receiver_gen.hint_cache.start, 0);
+        assert_eq!(receiver_gen.hint_cache.hints.len(), 1 + 16);
+        
+        // Two jumps are allowed within a second...
+        parse_dynamic_frame(&mut receiver_gen, &frames[5], config).unwrap();
+        parse_dynamic_frame(&mut receiver_gen, &frames[20], config).unwrap();
+        // ...in-order frames are not resyncs...
+        parse_dynamic_frame(&mut receiver_gen, &frames[21], config).unwrap();
+        // ...but the third jump is refused, leaving the receiver where it was
+        let result = parse_dynamic_frame(&mut receiver_gen, &frames[30], config);
+        assert!(matches!(result, Err(DynamicFramingError::ResyncRateLimited)));
+        assert_eq!(receiver_gen.sequence(), 22);
+        assert_eq!(receiver_gen.hint_cache.start, 22 - 8);
+        
+        // Beyond `resync_window` a frame cannot be located at all
+        let mut unlimited = SilentConfig { max_resyncs_per_second: 0, ..config };
+        unlimited.resync_window = 4;
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frames[10], unlimited).is_err());
+        parse_dynamic_frame(&mut receiver_gen, &frames[4], unlimited).unwrap();
+        
+        // A stream parser keeps a rate limited frame buffered for the next attempt
+        let single = SilentConfig { max_resyncs_per_second: 1, ..config };
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.append_data(&frames[3]).unwrap();
+        assert!(parser.try_parse_next(single).unwrap().is_some());
+        parser.append_data(&frames[10]).unwrap();
+        assert!(matches!(parser.try_parse_next(single), Err(DynamicFramingError::ResyncRateLimited)));
+        assert_eq!(parser.buffer_size(), frames[10].len());
+        let retry = SilentConfig { max_resyncs_per_second: 0, ..config };
+        assert_eq!(parser.try_parse_next(retry).unwrap().unwrap(), b"x");
+    }
+
+    #[test]
+    fn test_frame_types() {
+        let seed = [15u8; 32];
+        let config = SilentConfig::default();
+        let mut sender_gen = SaltGenerator::new(&seed);
+        
+        // Chaff and data of the same size are indistinguishable on the wire
+        let chaff = build_typed_frame(&mut sender_gen, FrameType::Chaff, b"0123456789", config).unwrap();
+        let data = build_dynamic_frame(&mut sender_gen, b"real data!", config).unwrap();
+        assert_eq!(chaff.len(), data.len());
+        let ping = build_typed_frame(&mut sender_gen, FrameType::Ping, b"probe", config).unwrap();
+        let rekey = build_typed_frame(&mut sender_gen, FrameType::Rekey, b"", config).unwrap();
+        let last = build_dynamic_frame(&mut sender_gen, b"last", config).unwrap();
+        
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        let (frame_type, payload, _) = parse_typed_frame(&mut receiver_gen, &chaff, config).unwrap();
+        assert_eq!((frame_type, payload.as_slice()), (FrameType::Chaff, &b"0123456789"[..]));
+        
+        // The stream parser drops chaff and routes control frames to the handler
+        let controls = Arc::new(Mutex::new(Vec::new()));
+        let seen = controls.clone();
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.set_control_handler(move |frame_type, payload| {
+            seen.lock().unwrap().push((frame_type, payload.to_vec()));
+        });
+        for frame in [&chaff, &data, &ping, &chaff, &rekey, &last] {
+            parser.append_data(frame).unwrap();
+        }
+        
+        // (the second chaff copy is a replay of seq 0)
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"real data!");
+        assert!(matches!(parser.try_parse_next(config), Err(DynamicFramingError::Replay { seq: 0, .. })));
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"last");
+        assert!(parser.try_parse_next(config).unwrap().is_none());
+        assert_eq!(*controls.lock().unwrap(), vec![(FrameType::Ping, b"probe".to_vec()), (FrameType::Rekey, Vec::new())]);
+    }
+
+    #[test]
+    fn test_in_place_apis() {
+        let seed = [42u8; 32];
+        let config = SilentConfig::default();
+        let payload = b"sealed where it lies";
+        let expected = build_typed_frame(&mut SaltGenerator::new(&seed), FrameType::Data, payload, config).unwrap();
+        
+        // Appending to a buffer and sealing around a reserved header give the same frame
+        let mut out = b"prefix".to_vec();
+        let written = build_frame_into(&mut SaltGenerator::new(&seed), FrameType::Data, payload, config, &mut out).unwrap();
+        assert_eq!((&out[..6], &out[6..], written), (&b"prefix"[..], &expected[..], expected.len()));
+        
+        let mut buf = vec![0u8; FRAME_HEADROOM];
+        buf.extend_from_slice(payload);
+        let start = seal_frame_in_place(&mut SaltGenerator::new(&seed), FrameType::Data, &mut buf, config).unwrap();
+        assert_eq!(&buf[start..], &expected[..]);
+        
+        // Opening returns a slice of the received bytes
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        let mut received = expected.clone();
+        let (frame_type, opened, consumed) = open_frame_in_place(&mut receiver_gen, &mut received, config).unwrap();
+        assert_eq!((frame_type, opened, consumed), (FrameType::Data, &payload[..], expected.len()));
+        
+        // The parser works through many frames fed in small pieces, compacting as it goes
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut wire = Vec::new();
+        for i in 0..200u32 {
+            build_frame_into(&mut sender_gen, FrameType::Data, &i.to_be_bytes(), config, &mut wire).unwrap();
+        }
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        let mut messages = Vec::new();
+        for chunk in wire.chunks(100) {
+            parser.append_data(chunk).unwrap();
+            while let Some(message) = parser.try_parse_next(config).unwrap() {
+                messages.push(u32::from_be_bytes(message.try_into().unwrap()));
+            }
+            assert!(parser.buffer.len() <= 2 * (parser.buffer_size() + 100));
+        }
+        assert_eq!(messages, (0..200).collect::<Vec<_>>());
+        assert_eq!(parser.buffer_size(), 0);
+        
+        // Cell mode cannot split a payload that is already in place
+        let cells = SilentConfig { cell_size: Some(64), ..config };
+        let mut buf = vec![0u8; FRAME_HEADROOM + 100];
+        assert!(seal_frame_in_place(&mut SaltGenerator::new(&seed), FrameType::Data, &mut buf, cells).is_err());
+    }
+    
+    #[test]
+    fn test_resync_scan() {
+        let seed = [5u8; 32];
+        let config = SilentConfig::default();
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let frames: Vec<Vec<u8>> = (0..4u8)
+            .map(|i| build_dynamic_frame(&mut sender_gen, &[i; 10], config).unwrap())
+            .collect();
+        
+        // A corrupted frame and inserted junk are skipped, the rest is parsed
+        let mut wire = frames[0].clone();
+        let mut corrupted = frames[1].clone();
+        corrupted[20] ^= 0xFF;
+        wire.extend_from_slice(&corrupted);
+        wire.extend_from_slice(&[0x5A; 37]);
+        wire.extend_from_slice(&frames[2]);
+        
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        let mut received = Vec::new();
+        // Bytes arrive in small pieces, so the scan also has to wait for data
+        for chunk in wire.chunks(9).chain([&frames[3][..]]) {
+            parser.append_data(chunk).unwrap();
+            while let Some(message) = parser.try_parse_next(config).unwrap() {
+                received.push(message[0]);
+            }
+        }
+        assert_eq!(received, vec![0, 2, 3]);
+        let stats = parser.resync_stats();
+        // One scan runs from the corrupted frame up to the next valid one
+        assert_eq!((stats.scans, stats.recovered, stats.failed), (1, 1, 0));
+        assert_eq!(stats.bytes_skipped, (frames[1].len() + 37) as u64);
+        
+        // Beyond the budget the scanned bytes are dropped, and later frames still parse
+        let strict = SilentConfig { resync_scan_budget: 16, ..config };
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.append_data(&[0x5A; 64]).unwrap();
+        assert!(matches!(parser.try_parse_next(strict), Err(DynamicFramingError::ResyncFailed { skipped: 64 })));
+        assert_eq!((parser.buffer_size(), parser.resync_stats().failed), (0, 1));
+        parser.append_data(&frames[0]).unwrap();
+        assert_eq!(parser.try_parse_next(strict).unwrap().unwrap(), [0; 10]);
+        
+        // Without a budget a failure drops the buffer, as before
+        let none = SilentConfig { resync_scan_budget: 0, ..config };
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.append_data(&corrupted).unwrap();
+        parser.append_data(&frames[2]).unwrap();
+        assert!(parser.try_parse_next(none).is_err());
+        assert_eq!(parser.buffer_size(), 0);
+    }
+    
+    #[test]
+    fn test_resync_after_corrupted_length() {
+        let seed = [6u8; 32];
+        let config = SilentConfig::default();
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let frames: Vec<Vec<u8>> = (0..12u8)
+            .map(|i| build_dynamic_frame(&mut sender_gen, &[i; 10], config).unwrap())
+            .collect();
+        
+        // Frame 1 claims 256 more bytes than it has: its body runs over the frames behind it
+        assert!(frames[1].len() < 256);
+        let mut corrupted = frames[1].clone();
+        corrupted[2] ^= 0x01;
+        let mut wire = frames[0].clone();
+        wire.extend_from_slice(&corrupted);
+        for frame in &frames[2..] {
+            wire.extend_from_slice(frame);
+        }
+        assert!(wire.len() > frames[0].len() + frames[1].len() + 256);
+        
+        // The failed open must not wipe them before the scan gets there
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        parser.append_data(&wire).unwrap();
+        let mut received = Vec::new();
+        while let Some(message) = parser.try_parse_next(config).unwrap() {
+            received.push(message[0]);
+        }
+        let expected: Vec<u8> = (0..12).filter(|&i| i != 1).collect();
+        assert_eq!(received, expected);
+        assert_eq!(parser.resync_stats().bytes_skipped, frames[1].len() as u64);
+    }
+    
+    #[test]
+    fn test_cell_mode() {
+        let seed = [16u8; 32];
+        let mut config = SilentConfig::default();
+        config.cell_size = Some(128);
+        let mut sender_gen = SaltGenerator::new(&seed);
+        
+        // 300 bytes need three cells of 107 payload bytes; an empty message takes one
+        let message: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let cells = build_dynamic_frame(&mut sender_gen, &message, config).unwrap();
+        assert_eq!(cells.len(), 3 * (7 + 128));
+        let empty = build_dynamic_frame(&mut sender_gen, b"", config).unwrap();
+        assert_eq!(empty.len(), 7 + 128);
+        
+        // A single cell is just a fragment; chaff between cells does not break a message
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        let (frame_type, fragment, consumed) = parse_typed_frame(&mut receiver_gen, &cells, config).unwrap();
+        assert_eq!((frame_type, fragment.as_slice(), consumed), (FrameType::Data, &message[..107], 135));
+        
+        let (mut first, mut last) = (Vec::new(), Vec::new());
+        append_frame(&mut sender_gen, FrameType::Data, true, b"split ", config, &mut first).unwrap();
+        let chaff = build_typed_frame(&mut sender_gen, FrameType::Chaff, b"", config).unwrap();
+        append_frame(&mut sender_gen, FrameType::Data, false, b"message", config, &mut last).unwrap();
+        
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&seed));
+        for byte in [&cells, &empty, &first, &chaff].into_iter().flatten() {
+            parser.append_data(std::slice::from_ref(byte)).unwrap();
+        }
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), message);
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"");
+        assert!(parser.try_parse_next(config).unwrap().is_none());
+        parser.append_data(&last).unwrap();
+        assert_eq!(parser.try_parse_next(config).unwrap().unwrap(), b"split message");
+        
+        config.cell_size = Some(MIN_CELL_SIZE - 1);
+        assert!(matches!(build_dynamic_frame(&mut sender_gen, b"x", config), Err(DynamicFramingError::InvalidLength(_))));
+    }
+
+    #[test]
+    fn test_padding_policies() {
+        let seed = [9u8; 32];
+        let policies = [
+            PaddingPolicy::None,
+            PaddingPolicy::Random { max: 100 },
+            PaddingPolicy::PowerOfTwo,
+            PaddingPolicy::for_mtu(1350),
+        ];
+        
+        for policy in policies {
+            let mut config = SilentConfig::default();
+            config.enable_sequence_hint = false;
+            config.padding = policy;
+            
+            let mut sender_gen = SaltGenerator::new(&seed);
+            let mut receiver_gen = SaltGenerator::new(&seed);
+            
+            let mut body_lens = Vec::new();
+            for len in [0usize, 1, 45, 300, 2000] {
+                let payload = vec![0xAB; len];
+                let frame = build_dynamic_frame(&mut sender_gen, &payload, config).unwrap();
+                let (decoded, consumed) = parse_dynamic_frame(&mut receiver_gen, &frame, config).unwrap();
+                assert_eq!(decoded, payload);
+                assert_eq!(consumed, frame.len());
+                
+                let body_len = frame.len() - 5;
+                let min_body = 1 + len + 4 + 16;
+                match policy {
+                    PaddingPolicy::None => assert_eq!(body_len, min_body),
+                    PaddingPolicy::Random { max } => assert!(body_len >= min_body && body_len <= min_body + max),
+                    PaddingPolicy::PowerOfTwo => assert!(body_len.is_power_of_two() && body_len >= 64),
+                    PaddingPolicy::FixedSize(size) => assert_eq!(body_len % size, 0),
+                }
+                body_lens.push(body_len);
+            }
+            
+            // Short messages are indistinguishable under the bucketing policies
+            if matches!(policy, PaddingPolicy::PowerOfTwo | PaddingPolicy::FixedSize(_)) {
+                assert_eq!(body_lens[0], body_lens[1]);
+            }
+        }
+    }
+
     #[test]
     fn test_sequences_must_match() {
         let seed = [2u8; 32];
-        let mut sender_gen = SaltGenerator::new(seed);
-        let mut receiver_gen = SaltGenerator::new(seed);
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
         
         let payload = b"Secret";
         let config = SilentConfig::default();
@@ -574,31 +3306,69 @@ mod tests {
         strict_config.enable_sequence_hint = false;
         
         // Re-generate frame without hint
-        let mut sender_gen_strict = SaltGenerator::new(seed);
+        let mut sender_gen_strict = SaltGenerator::new(&seed);
         let frame_strict = build_dynamic_frame(&mut sender_gen_strict, payload, strict_config).unwrap();
         
-        let mut receiver_gen_strict = SaltGenerator::new(seed);
+        let mut receiver_gen_strict = SaltGenerator::new(&seed);
         receiver_gen_strict.next_salt(); // Desync
         
         let result = parse_dynamic_frame(&mut receiver_gen_strict, &frame_strict, strict_config);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_header_is_authenticated() {
+        let seed = [6u8; 32];
+        let mut config = SilentConfig::default();
+        config.enable_double_ratchet = true;
+        config.ratchet_interval = 1;
+        
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        let frame0 = build_dynamic_frame(&mut sender_gen, b"plain", config).unwrap();
+        let frame1 = build_dynamic_frame(&mut sender_gen, b"with offer", config).unwrap();
+        parse_dynamic_frame(&mut receiver_gen, &frame0, config).unwrap();
+        
+        // Swapping the offered public key must not go unnoticed
+        let mut tampered = frame1.clone();
+        tampered[MAX_FIXED_HEADER_LEN + 5] ^= 0x01;
+        let result = parse_dynamic_frame(&mut receiver_gen, &tampered, config);
+        assert!(matches!(result, Err(DynamicFramingError::DecryptionError)));
+        
+        // ...and the failed attempt leaves the receiver untouched
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frame1, config).unwrap();
+        assert_eq!(decoded, b"with offer");
+        
+        // The version byte is checked before decryption
+        let frame2 = build_dynamic_frame(&mut sender_gen, b"v2", config).unwrap();
+        let mut tampered = frame2.clone();
+        tampered[4] ^= 0x01;
+        let result = parse_dynamic_frame(&mut receiver_gen, &tampered, config);
+        assert!(matches!(result, Err(DynamicFramingError::UnsupportedVersion(v)) if v == FRAME_FORMAT_VERSION ^ 0x01));
+        
+        // A frame cut from another stream with the same seed does not open
+        let mut other_stream = SaltGenerator::new(&seed);
+        other_stream.context_id = 7;
+        let result = parse_dynamic_frame(&mut other_stream, &frame0, config);
+        assert!(matches!(result, Err(DynamicFramingError::DecryptionError)));
+    }
+
     #[test]
     fn test_periodic_rekeying() {
         let seed = [3u8; 32];
-        let mut sender_gen = SaltGenerator::new(seed);
-        let mut receiver_gen = SaltGenerator::new(seed);
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
         
         let mut config = SilentConfig::default();
         config.enable_double_ratchet = true;
         config.ratchet_interval = 2; // Rekey every 2 frames
+        config.padding = PaddingPolicy::None; // Exact sizes below
         
         // Frame 1 (Seq 0): No Rekey
         let payload1 = b"Frame 1";
         let frame1 = build_dynamic_frame(&mut sender_gen, payload1, config).unwrap();
-        // 4(Len)+2(Hint)+Data+Tag. No 32B entropy for seq 0.
-        assert_eq!(frame1.len(), 4 + 2 + payload1.len() + 16); 
+        // 4(Len)+1(Version)+2(Hint)+1(Epoch)+Type+Data+PaddingLen+Tag. No Ratchet Block for seq 0.
+        assert_eq!(frame1.len(), 4 + 1 + 2 + 1 + 1 + payload1.len() + 4 + 16); 
         
         let (dec1, _) = parse_dynamic_frame(&mut receiver_gen, &frame1, config).unwrap();
         assert_eq!(dec1, payload1);
@@ -610,7 +3380,7 @@ mod tests {
         // Seq 2: Yes
         let payload2 = b"Frame 2";
         let frame2 = build_dynamic_frame(&mut sender_gen, payload2, config).unwrap();
-        assert_eq!(frame2.len(), 4 + 2 + payload2.len() + 16);
+        assert_eq!(frame2.len(), 4 + 1 + 2 + 1 + 1 + payload2.len() + 4 + 16);
         
         let (dec2, _) = parse_dynamic_frame(&mut receiver_gen, &frame2, config).unwrap();
         assert_eq!(dec2, payload2);
@@ -618,18 +3388,448 @@ mod tests {
         // Frame 3 (Seq 2): Rekey! (2 % 2 == 0)
         let payload3 = b"Frame 3 - Rekey";
         let frame3 = build_dynamic_frame(&mut sender_gen, payload3, config).unwrap();
-        // Should be larger by 32 bytes
-        assert_eq!(frame3.len(), 4 + 2 + 32 + payload3.len() + 16);
+        // Should carry an offer-only Ratchet Block (1B kind + 32B public key)
+        assert_eq!(frame3.len(), 4 + 1 + 2 + 1 + RATCHET_OFFER_LEN + 1 + payload3.len() + 4 + 16);
         
         let (dec3, _) = parse_dynamic_frame(&mut receiver_gen, &frame3, config).unwrap();
         assert_eq!(dec3, payload3);
         
-        // Verify seeds have diverged from a naive non-rekeyed generator
-        let mut naive_gen = SaltGenerator::new(seed);
+        // An offer alone carries no secret: nothing is mixed until it is answered
+        let mut naive_gen = SaltGenerator::new(&seed);
         naive_gen.next_salt(); naive_gen.next_salt(); naive_gen.next_salt(); // Advance 3 times
         
         let s_rekeyed = sender_gen.next_salt();
         let s_naive = naive_gen.next_salt();
-        assert_ne!(s_rekeyed, s_naive);
+        assert_eq!(s_rekeyed, s_naive);
+    }
+
+    #[test]
+    fn test_double_ratchet_heals_both_directions() {
+        let seed = [5u8; 32];
+        let stream_id = 8;
+        
+        let mut config = SilentConfig::default();
+        config.enable_double_ratchet = true;
+        config.ratchet_interval = 2;
+        
+        // Each endpoint shares one ratchet between its send and receive generators
+        let client_ratchet = DhRatchet::new_shared();
+        let server_ratchet = DhRatchet::new_shared();
+        
+        let mut client_send = SaltGenerator::new_directional(&seed, stream_id, Direction::ClientToServer);
+        let mut client_recv = SaltGenerator::new_directional(&seed, stream_id, Direction::ServerToClient);
+        let mut server_recv = SaltGenerator::new_directional(&seed, stream_id, Direction::ClientToServer);
+        let mut server_send = SaltGenerator::new_directional(&seed, stream_id, Direction::ServerToClient);
+        client_send.attach_ratchet(client_ratchet.clone());
+        client_recv.attach_ratchet(client_ratchet);
+        server_recv.attach_ratchet(server_ratchet.clone());
+        server_send.attach_ratchet(server_ratchet);
+        
+        // An eavesdropper who later learns the static seed
+        let mut attacker = SaltGenerator::new_directional(&seed, stream_id, Direction::ClientToServer);
+        
+        for round in 0..4 {
+            let request = format!("request {}", round);
+            let frame = build_dynamic_frame(&mut client_send, request.as_bytes(), config).unwrap();
+            let (decoded, _) = parse_dynamic_frame(&mut server_recv, &frame, config).unwrap();
+            assert_eq!(decoded, request.as_bytes());
+            
+            let response = format!("response {}", round);
+            let frame = build_dynamic_frame(&mut server_send, response.as_bytes(), config).unwrap();
+            let (decoded, _) = parse_dynamic_frame(&mut client_recv, &frame, config).unwrap();
+            assert_eq!(decoded, response.as_bytes());
+        }
+        
+        // Server offered at seq 2; the client answers at seq 4 and mixes the
+        // DH secret into its send chain, so seq 5 is out of the attacker's reach.
+        let answer = build_dynamic_frame(&mut client_send, b"answer", config).unwrap();
+        assert!(parse_dynamic_frame(&mut server_recv, &answer, config).is_ok());
+        let healed = build_dynamic_frame(&mut client_send, b"after healing", config).unwrap();
+        let (decoded, _) = parse_dynamic_frame(&mut server_recv, &healed, config).unwrap();
+        assert_eq!(decoded, b"after healing");
+        
+        for _ in 0..5 { attacker.next_salt(); }
+        assert!(parse_dynamic_frame(&mut attacker, &healed, config).is_err());
+    }
+
+    #[test]
+    fn test_epoch_recovers_from_lost_answer_frame() {
+        let mut config = SilentConfig::default();
+        config.enable_double_ratchet = true;
+        config.ratchet_interval = 2;
+        
+        for chain_keys in [false, true] {
+            let seed = [8u8; 32];
+            let stream_id = 12;
+            let client_ratchet = DhRatchet::new_shared();
+            let server_ratchet = DhRatchet::new_shared();
+            
+            let mut client_send = SaltGenerator::new_directional(&seed, stream_id, Direction::ClientToServer);
+            let mut client_recv = SaltGenerator::new_directional(&seed, stream_id, Direction::ServerToClient);
+            let mut server_recv = SaltGenerator::new_directional(&seed, stream_id, Direction::ClientToServer);
+            let mut server_send = SaltGenerator::new_directional(&seed, stream_id, Direction::ServerToClient);
+            for generator in [&mut client_send, &mut client_recv, &mut server_recv, &mut server_send] {
+                if chain_keys {
+                    generator.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+                }
+            }
+            client_send.attach_ratchet(client_ratchet.clone());
+            client_recv.attach_ratchet(client_ratchet);
+            server_recv.attach_ratchet(server_ratchet.clone());
+            server_send.attach_ratchet(server_ratchet);
+            
+            // Client offers at seq 2, server answers at seq 2 and moves to epoch 1
+            let mut responses = Vec::new();
+            for seq in 0..6 {
+                let request = build_dynamic_frame(&mut client_send, b"request", config).unwrap();
+                parse_dynamic_frame(&mut server_recv, &request, config).unwrap();
+                responses.push(build_dynamic_frame(&mut server_send, format!("response {}", seq).as_bytes(), config).unwrap());
+            }
+            assert_eq!(server_send.epoch(), 1);
+            
+            let mut client_parser = DynamicStreamParser::new(client_recv);
+            for response in &responses[..2] {
+                client_parser.append_data(response).unwrap();
+                assert!(client_parser.try_parse_next(config).unwrap().is_some());
+            }
+            
+            // The answer (seq 2) is lost: seq 3 is from an epoch the client cannot open yet,
+            // but the parser skips it; seq 4 repeats the answer and reopens the stream.
+            for response in &responses[3..] {
+                client_parser.append_data(response).unwrap();
+            }
+            assert!(matches!(
+                client_parser.try_parse_next(config),
+                Err(DynamicFramingError::UnknownEpoch { epoch: 1, .. })
+            ));
+            assert_eq!(client_parser.try_parse_next(config).unwrap().unwrap(), b"response 4");
+            assert_eq!(client_parser.try_parse_next(config).unwrap().unwrap(), b"response 5");
+            assert_eq!(client_parser.generator.epoch(), 1);
+            
+            // A frame of the previous epoch that shows up late is still accepted, but only once
+            let late = parse_dynamic_frame(&mut client_parser.generator, &responses[2], config);
+            assert_eq!(late.unwrap().0, b"response 2");
+            let replay = parse_dynamic_frame(&mut client_parser.generator, &responses[2], config);
+            assert!(matches!(replay, Err(DynamicFramingError::Replay { seq: 2, .. })));
+        }
+    }
+
+    #[test]
+    fn test_forged_rekey_frame_keeps_offer() {
+        let mut config = SilentConfig::default();
+        config.enable_double_ratchet = true;
+        config.ratchet_interval = 2;
+        
+        let seed = [9u8; 32];
+        let client_ratchet = DhRatchet::new_shared();
+        let mut client_send = SaltGenerator::new_directional(&seed, 4, Direction::ClientToServer);
+        let mut client_recv = SaltGenerator::new_directional(&seed, 4, Direction::ServerToClient);
+        let mut server_recv = SaltGenerator::new_directional(&seed, 4, Direction::ClientToServer);
+        let mut server_send = SaltGenerator::new_directional(&seed, 4, Direction::ServerToClient);
+        client_send.attach_ratchet(client_ratchet.clone());
+        client_recv.attach_ratchet(client_ratchet);
+        let server_ratchet = DhRatchet::new_shared();
+        server_recv.attach_ratchet(server_ratchet.clone());
+        server_send.attach_ratchet(server_ratchet);
+        
+        let mut responses = Vec::new();
+        for _ in 0..5 {
+            let request = build_dynamic_frame(&mut client_send, b"request", config).unwrap();
+            parse_dynamic_frame(&mut server_recv, &request, config).unwrap();
+            responses.push(build_dynamic_frame(&mut server_send, b"response", config).unwrap());
+        }
+        for response in &responses[..2] {
+            parse_dynamic_frame(&mut client_recv, response, config).unwrap();
+        }
+        
+        // The client missed the answer (seq 2), so seq 4 has to open epoch 1 from its
+        // repeated answer. A copy with a forged answer fails authentication...
+        let mut forged = responses[4].clone();
+        forged[4 + 1 + 2 + 1 + 40] ^= 0x01;
+        assert!(matches!(
+            parse_dynamic_frame(&mut client_recv, &forged, config),
+            Err(DynamicFramingError::DecryptionError)
+        ));
+        
+        // ...and leaves the offer it was answering usable for the genuine frame
+        let (decoded, _) = parse_dynamic_frame(&mut client_recv, &responses[4], config).unwrap();
+        assert_eq!(decoded, b"response");
+        assert_eq!(client_recv.epoch(), 1);
+    }
+
+    #[test]
+    fn test_chain_keys_erase_past_salts() {
+        let seed = [6u8; 32];
+        let config = SilentConfig::default();
+        
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        sender_gen.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+        receiver_gen.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+        
+        for i in 0..3 {
+            let payload = format!("chain frame {}", i);
+            let frame = build_dynamic_frame(&mut sender_gen, payload.as_bytes(), config).unwrap();
+            let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frame, config).unwrap();
+            assert_eq!(decoded, payload.as_bytes());
+        }
+        
+        // Past salts are gone on both sides, future ones are still derivable
+        assert!(sender_gen.get_salt_for_sequence(1).is_none());
+        assert!(receiver_gen.get_salt_for_sequence(1).is_none());
+        assert_eq!(sender_gen.get_salt_for_sequence(3), receiver_gen.get_salt_for_sequence(3));
+        
+        // And the chain does not match the plain seed-based schedule
+        assert_ne!(sender_gen.get_salt_for_sequence(3), SaltGenerator::new(&seed).get_salt_for_sequence(3));
+        
+        // Rewinding the sender must not reuse an erased key
+        sender_gen.reset();
+        sender_gen.next_salt();
+        assert_eq!(sender_gen.sequence(), 4);
+    }
+
+    #[test]
+    fn test_chain_keys_resync_and_skipped_cache() {
+        let seed = [7u8; 32];
+        let config = SilentConfig::default();
+        
+        let mut sender_gen = SaltGenerator::new(&seed);
+        let mut receiver_gen = SaltGenerator::new(&seed);
+        sender_gen.enable_chain_keys(2);
+        receiver_gen.enable_chain_keys(2);
+        
+        let frames: Vec<Vec<u8>> = (0..8)
+            .map(|i| build_dynamic_frame(&mut sender_gen, format!("frame {}", i).as_bytes(), config).unwrap())
+            .collect();
+        
+        // Frames 0 and 1 are lost: robust mode jumps to frame 2
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[2], config).unwrap();
+        assert_eq!(decoded, b"frame 2");
+        assert_eq!(receiver_gen.sequence(), 3);
+        
+        // A late frame is opened from the skipped cache, exactly once
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[1], config).unwrap();
+        assert_eq!(decoded, b"frame 1");
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frames[1], config).is_err());
+        
+        // Jumping beyond `max_skipped` is refused
+        assert!(parse_dynamic_frame(&mut receiver_gen, &frames[7], config).is_err());
+        let (decoded, _) = parse_dynamic_frame(&mut receiver_gen, &frames[3], config).unwrap();
+        assert_eq!(decoded, b"frame 3");
+    }
+
+    #[test]
+    fn test_directional_key_schedule() {
+        let base_seed = [4u8; 32];
+        let stream_id = 4;
+        let config = SilentConfig::default();
+        
+        // Client sends a request, server answers on the same stream
+        let mut client_send = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ClientToServer);
+        let mut server_recv = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ClientToServer);
+        let mut server_send = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ServerToClient);
+        let mut client_recv = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ServerToClient);
+        
+        // The two directions must never share a salt (key + nonce)
+        assert_ne!(client_send.get_salt_for_sequence(0), server_send.get_salt_for_sequence(0));
+        // Other streams are independent as well
+        let other = SaltGenerator::new_directional(&base_seed, stream_id + 4, Direction::ClientToServer);
+        assert_ne!(client_send.get_salt_for_sequence(0), other.get_salt_for_sequence(0));
+        
+        let request = build_dynamic_frame(&mut client_send, b"request", config).unwrap();
+        let ack = build_dynamic_frame(&mut server_send, b"ack", config).unwrap();
+        
+        let (decoded, _) = parse_dynamic_frame(&mut server_recv, &request, config).unwrap();
+        assert_eq!(decoded, b"request");
+        let (decoded, _) = parse_dynamic_frame(&mut client_recv, &ack, config).unwrap();
+        assert_eq!(decoded, b"ack");
+        
+        // An ACK fed to the wrong-direction generator must not decrypt
+        let mut wrong = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ClientToServer);
+        let strict = SilentConfig { enable_sequence_hint: false, ..config };
+        let mut strict_sender = SaltGenerator::new_directional(&base_seed, stream_id, Direction::ServerToClient);
+        let strict_ack = build_dynamic_frame(&mut strict_sender, b"ack", strict).unwrap();
+        assert!(parse_dynamic_frame(&mut wrong, &strict_ack, strict).is_err());
+    }
+
+    #[test]
+    fn test_silent_stream() {
+        // In-memory transport: reads from `incoming`, writes to `outgoing`
+        struct Pipe {
+            incoming: io::Cursor<Vec<u8>>,
+            outgoing: Vec<u8>,
+        }
+        impl Read for Pipe {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                // One byte at a time, to exercise partial frames
+                let len = buf.len().min(1);
+                self.incoming.read(&mut buf[..len])
+            }
+        }
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.outgoing.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        
+        let seed = [22u8; 32];
+        let config = SilentConfig::default();
+        let generator = |direction| SaltGenerator::new_directional(&seed, 4, direction);
+        let pipe = |incoming| Pipe { incoming: io::Cursor::new(incoming), outgoing: Vec::new() };
+        
+        let mut client = SilentStream::new(pipe(Vec::new()), generator(Direction::ClientToServer), generator(Direction::ServerToClient), config);
+        assert_eq!(client.write(&[]).unwrap(), 0);
+        client.write_all(b"hello ").unwrap();
+        client.send_message(b"").unwrap();
+        client.write_all(b"world").unwrap();
+        let wire = client.into_inner().outgoing;
+        
+        // Message boundaries are kept by recv_message, `read` joins the bytes
+        let mut server = SilentStream::new(pipe(wire.clone()), generator(Direction::ServerToClient), generator(Direction::ClientToServer), config);
+        assert_eq!(server.read(&mut []).unwrap(), 0);
+        assert_eq!(server.recv_message().unwrap().unwrap(), b"hello ");
+        let mut text = String::new();
+        server.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "world");
+        assert!(server.recv_message().unwrap().is_none());
+        
+        // A transport closed inside a frame is an error
+        let mut truncated = SilentStream::new(pipe(wire[..wire.len() - 1].to_vec()), generator(Direction::ServerToClient), generator(Direction::ClientToServer), config);
+        let mut rest = Vec::new();
+        assert_eq!(truncated.read_to_end(&mut rest).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(rest, b"hello ");
+        
+        // So is one closed between the cells of a message
+        let cells = SilentConfig { cell_size: Some(128), ..config };
+        let mut client = SilentStream::new(pipe(Vec::new()), generator(Direction::ClientToServer), generator(Direction::ServerToClient), cells);
+        client.send_message(&[7; 300]).unwrap();
+        let wire = client.into_inner().outgoing;
+        let mut truncated = SilentStream::new(pipe(wire[..2 * (7 + 128)].to_vec()), generator(Direction::ServerToClient), generator(Direction::ClientToServer), cells);
+        assert_eq!(truncated.recv_message().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+    
+    #[test]
+    fn test_datagram_frames() {
+        let seed = [23u8; 32];
+        let config = SilentConfig::default();
+        for chain_keys in [false, true] {
+            let generator = || {
+                let mut generator = SaltGenerator::new_directional(&seed, DATAGRAM_CONTEXT_ID, Direction::ClientToServer);
+                if chain_keys {
+                    generator.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+                }
+                generator
+            };
+            let mut sender = generator();
+            let mut receiver = generator();
+            let datagrams: Vec<Vec<u8>> = (0..5u8)
+                .map(|i| build_datagram_frame(&mut sender, FrameType::Data, &[i; 10], config).unwrap())
+                .collect();
+            
+            // Reordered, with datagram 2 missing for now
+            for i in [3, 0, 4, 1] {
+                let (frame_type, payload) = parse_datagram_frame(&mut receiver, &datagrams[i], config).unwrap();
+                assert_eq!((frame_type, payload), (FrameType::Data, vec![i as u8; 10]));
+            }
+            assert!(matches!(
+                parse_datagram_frame(&mut receiver, &datagrams[3], config),
+                Err(DynamicFramingError::Replay { seq: 3, .. })
+            ));
+            
+            // A flipped bit anywhere fails, the genuine late datagram still opens
+            let mut tampered = datagrams[2].clone();
+            tampered[12] ^= 1;
+            assert!(parse_datagram_frame(&mut receiver, &tampered, config).is_err());
+            assert_eq!(parse_datagram_frame(&mut receiver, &datagrams[2], config).unwrap().1, vec![2; 10]);
+        }
+        
+        // The sequence is restored from its low 32 bits, closest to the receive position
+        assert_eq!(expand_datagram_sequence(0, 7), 7);
+        assert_eq!(expand_datagram_sequence((1 << 32) + 5, u32::MAX), u32::MAX as u64);
+        assert_eq!(expand_datagram_sequence(u32::MAX as u64, 2), (1 << 32) + 2);
+    }
+    
+    #[test]
+    fn test_peer_generators() {
+        let seed = [29u8; 32];
+        let stream_id = 4;
+        let mut config = SilentConfig::default();
+        config.enable_double_ratchet = true;
+        config.ratchet_interval = 2;
+        
+        // Each peer keeps one ratchet per stream, shared by both of its directions
+        let mut client_ratchets = HashMap::new();
+        let mut server_ratchets = HashMap::new();
+        let mut client_send = stream_generator(&mut client_ratchets, &seed, stream_id, Role::Client.sending());
+        let mut client_recv = stream_generator(&mut client_ratchets, &seed, stream_id, Role::Client.receiving());
+        let mut server_recv = stream_generator(&mut server_ratchets, &seed, stream_id, Role::Server.receiving());
+        let mut server_send = stream_generator(&mut server_ratchets, &seed, stream_id, Role::Server.sending());
+        assert_eq!((client_ratchets.len(), server_ratchets.len()), (1, 1));
+        
+        for round in 0..4 {
+            let request = format!("request {}", round);
+            let frame = build_dynamic_frame(&mut client_send, request.as_bytes(), config).unwrap();
+            assert_eq!(parse_dynamic_frame(&mut server_recv, &frame, config).unwrap().0, request.as_bytes());
+            
+            let response = format!("response {}", round);
+            let frame = build_dynamic_frame(&mut server_send, response.as_bytes(), config).unwrap();
+            assert_eq!(parse_dynamic_frame(&mut client_recv, &frame, config).unwrap().0, response.as_bytes());
+        }
+        
+        // Datagram generators pair up across roles, never within one
+        let (mut client_out, mut client_in) = datagram_generators(&seed, Role::Client);
+        let (mut server_out, mut server_in) = datagram_generators(&seed, Role::Server);
+        let datagram = build_datagram_frame(&mut client_out, FrameType::Data, b"ping", config).unwrap();
+        assert!(parse_datagram_frame(&mut client_in, &datagram, config).is_err());
+        assert_eq!(parse_datagram_frame(&mut server_in, &datagram, config).unwrap().1, b"ping");
+        let datagram = build_datagram_frame(&mut server_out, FrameType::Data, b"pong", config).unwrap();
+        assert_eq!(parse_datagram_frame(&mut client_in, &datagram, config).unwrap().1, b"pong");
+    }
+    
+    #[test]
+    fn test_debug_redacts_secrets() {
+        // 0xAB repeated shows up as "171" in a byte array
+        let mut generator = SaltGenerator::new(&[0xABu8; 32]);
+        generator.enable_chain_keys(DEFAULT_MAX_SKIPPED_KEYS);
+        let parser = DynamicStreamParser::new(SaltGenerator::new(&[0xABu8; 32]));
+        for output in [format!("{:?}", generator), format!("{:?}", parser), format!("{:?}", DhRatchet::new())] {
+            assert!(!output.contains("171"), "{}", output);
+        }
+        assert!(format!("{:?}", generator).contains("chain_keys: true"));
+        
+        // Salts are wiped on drop but still read like plain arrays
+        let salt = generator.next_salt();
+        assert_eq!(salt.len(), 32);
+    }
+
+    #[test]
+    fn test_config_negotiation() {
+        let client = SilentConfig::parse("ratchet:500").unwrap();
+        let server = SilentConfig { enable_header_protection: true, ratchet_interval: 200, ..SilentConfig::default() };
+
+        // Both sides settle on the same shared settings and keep their local ones
+        let on_client = client.negotiate(&server.encode_wire()).unwrap();
+        let on_server = SilentConfig { resync_window: 10, ..server }.negotiate(&client.encode_wire()).unwrap();
+        assert_eq!(on_client.encode_wire(), on_server.encode_wire());
+        assert!(on_client.enable_double_ratchet && on_client.enable_header_protection);
+        assert_eq!(on_client.ratchet_interval, 500);
+        assert_eq!(on_server.resync_window, 10);
+
+        // Frames built with one side's result open with the other's
+        let mut sender = SaltGenerator::new(&[31u8; 32]);
+        let mut parser = DynamicStreamParser::new(SaltGenerator::new(&[31u8; 32]));
+        parser.append_data(&build_dynamic_frame(&mut sender, b"agreed", on_client).unwrap()).unwrap();
+        assert_eq!(parser.try_parse_next(on_server).unwrap(), Some(b"agreed".to_vec()));
+
+        let aes = SilentConfig { cipher_suite: CipherSuite::Aes256Gcm, ..server };
+        assert!(matches!(client.negotiate(&aes.encode_wire()), Err(DynamicFramingError::IncompatibleConfig(_))));
+        let mut future = client.encode_wire();
+        future[1] = FRAME_FORMAT_VERSION + 1;
+        assert!(matches!(server.negotiate(&future), Err(DynamicFramingError::IncompatibleConfig(_))));
+        assert!(SilentConfig::parse("ratchet:0").is_none());
+        assert!(SilentConfig::parse("turbo").is_none());
     }
 }
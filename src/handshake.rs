@@ -0,0 +1,493 @@
+//! Handshake Module
+//!
+//! Authenticated key agreement that replaces the old hard-coded session seed.
+//! Runs once per connection on a pair of dedicated unidirectional control streams
+//! and yields the per-connection base seed fed into `SaltGenerator`. A reconnecting
+//! client may resume its session instead (see `resumption`).
+//!
+//! # Message Structure
+//! ClientHello: [Version (1B)] [Type (1B)] [Client Ephemeral X25519 PubKey (32B)] [Config (12B)] [Auth]
+//! ServerHello: [Version (1B)] [Type (1B)] [Server Ephemeral X25519 PubKey (32B)] [Config (12B)] [Auth]
+//!
+//! `Auth` is either an HMAC-SHA256 tag keyed by a pre-shared key (32 bytes), or an
+//! Ed25519 signature made with a long-term identity key (64 bytes). The server's
+//! auth covers the full ClientHello, binding both ephemeral keys together.
+//!
+//! # Config Negotiation
+//! `Config` is the sender's `SilentConfig::encode_wire`. Both sides run
+//! `SilentConfig::negotiate` on the peer's config and get the same framing settings;
+//! incompatible configs fail the handshake with `IncompatibleConfig` instead of every
+//! frame later failing to decrypt. The configs are authenticated with the rest of the
+//! hellos, so an attacker cannot downgrade them.
+//!
+//! # Seed Derivation
+//! BaseSeed = HKDF-SHA256(salt = SHA256(ClientHello || ServerHello), ikm = X25519 || PSK)
+//!
+//! The PSK, the DH output and the base seed are wiped from memory when dropped.
+
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::digest::{Context, SHA256};
+use ring::hkdf;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+use crate::dynamic_framing::{DynamicFramingError, Secret, SilentConfig, CONFIG_WIRE_LEN};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Client-initiated unidirectional stream carrying the ClientHello
+pub const CLIENT_HANDSHAKE_STREAM_ID: u64 = 2;
+
+/// Server-initiated unidirectional stream carrying the ServerHello
+pub const SERVER_HANDSHAKE_STREAM_ID: u64 = 3;
+
+/// Environment variable holding the hex-encoded 32-byte pre-shared key
+pub const PSK_ENV_VAR: &str = "SILENT_SPEAKER_PSK";
+
+/// Handshake wire format version
+/// 1: ephemeral keys only; 2: framing config in both hellos
+pub const HANDSHAKE_VERSION: u8 = 2;
+
+const TYPE_CLIENT_HELLO: u8 = 1;
+const TYPE_SERVER_HELLO: u8 = 2;
+
+const PUBLIC_KEY_LEN: usize = 32;
+const BODY_LEN: usize = 2 + PUBLIC_KEY_LEN + CONFIG_WIRE_LEN;
+
+const CLIENT_AUTH_LABEL: &[u8] = b"fengni v1 client hello";
+const SERVER_AUTH_LABEL: &[u8] = b"fengni v1 server hello";
+const BASE_SEED_INFO: &[u8] = b"fengni v1 base seed";
+
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    #[error("Unsupported handshake version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unexpected handshake message type: {0}")]
+    UnexpectedMessage(u8),
+
+    #[error("Peer authentication failed")]
+    AuthenticationFailed,
+
+    #[error("Key agreement failed")]
+    KeyAgreementFailed,
+
+    #[error("Handshake already completed")]
+    AlreadyCompleted,
+
+    #[error("Invalid key material: {0}")]
+    InvalidKey(String),
+
+    #[error("Session config rejected: {0}")]
+    IncompatibleConfig(#[from] DynamicFramingError),
+}
+
+/// How each side proves it is a legitimate peer
+pub enum HandshakeAuth {
+    /// Both peers share a 32-byte secret. The PSK is also mixed into the seed.
+    PreSharedKey([u8; 32]),
+
+    /// Each peer signs with its own Ed25519 key and pins the peer's public key.
+    Identity {
+        keypair: Ed25519KeyPair,
+        peer_public_key: [u8; 32],
+    },
+}
+
+impl HandshakeAuth {
+    /// Load a pre-shared key from `SILENT_SPEAKER_PSK` (64 hex characters)
+    pub fn from_env() -> Result<Self, HandshakeError> {
+        let value = std::env::var(PSK_ENV_VAR)
+            .map(Zeroizing::new)
+            .map_err(|_| HandshakeError::InvalidKey(format!("{} is not set", PSK_ENV_VAR)))?;
+        Self::from_hex(value.trim())
+    }
+
+    /// Parse a hex-encoded 32-byte pre-shared key
+    pub fn from_hex(value: &str) -> Result<Self, HandshakeError> {
+        let bytes = hex::decode(value)
+            .map(Zeroizing::new)
+            .map_err(|e| HandshakeError::InvalidKey(e.to_string()))?;
+        let psk: [u8; 32] = bytes.as_slice().try_into()
+            .map_err(|_| HandshakeError::InvalidKey(format!("expected 32 bytes, got {}", bytes.len())))?;
+        Ok(HandshakeAuth::PreSharedKey(psk))
+    }
+
+    /// Build an identity authenticator from a PKCS#8 Ed25519 key and the peer's public key
+    pub fn identity(pkcs8: &[u8], peer_public_key: [u8; 32]) -> Result<Self, HandshakeError> {
+        let keypair = Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|e| HandshakeError::InvalidKey(e.to_string()))?;
+        Ok(HandshakeAuth::Identity { keypair, peer_public_key })
+    }
+
+    /// Our own Ed25519 public key (identity mode only)
+    pub fn public_key(&self) -> Option<[u8; 32]> {
+        match self {
+            HandshakeAuth::PreSharedKey(_) => None,
+            HandshakeAuth::Identity { keypair, .. } => keypair.public_key().as_ref().try_into().ok(),
+        }
+    }
+
+    fn auth_len(&self) -> usize {
+        match self {
+            HandshakeAuth::PreSharedKey(_) => 32,
+            HandshakeAuth::Identity { .. } => 64,
+        }
+    }
+
+    fn message_len(&self) -> usize {
+        BODY_LEN + self.auth_len()
+    }
+
+    fn authenticate(&self, label: &[u8], transcript: &[&[u8]]) -> Vec<u8> {
+        match self {
+            HandshakeAuth::PreSharedKey(psk) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, psk);
+                let mut context = hmac::Context::with_key(&key);
+                context.update(label);
+                for part in transcript {
+                    context.update(part);
+                }
+                context.sign().as_ref().to_vec()
+            }
+            HandshakeAuth::Identity { keypair, .. } => {
+                let message = concat_transcript(label, transcript);
+                keypair.sign(&message).as_ref().to_vec()
+            }
+        }
+    }
+
+    fn verify(&self, label: &[u8], transcript: &[&[u8]], tag: &[u8]) -> Result<(), HandshakeError> {
+        match self {
+            HandshakeAuth::PreSharedKey(psk) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, psk);
+                let message = concat_transcript(label, transcript);
+                hmac::verify(&key, &message, tag)
+                    .map_err(|_| HandshakeError::AuthenticationFailed)
+            }
+            HandshakeAuth::Identity { peer_public_key, .. } => {
+                let message = concat_transcript(label, transcript);
+                signature::UnparsedPublicKey::new(&signature::ED25519, peer_public_key)
+                    .verify(&message, tag)
+                    .map_err(|_| HandshakeError::AuthenticationFailed)
+            }
+        }
+    }
+
+    fn psk_bytes(&self) -> &[u8] {
+        match self {
+            HandshakeAuth::PreSharedKey(psk) => psk,
+            HandshakeAuth::Identity { .. } => &[],
+        }
+    }
+}
+
+impl Drop for HandshakeAuth {
+    fn drop(&mut self) {
+        if let HandshakeAuth::PreSharedKey(psk) = self {
+            psk.zeroize();
+        }
+    }
+}
+
+impl std::fmt::Debug for HandshakeAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeAuth::PreSharedKey(_) => f.write_str("PreSharedKey(<redacted>)"),
+            HandshakeAuth::Identity { peer_public_key, .. } => f.debug_struct("Identity")
+                .field("public_key", &self.public_key())
+                .field("peer_public_key", peer_public_key)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Client side of the handshake
+pub struct ClientHandshake {
+    private_key: Option<EphemeralPrivateKey>,
+    client_hello: Vec<u8>,
+    buffer: Vec<u8>,
+    config: SilentConfig,
+    negotiated: Option<SilentConfig>,
+}
+
+impl ClientHandshake {
+    /// Start a handshake proposing `config`. Returns the state and the ClientHello
+    /// to send on `CLIENT_HANDSHAKE_STREAM_ID`.
+    pub fn new(auth: &HandshakeAuth, config: SilentConfig) -> Result<(Self, Vec<u8>), HandshakeError> {
+        let rng = SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+        let public_key = private_key.compute_public_key()
+            .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+
+        let mut client_hello = Vec::with_capacity(auth.message_len());
+        client_hello.push(HANDSHAKE_VERSION);
+        client_hello.push(TYPE_CLIENT_HELLO);
+        client_hello.extend_from_slice(public_key.as_ref());
+        client_hello.extend_from_slice(&config.encode_wire());
+        let tag = auth.authenticate(CLIENT_AUTH_LABEL, &[&client_hello]);
+        client_hello.extend_from_slice(&tag);
+
+        let state = Self {
+            private_key: Some(private_key),
+            client_hello: client_hello.clone(),
+            buffer: Vec::new(),
+            config,
+            negotiated: None,
+        };
+        Ok((state, client_hello))
+    }
+
+    /// Feed bytes received on `SERVER_HANDSHAKE_STREAM_ID`.
+    /// Returns:
+    /// - Ok(Some(seed)): Handshake complete, per-connection base seed
+    ///   (the agreed config is then available from `negotiated_config`).
+    /// - Ok(None): Incomplete ServerHello.
+    /// - Err: Authentication, protocol or config failure. The connection should be closed.
+    pub fn on_data(&mut self, auth: &HandshakeAuth, data: &[u8]) -> Result<Option<Secret>, HandshakeError> {
+        if self.private_key.is_none() {
+            return Err(HandshakeError::AlreadyCompleted);
+        }
+
+        self.buffer.extend_from_slice(data);
+        // Hellos of other versions differ in length, so check the version before waiting for more
+        check_version(&self.buffer)?;
+        let message_len = auth.message_len();
+        if self.buffer.len() < message_len {
+            return Ok(None);
+        }
+
+        let server_hello = &self.buffer[..message_len];
+        let (peer_public_key, peer_config) = check_body(server_hello, TYPE_SERVER_HELLO)?;
+        auth.verify(
+            SERVER_AUTH_LABEL,
+            &[&self.client_hello, &server_hello[..BODY_LEN]],
+            &server_hello[BODY_LEN..],
+        )?;
+        let negotiated = self.config.negotiate(peer_config)?;
+
+        let private_key = self.private_key.take().unwrap();
+        let seed = derive_base_seed(auth, private_key, peer_public_key, &self.client_hello, server_hello)?;
+        self.negotiated = Some(negotiated);
+        Ok(Some(seed))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.private_key.is_none()
+    }
+
+    /// Framing settings agreed with the server (None until the handshake completes)
+    pub fn negotiated_config(&self) -> Option<SilentConfig> {
+        self.negotiated
+    }
+}
+
+/// Server side of the handshake
+pub struct ServerHandshake {
+    buffer: Vec<u8>,
+    config: SilentConfig,
+    negotiated: Option<SilentConfig>,
+}
+
+impl ServerHandshake {
+    /// Wait for a ClientHello, answering it with `config`
+    pub fn new(config: SilentConfig) -> Self {
+        Self {
+            buffer: Vec::new(),
+            config,
+            negotiated: None,
+        }
+    }
+
+    /// Feed bytes received on `CLIENT_HANDSHAKE_STREAM_ID`.
+    /// Returns:
+    /// - Ok(Some((server_hello, seed))): Send `server_hello` on `SERVER_HANDSHAKE_STREAM_ID`
+    ///   (the agreed config is then available from `negotiated_config`).
+    /// - Ok(None): Incomplete ClientHello.
+    /// - Err: Authentication, protocol or config failure. The connection should be closed.
+    pub fn on_data(&mut self, auth: &HandshakeAuth, data: &[u8]) -> Result<Option<(Vec<u8>, Secret)>, HandshakeError> {
+        if self.negotiated.is_some() {
+            return Err(HandshakeError::AlreadyCompleted);
+        }
+
+        self.buffer.extend_from_slice(data);
+        // Hellos of other versions differ in length, so check the version before waiting for more
+        check_version(&self.buffer)?;
+        let message_len = auth.message_len();
+        if self.buffer.len() < message_len {
+            return Ok(None);
+        }
+
+        let client_hello = &self.buffer[..message_len];
+        let (peer_public_key, peer_config) = check_body(client_hello, TYPE_CLIENT_HELLO)?;
+        auth.verify(CLIENT_AUTH_LABEL, &[&client_hello[..BODY_LEN]], &client_hello[BODY_LEN..])?;
+        let negotiated = self.config.negotiate(peer_config)?;
+
+        let rng = SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+        let public_key = private_key.compute_public_key()
+            .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+
+        let mut server_hello = Vec::with_capacity(message_len);
+        server_hello.push(HANDSHAKE_VERSION);
+        server_hello.push(TYPE_SERVER_HELLO);
+        server_hello.extend_from_slice(public_key.as_ref());
+        server_hello.extend_from_slice(&self.config.encode_wire());
+        let tag = auth.authenticate(SERVER_AUTH_LABEL, &[client_hello, &server_hello]);
+        server_hello.extend_from_slice(&tag);
+
+        let seed = derive_base_seed(auth, private_key, peer_public_key, client_hello, &server_hello)?;
+        self.negotiated = Some(negotiated);
+        Ok(Some((server_hello, seed)))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.negotiated.is_some()
+    }
+
+    /// Framing settings agreed with the client (None until the handshake completes)
+    pub fn negotiated_config(&self) -> Option<SilentConfig> {
+        self.negotiated
+    }
+}
+
+impl Default for ServerHandshake {
+    fn default() -> Self {
+        Self::new(SilentConfig::default())
+    }
+}
+
+/// Reject a hello of another handshake version as soon as its first byte has arrived
+fn check_version(message: &[u8]) -> Result<(), HandshakeError> {
+    match message.first() {
+        Some(&version) if version != HANDSHAKE_VERSION => Err(HandshakeError::UnsupportedVersion(version)),
+        _ => Ok(()),
+    }
+}
+
+/// Check the type, and split the body into the ephemeral public key and the config
+fn check_body(message: &[u8], expected_type: u8) -> Result<(&[u8], &[u8]), HandshakeError> {
+    if message[1] != expected_type {
+        return Err(HandshakeError::UnexpectedMessage(message[1]));
+    }
+    Ok(message[2..BODY_LEN].split_at(PUBLIC_KEY_LEN))
+}
+
+fn concat_transcript(label: &[u8], transcript: &[&[u8]]) -> Vec<u8> {
+    let mut message = label.to_vec();
+    for part in transcript {
+        message.extend_from_slice(part);
+    }
+    message
+}
+
+fn derive_base_seed(
+    auth: &HandshakeAuth,
+    private_key: EphemeralPrivateKey,
+    peer_public_key: &[u8],
+    client_hello: &[u8],
+    server_hello: &[u8],
+) -> Result<Secret, HandshakeError> {
+    let peer = UnparsedPublicKey::new(&X25519, peer_public_key);
+    let psk = auth.psk_bytes();
+    let ikm = agreement::agree_ephemeral(private_key, &peer, |shared| {
+        let mut ikm = Zeroizing::new(shared.to_vec());
+        ikm.extend_from_slice(psk);
+        ikm
+    })
+    .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+
+    let mut context = Context::new(&SHA256);
+    context.update(client_hello);
+    context.update(server_hello);
+    let transcript_hash = context.finish();
+
+    let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, transcript_hash.as_ref());
+    let prk = salt.extract(&ikm);
+    let okm = prk.expand(&[BASE_SEED_INFO], hkdf::HKDF_SHA256)
+        .map_err(|_| HandshakeError::KeyAgreementFailed)?;
+    let mut seed = Secret::default();
+    okm.fill(&mut seed[..]).map_err(|_| HandshakeError::KeyAgreementFailed)?;
+    Ok(seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(client_auth: &HandshakeAuth, server_auth: &HandshakeAuth) -> Result<(Secret, Secret), HandshakeError> {
+        let (mut client, client_hello) = ClientHandshake::new(client_auth, SilentConfig::default())?;
+        let mut server = ServerHandshake::default();
+
+        // Deliver the ClientHello in two pieces to exercise buffering
+        assert!(server.on_data(server_auth, &client_hello[..10])?.is_none());
+        let (server_hello, server_seed) = server.on_data(server_auth, &client_hello[10..])?.unwrap();
+
+        let client_seed = client.on_data(client_auth, &server_hello)?.unwrap();
+        Ok((client_seed, server_seed))
+    }
+
+    #[test]
+    fn test_psk_handshake_agrees_on_fresh_seed() {
+        let auth = HandshakeAuth::PreSharedKey([7u8; 32]);
+        let (client_seed, server_seed) = run(&auth, &auth).unwrap();
+        assert_eq!(client_seed, server_seed);
+
+        // Ephemeral keys make every connection's seed different
+        let (second_seed, _) = run(&auth, &auth).unwrap();
+        assert_ne!(client_seed, second_seed);
+    }
+
+    #[test]
+    fn test_psk_mismatch_is_rejected() {
+        let client_auth = HandshakeAuth::PreSharedKey([1u8; 32]);
+        let server_auth = HandshakeAuth::PreSharedKey([2u8; 32]);
+        let result = run(&client_auth, &server_auth);
+        assert!(matches!(result, Err(HandshakeError::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn test_identity_handshake() {
+        let rng = SystemRandom::new();
+        let client_pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let server_pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let client_public = HandshakeAuth::identity(client_pkcs8.as_ref(), [0u8; 32]).unwrap().public_key().unwrap();
+        let server_public = HandshakeAuth::identity(server_pkcs8.as_ref(), [0u8; 32]).unwrap().public_key().unwrap();
+
+        let client_auth = HandshakeAuth::identity(client_pkcs8.as_ref(), server_public).unwrap();
+        let server_auth = HandshakeAuth::identity(server_pkcs8.as_ref(), client_public).unwrap();
+        let (client_seed, server_seed) = run(&client_auth, &server_auth).unwrap();
+        assert_eq!(client_seed, server_seed);
+
+        // A client pinning the wrong server key must refuse the ServerHello
+        let impostor = HandshakeAuth::identity(client_pkcs8.as_ref(), client_public).unwrap();
+        assert!(matches!(run(&impostor, &server_auth), Err(HandshakeError::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn test_config_negotiation() {
+        let auth = HandshakeAuth::PreSharedKey([3u8; 32]);
+        let client_config = SilentConfig { enable_double_ratchet: true, ..SilentConfig::default() };
+        let server_config = SilentConfig { enable_header_protection: true, ..SilentConfig::default() };
+        let (mut client, client_hello) = ClientHandshake::new(&auth, client_config).unwrap();
+        let mut server = ServerHandshake::new(server_config);
+        let (server_hello, _) = server.on_data(&auth, &client_hello).unwrap().unwrap();
+        client.on_data(&auth, &server_hello).unwrap().unwrap();
+
+        let agreed = client.negotiated_config().unwrap();
+        assert_eq!(Some(agreed.encode_wire()), server.negotiated_config().map(|config| config.encode_wire()));
+        assert!(agreed.enable_double_ratchet && agreed.enable_header_protection);
+
+        // A cipher suite mismatch is reported as such, before any frame is sent
+        let aes = SilentConfig { cipher_suite: crate::dynamic_framing::CipherSuite::Aes256Gcm, ..SilentConfig::default() };
+        let (_, client_hello) = ClientHandshake::new(&auth, aes).unwrap();
+        let result = ServerHandshake::default().on_data(&auth, &client_hello);
+        assert!(matches!(result, Err(HandshakeError::IncompatibleConfig(_))));
+
+        // A hello of another version is rejected without waiting for the rest
+        let result = ServerHandshake::default().on_data(&auth, &[1, TYPE_CLIENT_HELLO]);
+        assert!(matches!(result, Err(HandshakeError::UnsupportedVersion(1))));
+    }
+}
@@ -18,6 +18,21 @@ pub mod critical_sender;
 pub mod framing;
 /// 动态分帧模块 (Phase 3)
 pub mod dynamic_framing;
+/// 会话握手模块（X25519 密钥协商）
+pub mod handshake;
+/// 掩护流量模块（恒定速率发送）
+pub mod cover_traffic;
+/// 消息合并模块（多条消息合并为一个批量帧）
+pub mod coalescing;
+/// 流量变形模块（按目标分布整形帧大小与间隔）
+pub mod morphing;
+/// 流量分析模块（离线评估可区分性）
+pub mod analysis;
+/// 会话恢复模块（加密保存/恢复会话状态，重连时跳过完整握手）
+pub mod resumption;
+/// tokio 编解码器（需启用 feature = "tokio-codec"）
+#[cfg(feature = "tokio-codec")]
+pub mod codec;
 
 /// 重新导出常用类型
 pub use whisper::*;
@@ -32,13 +47,11 @@ pub use stream::{
 };
 pub use critical_sender::CriticalSender;
 pub use framing::{frame_message, parse_framed_message, FramingError}; // 新增导出
+pub use handshake::{ClientHandshake, ServerHandshake, HandshakeAuth, HandshakeError};
 
 /// 库版本信息
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// 默认会话基础种子 (Phase 3+)
-pub const SESSION_BASE_SEED: [u8; 32] = [0x42; 32];
-
 // 日志系统
 pub mod logging;
 
@@ -0,0 +1,262 @@
+//! Traffic Morphing Module
+//!
+//! Reshapes the sender's traffic to follow a target distribution (e.g. a
+//! video-streaming or web-browsing profile) instead of the application's own:
+//! - Frame sizes are drawn from the profile's size histogram
+//! - Gaps between frames are drawn from its interval histogram
+//! - Messages larger than the drawn size are split across frames (continuation
+//!   flag, see `SilentConfig::cell_size`), smaller ones are padded up to it
+//! - When nothing is queued, chaff frames of the drawn size keep the shape
+//!
+//! The engine is `cover_traffic::PacedSender::morphing`. Morphing only shapes what
+//! the sender puts on the wire, so the profile is not part of the negotiated
+//! `SilentConfig`: the event loop hands it to `PacedSender::from_config`.
+//!
+//! # Profile File
+//! One histogram bin per line, `#` starts a comment:
+//! ```text
+//! name video-streaming
+//! # size <encrypted body bytes> <weight>
+//! size 1200 80
+//! size 300 20
+//! # interval <milliseconds> <weight>
+//! interval 16 90
+//! interval 40 10
+//! ```
+
+use crate::dynamic_framing::MIN_CELL_SIZE;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Environment variable selecting a profile file in the binaries
+pub const MORPHING_PROFILE_ENV_VAR: &str = "SILENT_SPEAKER_MORPHING_PROFILE";
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("Cannot read profile: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid profile line {line}: {reason}")]
+    InvalidLine { line: usize, reason: &'static str },
+
+    #[error("Profile has no {0} histogram")]
+    MissingHistogram(&'static str),
+}
+
+/// Random draws behind traffic shaping: the system RNG for live traffic, or a seeded
+/// `XorShift` for reproducible simulations (`analysis`, tests)
+pub enum ShapingRng {
+    System(SystemRandom),
+    Seeded(XorShift),
+}
+
+impl ShapingRng {
+    pub fn system() -> Self {
+        ShapingRng::System(SystemRandom::new())
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        ShapingRng::Seeded(XorShift::new(seed))
+    }
+
+    /// Next 64 random bits (None if the system RNG fails)
+    pub fn next_u64(&mut self) -> Option<u64> {
+        match self {
+            ShapingRng::System(rng) => {
+                let mut bytes = [0u8; 8];
+                rng.fill(&mut bytes).ok().map(|()| u64::from_be_bytes(bytes))
+            }
+            ShapingRng::Seeded(rng) => Some(rng.next_u64()),
+        }
+    }
+}
+
+/// Small deterministic PRNG (xorshift64*) for reproducible simulations, never for keys
+#[derive(Debug, Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in (0, 1]
+    pub fn unit(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn range(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next_u64() % (high - low) as u64) as usize
+    }
+}
+
+/// Weighted bins of a discrete distribution
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Histogram<T> {
+    bins: Vec<(T, u32)>,
+    total_weight: u64,
+}
+
+impl<T: Copy> Histogram<T> {
+    fn new() -> Self {
+        Self { bins: Vec::new(), total_weight: 0 }
+    }
+
+    fn add(&mut self, value: T, weight: u32) {
+        self.bins.push((value, weight));
+        self.total_weight += weight as u64;
+    }
+
+    /// Draw a value with probability proportional to its weight
+    fn sample(&self, rng: &mut ShapingRng) -> T {
+        // A failing RNG falls back to the first bin rather than stalling the sender
+        let mut point = rng.next_u64().map_or(0, |draw| draw % self.total_weight);
+        for &(value, weight) in &self.bins {
+            if point < weight as u64 {
+                return value;
+            }
+            point -= weight as u64;
+        }
+        self.bins[self.bins.len() - 1].0
+    }
+}
+
+/// Target distribution of frame sizes and inter-frame intervals
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MorphingProfile {
+    name: String,
+    sizes: Histogram<usize>,
+    intervals: Histogram<Duration>,
+}
+
+impl MorphingProfile {
+    /// Parse a profile file (see the module documentation for the format)
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        let mut name = String::new();
+        let mut sizes = Histogram::new();
+        let mut intervals = Histogram::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line = index + 1;
+            let content = raw_line.split('#').next().unwrap_or("").trim();
+            let mut fields = content.split_whitespace();
+            let Some(key) = fields.next() else { continue };
+
+            if key == "name" {
+                name = fields.collect::<Vec<_>>().join(" ");
+                continue;
+            }
+
+            let value: u64 = fields.next().and_then(|v| v.parse().ok())
+                .ok_or(ProfileError::InvalidLine { line, reason: "expected a numeric value" })?;
+            let weight: u32 = fields.next().and_then(|w| w.parse().ok())
+                .filter(|w| *w > 0)
+                .ok_or(ProfileError::InvalidLine { line, reason: "expected a positive weight" })?;
+            if fields.next().is_some() {
+                return Err(ProfileError::InvalidLine { line, reason: "trailing fields" });
+            }
+
+            match key {
+                "size" => {
+                    if value < MIN_CELL_SIZE as u64 || value > u32::MAX as u64 {
+                        return Err(ProfileError::InvalidLine { line, reason: "size out of range" });
+                    }
+                    sizes.add(value as usize, weight);
+                }
+                "interval" => {
+                    // A zero gap would let the sender emit frames without bound
+                    if value == 0 {
+                        return Err(ProfileError::InvalidLine { line, reason: "interval must be positive" });
+                    }
+                    intervals.add(Duration::from_millis(value), weight);
+                }
+                _ => return Err(ProfileError::InvalidLine { line, reason: "unknown key" }),
+            }
+        }
+
+        if sizes.bins.is_empty() {
+            return Err(ProfileError::MissingHistogram("size"));
+        }
+        if intervals.bins.is_empty() {
+            return Err(ProfileError::MissingHistogram("interval"));
+        }
+        Ok(Self { name, sizes, intervals })
+    }
+
+    /// Load a profile file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Profile named by `MORPHING_PROFILE_ENV_VAR` (None if unset)
+    pub fn from_env() -> Option<Result<Self, ProfileError>> {
+        std::env::var_os(MORPHING_PROFILE_ENV_VAR).map(Self::load)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Draw the encrypted body size of the next frame
+    pub fn sample_size(&self, rng: &mut ShapingRng) -> usize {
+        self.sizes.sample(rng)
+    }
+
+    /// Draw the gap before the next frame
+    pub fn sample_interval(&self, rng: &mut ShapingRng) -> Duration {
+        self.intervals.sample(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "
+        name web browsing
+        # bursts of full packets, then a pause
+        size 1200 3   # most frames
+        size 100 1
+        interval 5 1
+        interval 250 1
+    ";
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = MorphingProfile::parse(PROFILE).unwrap();
+        assert_eq!(profile.name(), "web browsing");
+
+        let mut rng = ShapingRng::seeded(3);
+        let sizes: Vec<usize> = (0..400).map(|_| profile.sample_size(&mut rng)).collect();
+        assert!(sizes.iter().all(|size| *size == 1200 || *size == 100));
+        let large = sizes.iter().filter(|size| **size == 1200).count();
+        assert!(large > 200 && large < 380, "{} large frames out of 400", large);
+        let interval = profile.sample_interval(&mut rng);
+        assert!(interval == Duration::from_millis(5) || interval == Duration::from_millis(250));
+
+        // The bundled profiles stay loadable
+        for name in ["video-streaming", "web-browsing"] {
+            let path = format!("{}/profiles/{}.profile", env!("CARGO_MANIFEST_DIR"), name);
+            assert_eq!(MorphingProfile::load(path).unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        let invalid = |text: &str| MorphingProfile::parse(text).unwrap_err().to_string();
+        assert_eq!(invalid("size 1200 1"), "Profile has no interval histogram");
+        assert_eq!(invalid("size 1200 1\ninterval 10 0"), "Invalid profile line 2: expected a positive weight");
+        assert_eq!(invalid("size 10 1\ninterval 10 1"), "Invalid profile line 1: size out of range");
+        assert_eq!(invalid("burst 3 1"), "Invalid profile line 1: unknown key");
+        assert!(matches!(MorphingProfile::load("/nonexistent/profile"), Err(ProfileError::Io(_))));
+    }
+}
@@ -0,0 +1,783 @@
+//! Session Resumption Module
+//!
+//! Carries a fengni session over to a new QUIC connection. Both peers derive the same
+//! `ResumptionTicket` from the handshake's base seed; when a connection goes away each
+//! side seals its `SessionState` (base seed, agreed config, stream generators and
+//! parsers, datagram generators) under that ticket, and opens it again after
+//! reconnecting, so streams continue where they stopped instead of starting a new
+//! key schedule.
+//!
+//! The server keeps sealed states in memory (`ResumptionStore`); the client saves its
+//! state, ticket and QUIC session to the file named by `RESUMPTION_FILE_ENV_VAR`
+//! (`SavedSession`). A reconnecting client sends a ResumeHello instead of a ClientHello
+//! and continues its streams right away, in QUIC 0-RTT data when the QUIC session
+//! resumes too. If the server has no matching state it rejects the attempt, and the
+//! client falls back to a full handshake.
+//!
+//! # Sealed State
+//! [Version (1B)] [Ticket ID (16B)] [Generation (8B)] [Nonce (12B)] [Encrypted State + Tag]
+//! encrypted with ChaCha20-Poly1305 under the ticket key, Version || Ticket ID || Generation as AAD.
+//!
+//! # Resume Exchange
+//! ResumeHello (on `CLIENT_RESUME_STREAM_ID`): [Version (1B)] [Ticket ID (16B)] [Generation (8B)] [Tag (32B)]
+//! ResumeAnswer (on `SERVER_RESUME_STREAM_ID`): [Version (1B)] [Accepted (1B)] [Tag (32B), if accepted]
+//!
+//! The hello's tag is HMAC-SHA256 under the ticket key of the sealed generation, the
+//! accept's tag covers the hello under the key of the next generation.
+//!
+//! # What is not kept
+//! - DH ratchets: ephemeral X25519 keys cannot be exported. Both peers attach fresh
+//!   ratchets after resuming (`SessionState::attach_ratchets`), and the next rekey
+//!   frames start a new offer/answer round.
+//! - Control handlers, hint caches and resync rate limits, which are rebuilt.
+//! - Frames in flight: a frame cut off by the disconnect stays in its parser's buffer
+//!   and is skipped by the resync scan once the stream continues.
+//!
+//! # Single Use
+//! Opening a state twice would reuse the sending keys of every stream from the same
+//! sequence. Each successful `SessionState::open` therefore ratchets the ticket to its
+//! next generation (Key' = HKDF-Expand(Key, TicketRatchetLabel)) and wipes the old key,
+//! so any state sealed before fails with `AlreadyResumed`. Persist the ticket again
+//! (`to_bytes`) after every `open`, and derive it with `from_base_seed` only once per session.
+//! `SavedSession::take` deletes the file it reads, and `ResumptionStore::resume` removes
+//! the state it opens, so a replayed ResumeHello finds nothing to resume.
+
+use crate::dynamic_framing::{
+    DhRatchet, DynamicFramingError, DynamicStreamParser, RatchetHandle, SaltGenerator, Secret, SilentConfig,
+    CONFIG_WIRE_LEN,
+};
+use ring::aead::{self, Aad, LessSafeKey, UnboundKey, CHACHA20_POLY1305};
+use ring::hkdf;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Format version of sealed states
+pub const RESUMPTION_VERSION: u8 = 1;
+
+/// Length of a ticket identifier
+pub const TICKET_ID_LEN: usize = 16;
+
+const TICKET_ID_LABEL: &[u8] = b"fengni v1 resumption ticket";
+const TICKET_KEY_LABEL: &[u8] = b"fengni v1 resumption key";
+const TICKET_RATCHET_LABEL: &[u8] = b"fengni v1 resumption ratchet";
+const RESUME_HELLO_LABEL: &[u8] = b"fengni v1 resume hello";
+const RESUME_ACCEPT_LABEL: &[u8] = b"fengni v1 resume accept";
+
+/// Length of a serialized ticket (see `ResumptionTicket::to_bytes`)
+pub const TICKET_LEN: usize = TICKET_ID_LEN + 8 + 32;
+
+/// Client-initiated unidirectional stream carrying the ResumeHello (instead of a ClientHello)
+pub const CLIENT_RESUME_STREAM_ID: u64 = 6;
+
+/// Server-initiated unidirectional stream carrying the answer to a ResumeHello
+pub const SERVER_RESUME_STREAM_ID: u64 = 7;
+
+/// Length of a ResumeHello
+pub const RESUME_HELLO_LEN: usize = HEADER_LEN + 32;
+
+/// Length of the answer accepting a ResumeHello
+pub const RESUME_ACCEPT_LEN: usize = 2 + 32;
+
+/// Answer rejecting a ResumeHello: the client falls back to a full handshake
+pub const RESUME_REJECT: [u8; 2] = [RESUMPTION_VERSION, 0];
+
+/// Environment variable naming the file where the client keeps its `SavedSession`
+pub const RESUMPTION_FILE_ENV_VAR: &str = "SILENT_SPEAKER_RESUMPTION_FILE";
+
+/// Most sealed states a `ResumptionStore` keeps
+pub const MAX_STORED_SESSIONS: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum ResumptionError {
+    #[error("Unsupported resumption state version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("State was sealed under another ticket")]
+    TicketMismatch,
+
+    #[error("State was already resumed (sealed at ticket generation {sealed}, ticket is at {current})")]
+    AlreadyResumed { sealed: u64, current: u64 },
+
+    #[error("Sealed state failed authentication")]
+    AuthenticationFailed,
+
+    #[error("Malformed resumption state")]
+    Malformed,
+
+    #[error("Encryption failed")]
+    EncryptionError,
+
+    #[error("No stored state for this ticket")]
+    UnknownTicket,
+
+    #[error("Server rejected the resume attempt")]
+    Rejected,
+
+    #[error("Session config rejected: {0}")]
+    IncompatibleConfig(#[from] DynamicFramingError),
+
+    #[error("Resumption file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Names a session and holds the key its state is sealed with.
+/// Keep it as secret as the session seed. Not `Clone`: a copy would still open
+/// the states its original has already resumed.
+#[derive(PartialEq, Eq)]
+pub struct ResumptionTicket {
+    id: [u8; TICKET_ID_LEN],
+    generation: u64,
+    key: Secret,
+}
+
+impl ResumptionTicket {
+    /// Ticket of the session with this base seed (the handshake result), the same on both peers:
+    /// ID = HKDF-Expand(BaseSeed, TicketIdLabel)[..16], Key = HKDF-Expand(BaseSeed, TicketKeyLabel)
+    pub fn from_base_seed(base_seed: &[u8; 32]) -> Self {
+        let mut id = [0u8; TICKET_ID_LEN];
+        id.copy_from_slice(&expand(base_seed, TICKET_ID_LABEL)[..TICKET_ID_LEN]);
+        Self { id, generation: 0, key: expand(base_seed, TICKET_KEY_LABEL) }
+    }
+
+    /// Public identifier of the session (e.g. to look up the stored state)
+    pub fn id(&self) -> [u8; TICKET_ID_LEN] {
+        self.id
+    }
+
+    /// Number of states resumed with this ticket so far
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Serialized ticket for storage: [ID (16B)] [Generation (8B)] [Key (32B)]
+    pub fn to_bytes(&self) -> Zeroizing<[u8; TICKET_LEN]> {
+        let mut bytes = Zeroizing::new([0u8; TICKET_LEN]);
+        bytes[..TICKET_ID_LEN].copy_from_slice(&self.id);
+        bytes[TICKET_ID_LEN..TICKET_ID_LEN + 8].copy_from_slice(&self.generation.to_be_bytes());
+        bytes[TICKET_ID_LEN + 8..].copy_from_slice(&self.key[..]);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; TICKET_LEN]) -> Self {
+        let mut id = [0u8; TICKET_ID_LEN];
+        id.copy_from_slice(&bytes[..TICKET_ID_LEN]);
+        let generation = u64::from_be_bytes(bytes[TICKET_ID_LEN..TICKET_ID_LEN + 8].try_into().unwrap());
+        let mut key = Secret::default();
+        key.copy_from_slice(&bytes[TICKET_ID_LEN + 8..]);
+        Self { id, generation, key }
+    }
+
+    /// Move to the next generation; the old key is wiped when replaced
+    fn advance(&mut self) {
+        self.key = expand(&self.key, TICKET_RATCHET_LABEL);
+        self.generation += 1;
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &self.key[..]).expect("ticket key length is fixed at 32 bytes"))
+    }
+
+    /// Encrypt a serialized state (in place, so no plaintext copy is left behind)
+    fn seal(&self, mut state: Zeroizing<Vec<u8>>) -> Result<Vec<u8>, ResumptionError> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| ResumptionError::EncryptionError)?;
+
+        let mut header = Vec::with_capacity(HEADER_LEN + aead::NONCE_LEN);
+        header.extend_from_slice(&self.header());
+        self.aead_key()
+            .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), Aad::from(&header[..]), &mut *state)
+            .map_err(|_| ResumptionError::EncryptionError)?;
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(&state);
+        Ok(header)
+    }
+
+    /// Version, ID and generation, as they start sealed states and ResumeHellos
+    fn header(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = RESUMPTION_VERSION;
+        header[1..1 + TICKET_ID_LEN].copy_from_slice(&self.id);
+        header[1 + TICKET_ID_LEN..].copy_from_slice(&self.generation.to_be_bytes());
+        header
+    }
+
+    /// Check that `message` starts with a header of this ticket at its current generation
+    fn check_header(&self, message: &[u8]) -> Result<(), ResumptionError> {
+        if message.len() < HEADER_LEN {
+            return Err(ResumptionError::Malformed);
+        }
+        if message[0] != RESUMPTION_VERSION {
+            return Err(ResumptionError::UnsupportedVersion(message[0]));
+        }
+        if message[1..1 + TICKET_ID_LEN] != self.id {
+            return Err(ResumptionError::TicketMismatch);
+        }
+        let generation = u64::from_be_bytes(message[1 + TICKET_ID_LEN..HEADER_LEN].try_into().unwrap());
+        if generation < self.generation {
+            return Err(ResumptionError::AlreadyResumed { sealed: generation, current: self.generation });
+        }
+        if generation > self.generation {
+            return Err(ResumptionError::TicketMismatch);
+        }
+        Ok(())
+    }
+
+    fn hmac_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, &self.key[..])
+    }
+
+    /// ResumeHello asking the server to resume the state sealed at the current generation
+    /// (build it before opening that state, which advances the ticket)
+    pub fn resume_hello(&self) -> [u8; RESUME_HELLO_LEN] {
+        let header = self.header();
+        let tag = hmac::sign(&self.hmac_key(), &[RESUME_HELLO_LABEL, &header[..]].concat());
+        let mut hello = [0u8; RESUME_HELLO_LEN];
+        hello[..HEADER_LEN].copy_from_slice(&header);
+        hello[HEADER_LEN..].copy_from_slice(tag.as_ref());
+        hello
+    }
+
+    fn check_resume_hello(&self, hello: &[u8]) -> Result<(), ResumptionError> {
+        if hello.len() != RESUME_HELLO_LEN {
+            return Err(ResumptionError::Malformed);
+        }
+        self.check_header(hello)?;
+        let (header, tag) = hello.split_at(HEADER_LEN);
+        hmac::verify(&self.hmac_key(), &[RESUME_HELLO_LABEL, header].concat(), tag)
+            .map_err(|_| ResumptionError::AuthenticationFailed)
+    }
+
+    /// Answer accepting `hello`, made after the state was opened (at the next generation)
+    fn resume_accept(&self, hello: &[u8]) -> [u8; RESUME_ACCEPT_LEN] {
+        let tag = hmac::sign(&self.hmac_key(), &[RESUME_ACCEPT_LABEL, hello].concat());
+        let mut accept = [0u8; RESUME_ACCEPT_LEN];
+        accept[..2].copy_from_slice(&[RESUMPTION_VERSION, 1]);
+        accept[2..].copy_from_slice(tag.as_ref());
+        accept
+    }
+
+    /// Decrypt a state sealed with `seal` at the current generation, then advance;
+    /// the plaintext is wiped when dropped
+    fn open(&mut self, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>, ResumptionError> {
+        if sealed.len() < HEADER_LEN + aead::NONCE_LEN {
+            return Err(ResumptionError::Malformed);
+        }
+        self.check_header(sealed)?;
+        let nonce: [u8; aead::NONCE_LEN] = sealed[HEADER_LEN..HEADER_LEN + aead::NONCE_LEN].try_into().unwrap();
+        let mut state = Zeroizing::new(sealed[HEADER_LEN + aead::NONCE_LEN..].to_vec());
+        let len = self.aead_key()
+            .open_in_place(aead::Nonce::assume_unique_for_key(nonce), Aad::from(&sealed[..HEADER_LEN]), &mut state[..])
+            .map_err(|_| ResumptionError::AuthenticationFailed)?
+            .len();
+        state.truncate(len);
+        self.advance();
+        Ok(state)
+    }
+}
+
+/// Version, ticket ID and generation of a sealed state (its AAD)
+const HEADER_LEN: usize = 1 + TICKET_ID_LEN + 8;
+
+/// HKDF-Expand(key, label) into a 32-byte secret
+fn expand(key: &[u8; 32], label: &[u8]) -> Secret {
+    let mut okm = Secret::default();
+    hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, key)
+        .expand(&[label], hkdf::HKDF_SHA256)
+        .and_then(|expanded| expanded.fill(&mut okm[..]))
+        .expect("HKDF output length is fixed at 32 bytes");
+    okm
+}
+
+impl std::fmt::Debug for ResumptionTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResumptionTicket")
+            .field("id", &self.id)
+            .field("generation", &self.generation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Everything a peer needs to continue a session on a new connection
+pub struct SessionState {
+    /// Base seed of the session, for streams opened after resuming
+    pub base_seed: Secret,
+    /// Framing settings agreed in the handshake (`SilentConfig::encode_wire`)
+    pub config: [u8; CONFIG_WIRE_LEN],
+    /// Sending generators by stream ID (e.g. the server's `Client.generators`)
+    pub senders: HashMap<u64, SaltGenerator>,
+    /// Stream parsers by stream ID, with their receiving generators and buffered data
+    pub parsers: HashMap<u64, DynamicStreamParser>,
+    /// (Sending, receiving) datagram generators (see `datagram_generators`)
+    pub datagrams: Option<(SaltGenerator, SaltGenerator)>,
+}
+
+impl SessionState {
+    /// State of a session with no streams yet, after a handshake that agreed on `config`
+    pub fn new(base_seed: &[u8; 32], config: SilentConfig) -> Self {
+        Self {
+            base_seed: Secret::new(*base_seed),
+            config: config.encode_wire(),
+            senders: HashMap::new(),
+            parsers: HashMap::new(),
+            datagrams: None,
+        }
+    }
+
+    /// The agreed framing settings, with the local-only settings (padding, windows, ...) of `local`.
+    /// Fails if `local` now uses another cipher suite or frame format.
+    pub fn negotiated_config(&self, local: SilentConfig) -> Result<SilentConfig, ResumptionError> {
+        // With every feature off locally, negotiating yields exactly the stored settings
+        let base = SilentConfig {
+            enable_sequence_hint: false,
+            enable_double_ratchet: false,
+            enable_header_protection: false,
+            ..local
+        };
+        Ok(base.negotiate(&self.config)?)
+    }
+
+    /// Attach fresh DH ratchets to the restored streams, shared by the sender and the
+    /// parser of each stream like `stream_generator` does
+    pub fn attach_ratchets(&mut self, ratchets: &mut HashMap<u64, RatchetHandle>) {
+        for (stream_id, generator) in &mut self.senders {
+            generator.attach_ratchet(ratchets.entry(*stream_id).or_insert_with(DhRatchet::new_shared).clone());
+        }
+        for (stream_id, parser) in &mut self.parsers {
+            parser.attach_ratchet(ratchets.entry(*stream_id).or_insert_with(DhRatchet::new_shared).clone());
+        }
+    }
+
+    /// Serialize and encrypt the state under `ticket`
+    pub fn seal(&self, ticket: &ResumptionTicket) -> Result<Vec<u8>, ResumptionError> {
+        let mut state = Zeroizing::new(Vec::new());
+        state.extend_from_slice(&self.base_seed[..]);
+        state.extend_from_slice(&self.config);
+        state.extend_from_slice(&(self.senders.len() as u32).to_be_bytes());
+        for (stream_id, generator) in &self.senders {
+            state.extend_from_slice(&stream_id.to_be_bytes());
+            generator.encode_state(&mut state);
+        }
+        state.extend_from_slice(&(self.parsers.len() as u32).to_be_bytes());
+        for (stream_id, parser) in &self.parsers {
+            state.extend_from_slice(&stream_id.to_be_bytes());
+            parser.encode_state(&mut state);
+        }
+        match &self.datagrams {
+            Some((sender, receiver)) => {
+                state.push(1);
+                sender.encode_state(&mut state);
+                receiver.encode_state(&mut state);
+            }
+            None => state.push(0),
+        }
+        ticket.seal(state)
+    }
+
+    /// Decrypt and restore a state sealed under `ticket`, which then moves to its next
+    /// generation: the same sealed state cannot be opened again (see "Single Use")
+    pub fn open(ticket: &mut ResumptionTicket, sealed: &[u8]) -> Result<Self, ResumptionError> {
+        let state = ticket.open(sealed)?;
+        Self::decode(&mut StateReader::new(&state)).ok_or(ResumptionError::Malformed)
+    }
+
+    fn decode(reader: &mut StateReader) -> Option<Self> {
+        let base_seed = Secret::new(reader.bytes()?);
+        let config = reader.bytes()?;
+        let mut senders = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let stream_id = reader.u64()?;
+            senders.insert(stream_id, SaltGenerator::decode_state(reader)?);
+        }
+        let mut parsers = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let stream_id = reader.u64()?;
+            parsers.insert(stream_id, DynamicStreamParser::decode_state(reader)?);
+        }
+        let datagrams = match reader.u8()? {
+            0 => None,
+            1 => Some((SaltGenerator::decode_state(reader)?, SaltGenerator::decode_state(reader)?)),
+            _ => return None,
+        };
+        if !reader.is_empty() {
+            return None;
+        }
+        Some(Self { base_seed, config, senders, parsers, datagrams })
+    }
+}
+
+/// What the client keeps between connections to resume its session
+pub struct SavedSession {
+    pub ticket: ResumptionTicket,
+    /// `SessionState` sealed under `ticket`
+    pub sealed: Vec<u8>,
+    /// QUIC session (`quiche::Connection::session`), so the next connection can send 0-RTT data
+    pub quic_session: Option<Vec<u8>>,
+}
+
+impl SavedSession {
+    /// Path from `RESUMPTION_FILE_ENV_VAR` (None if unset: the client does not resume)
+    pub fn path_from_env() -> Option<PathBuf> {
+        std::env::var_os(RESUMPTION_FILE_ENV_VAR).map(PathBuf::from)
+    }
+
+    /// Serialized form: [Ticket (56B)] [QUIC Session Length (4B)] [QUIC Session] [Sealed State]
+    /// (length 0 without a QUIC session)
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let quic_session = self.quic_session.as_deref().unwrap_or_default();
+        let mut bytes = Zeroizing::new(Vec::with_capacity(TICKET_LEN + 4 + quic_session.len() + self.sealed.len()));
+        bytes.extend_from_slice(&self.ticket.to_bytes()[..]);
+        bytes.extend_from_slice(&(quic_session.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(quic_session);
+        bytes.extend_from_slice(&self.sealed);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ResumptionError> {
+        let mut reader = StateReader::new(bytes);
+        let ticket = ResumptionTicket::from_bytes(&Zeroizing::new(reader.bytes::<TICKET_LEN>().ok_or(ResumptionError::Malformed)?));
+        let quic_session = reader.length_prefixed().ok_or(ResumptionError::Malformed)?;
+        let quic_session = (!quic_session.is_empty()).then(|| quic_session.to_vec());
+        Ok(Self { ticket, sealed: reader.rest().to_vec(), quic_session })
+    }
+
+    /// Write to `path`, readable by the owner only (it holds the ticket key)
+    pub fn store(&self, path: &Path) -> Result<(), ResumptionError> {
+        use std::io::Write;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read a session saved with `store` and delete the file, so that the saved
+    /// state cannot be resumed twice even if this run never saves a new one
+    pub fn take(path: &Path) -> Result<Self, ResumptionError> {
+        let bytes = Zeroizing::new(std::fs::read(path)?);
+        std::fs::remove_file(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Open the saved state and start a resume attempt.
+    /// Returns the attempt, whose `hello` goes on `CLIENT_RESUME_STREAM_ID`, and the restored state.
+    pub fn resume(self) -> Result<(ResumeAttempt, SessionState), ResumptionError> {
+        let Self { mut ticket, sealed, .. } = self;
+        let hello = ticket.resume_hello();
+        let state = SessionState::open(&mut ticket, &sealed)?;
+        Ok((ResumeAttempt { ticket: Some(ticket), hello, buffer: Vec::new() }, state))
+    }
+}
+
+/// Client side of a resume attempt, waiting for the server's answer
+pub struct ResumeAttempt {
+    ticket: Option<ResumptionTicket>,
+    hello: [u8; RESUME_HELLO_LEN],
+    buffer: Vec<u8>,
+}
+
+impl ResumeAttempt {
+    /// ResumeHello to send on `CLIENT_RESUME_STREAM_ID`
+    pub fn hello(&self) -> &[u8] {
+        &self.hello
+    }
+
+    /// Feed bytes received on `SERVER_RESUME_STREAM_ID`.
+    /// Returns:
+    /// - Ok(Some(ticket)): The server resumed the session; seal the state under `ticket` next time.
+    /// - Ok(None): Incomplete answer.
+    /// - Err: Rejected or invalid answer. Drop the restored state and run a full handshake.
+    pub fn on_data(&mut self, data: &[u8]) -> Result<Option<ResumptionTicket>, ResumptionError> {
+        let Some(ticket) = self.ticket.as_ref() else {
+            return Err(ResumptionError::Malformed);
+        };
+        self.buffer.extend_from_slice(data);
+        match self.buffer[..] {
+            [] | [_] => return Ok(None),
+            [version, ..] if version != RESUMPTION_VERSION => return Err(ResumptionError::UnsupportedVersion(version)),
+            [_, 0] => return Err(ResumptionError::Rejected),
+            [_, 1, ..] if self.buffer.len() < RESUME_ACCEPT_LEN => return Ok(None),
+            [_, 1, ..] if self.buffer.len() == RESUME_ACCEPT_LEN => {}
+            _ => return Err(ResumptionError::Malformed),
+        }
+        let tag = &self.buffer[2..];
+        hmac::verify(&ticket.hmac_key(), &[RESUME_ACCEPT_LABEL, &self.hello[..]].concat(), tag)
+            .map_err(|_| ResumptionError::AuthenticationFailed)?;
+        Ok(self.ticket.take())
+    }
+}
+
+/// Sealed states the server keeps for clients that may reconnect, by ticket ID.
+/// Holds at most `MAX_STORED_SESSIONS`; the oldest is dropped first.
+#[derive(Default)]
+pub struct ResumptionStore {
+    sessions: HashMap<[u8; TICKET_ID_LEN], (ResumptionTicket, Vec<u8>)>,
+    order: VecDeque<[u8; TICKET_ID_LEN]>,
+}
+
+impl ResumptionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep a state sealed under `ticket` until the client resumes it
+    pub fn insert(&mut self, ticket: ResumptionTicket, sealed: Vec<u8>) {
+        let id = ticket.id();
+        if self.sessions.insert(id, (ticket, sealed)).is_some() {
+            self.order.retain(|stored| *stored != id);
+        }
+        self.order.push_back(id);
+        while self.order.len() > MAX_STORED_SESSIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.sessions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Answer a ResumeHello. On success the stored state is opened and removed, and the
+    /// result holds the state, its ticket (at the next generation) and the answer to send
+    /// on `SERVER_RESUME_STREAM_ID`. On error send `RESUME_REJECT`; a hello that fails
+    /// authentication leaves the stored state in place.
+    pub fn resume(&mut self, hello: &[u8]) -> Result<(SessionState, ResumptionTicket, [u8; RESUME_ACCEPT_LEN]), ResumptionError> {
+        if hello.len() != RESUME_HELLO_LEN {
+            return Err(ResumptionError::Malformed);
+        }
+        let id: [u8; TICKET_ID_LEN] = hello[1..1 + TICKET_ID_LEN].try_into().unwrap();
+        let (ticket, _) = self.sessions.get(&id).ok_or(ResumptionError::UnknownTicket)?;
+        ticket.check_resume_hello(hello)?;
+
+        let (mut ticket, sealed) = self.sessions.remove(&id).unwrap();
+        self.order.retain(|stored| *stored != id);
+        let state = SessionState::open(&mut ticket, &sealed)?;
+        let accept = ticket.resume_accept(hello);
+        Ok((state, ticket, accept))
+    }
+}
+
+/// Big-endian reader over a serialized state
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.data.split_first_chunk::<N>()?;
+        self.data = rest;
+        Some(*head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|[byte]| byte)
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_be_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        self.bytes().map(u64::from_be_bytes)
+    }
+
+    /// [Length (4B)] [Bytes]
+    pub(crate) fn length_prefixed(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Everything not read yet
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_framing::{build_dynamic_frame, build_typed_frame, Direction, FrameType};
+
+    #[test]
+    fn test_resumed_streams_continue() {
+        let base_seed = [3u8; 32];
+        let config = SilentConfig::default();
+        let mut client_tx = SaltGenerator::new_directional(&base_seed, 4, Direction::ClientToServer);
+        let mut server_rx = DynamicStreamParser::new(SaltGenerator::new_directional(&base_seed, 4, Direction::ClientToServer));
+        let first = build_dynamic_frame(&mut client_tx, b"before", config).unwrap();
+        server_rx.append_data(&first).unwrap();
+        assert_eq!(server_rx.try_parse_next(config).unwrap().unwrap(), b"before");
+        for _ in 1..5 {
+            let frame = build_dynamic_frame(&mut client_tx, b"before", config).unwrap();
+            server_rx.append_data(&frame).unwrap();
+            assert_eq!(server_rx.try_parse_next(config).unwrap().unwrap(), b"before");
+        }
+        // The connection drops while a frame is in flight: half of it is buffered
+        let cut = build_dynamic_frame(&mut client_tx, b"cut off", config).unwrap();
+        server_rx.append_data(&cut[..cut.len() / 2]).unwrap();
+        assert_eq!(server_rx.try_parse_next(config).unwrap(), None);
+
+        // Each side seals its own half of the stream under its copy of the session's ticket
+        let mut client_ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let mut server_ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let mut client_state = SessionState::new(&base_seed, config);
+        client_state.senders.insert(4, client_tx);
+        let client_sealed = client_state.seal(&client_ticket).unwrap();
+        let mut server_state = SessionState::new(&base_seed, config);
+        server_state.parsers.insert(4, server_rx);
+        let server_sealed = server_state.seal(&server_ticket).unwrap();
+
+        // After reconnecting, the stream continues at the same sequence...
+        let client_state = SessionState::open(&mut client_ticket, &client_sealed).unwrap();
+        let mut server_state = SessionState::open(&mut server_ticket, &server_sealed).unwrap();
+        assert_eq!(client_state.base_seed, server_state.base_seed);
+        assert_eq!(client_state.negotiated_config(config).unwrap().encode_wire(), config.encode_wire());
+        let mut client_tx = client_state.senders.into_iter().next().unwrap().1;
+        let mut server_rx = server_state.parsers.remove(&4).unwrap();
+        assert_eq!((client_tx.sequence(), server_rx.generator().sequence()), (6, 5));
+        let frame = build_dynamic_frame(&mut client_tx, b"after", config).unwrap();
+        server_rx.append_data(&frame).unwrap();
+
+        // ...past the frame cut off with the old connection
+        assert_eq!(server_rx.try_parse_next(config).unwrap().unwrap(), b"after");
+        assert_eq!(server_rx.resync_stats().bytes_skipped, (cut.len() / 2) as u64);
+
+        // Frames of the old connection are still rejected as replays
+        server_rx.append_data(&first).unwrap();
+        assert!(matches!(server_rx.try_parse_next(config), Err(DynamicFramingError::Replay { .. })));
+    }
+
+    #[test]
+    fn test_parser_state_survives_resumption() {
+        let base_seed = [6u8; 32];
+        let config = SilentConfig::default();
+        let mut tx = SaltGenerator::new_directional(&base_seed, 8, Direction::ServerToClient);
+        let mut rx = DynamicStreamParser::new(SaltGenerator::new_directional(&base_seed, 8, Direction::ServerToClient));
+        let batch = [&[0, 0, 0, 3][..], b"one", &[0, 0, 0, 3], b"two"].concat();
+        rx.append_data(&build_typed_frame(&mut tx, FrameType::Batch, &batch, config).unwrap()).unwrap();
+        assert_eq!(rx.try_parse_next(config).unwrap().unwrap(), b"one");
+
+        // The second message of the batch was already decrypted: it is kept with the parser
+        let mut ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let mut state = SessionState::new(&base_seed, config);
+        state.parsers.insert(8, rx);
+        let sealed = state.seal(&ticket).unwrap();
+        let mut rx = SessionState::open(&mut ticket, &sealed).unwrap().parsers.remove(&8).unwrap();
+        assert_eq!(rx.try_parse_next(config).unwrap().unwrap(), b"two");
+        assert_eq!(rx.try_parse_next(config).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sealed_state_is_bound_to_ticket() {
+        let mut ticket = ResumptionTicket::from_base_seed(&[1u8; 32]);
+        assert_eq!(ResumptionTicket::from_bytes(&ticket.to_bytes()), ticket);
+        let mut generator = SaltGenerator::new(&[9u8; 32]);
+        generator.enable_chain_keys(16);
+        generator.next_salt();
+        let mut state = SessionState::new(&[1u8; 32], SilentConfig::default());
+        state.senders.insert(0, generator);
+        let sealed = state.seal(&ticket).unwrap();
+
+        let mut other = ResumptionTicket::from_base_seed(&[2u8; 32]);
+        assert!(matches!(SessionState::open(&mut other, &sealed), Err(ResumptionError::TicketMismatch)));
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(SessionState::open(&mut ticket, &tampered), Err(ResumptionError::AuthenticationFailed)));
+
+        let restored = SessionState::open(&mut ticket, &sealed).unwrap();
+        assert!(restored.senders[&0].uses_chain_keys());
+    }
+
+    #[test]
+    fn test_sealed_state_opens_once() {
+        let base_seed = [4u8; 32];
+        let config = SilentConfig::default();
+        let mut ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let mut client_tx = SaltGenerator::new_directional(&base_seed, 4, Direction::ClientToServer);
+        build_dynamic_frame(&mut client_tx, b"before", config).unwrap();
+        let mut state = SessionState::new(&base_seed, config);
+        state.senders.insert(4, client_tx);
+        let sealed = state.seal(&ticket).unwrap();
+
+        let mut state = SessionState::open(&mut ticket, &sealed).unwrap();
+        build_dynamic_frame(state.senders.get_mut(&4).unwrap(), b"after", config).unwrap();
+        assert_eq!(ticket.generation(), 1);
+
+        // A second open of the same blob would hand out the sending key of "after" again
+        assert!(matches!(
+            SessionState::open(&mut ticket, &sealed),
+            Err(ResumptionError::AlreadyResumed { sealed: 0, current: 1 })
+        ));
+
+        // The stored ticket moves along: states sealed after resuming open with it
+        let mut stored = ResumptionTicket::from_bytes(&ticket.to_bytes());
+        let sealed = state.seal(&ticket).unwrap();
+        let resumed = SessionState::open(&mut stored, &sealed).unwrap();
+        assert_eq!(resumed.senders[&4].sequence(), 2);
+    }
+
+    #[test]
+    fn test_resume_exchange() {
+        let base_seed = [5u8; 32];
+        let config = SilentConfig::default();
+        let client_ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let server_ticket = ResumptionTicket::from_base_seed(&base_seed);
+        let mut store = ResumptionStore::new();
+        store.insert(server_ticket, SessionState::new(&base_seed, config).seal(&ResumptionTicket::from_base_seed(&base_seed)).unwrap());
+
+        // The client's session survives a round trip through its file, which is then gone
+        let path = std::env::temp_dir().join(format!("fengni-resumption-{}", std::process::id()));
+        let client_sealed = SessionState::new(&base_seed, config).seal(&client_ticket).unwrap();
+        SavedSession { ticket: client_ticket, sealed: client_sealed, quic_session: Some(b"quic".to_vec()) }
+            .store(&path).unwrap();
+        let saved = SavedSession::take(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(saved.quic_session.as_deref(), Some(&b"quic"[..]));
+        let (mut attempt, state) = saved.resume().unwrap();
+        assert_eq!(&state.base_seed[..], &base_seed);
+
+        // A forged hello leaves the stored state alone
+        let mut forged = attempt.hello().to_vec();
+        forged[RESUME_HELLO_LEN - 1] ^= 1;
+        assert!(matches!(store.resume(&forged), Err(ResumptionError::AuthenticationFailed)));
+        assert_eq!(store.len(), 1);
+
+        let (_, server_ticket, accept) = store.resume(attempt.hello()).unwrap();
+        assert!(attempt.on_data(&accept[..1]).unwrap().is_none());
+        let client_ticket = attempt.on_data(&accept[1..]).unwrap().unwrap();
+        assert_eq!(client_ticket, server_ticket);
+        assert_eq!(client_ticket.generation(), 1);
+
+        // A replayed hello finds nothing to resume
+        assert!(matches!(store.resume(attempt.hello()), Err(ResumptionError::UnknownTicket)));
+        assert!(store.is_empty());
+
+        let saved = SavedSession { ticket: client_ticket, sealed: SessionState::new(&base_seed, config).seal(&server_ticket).unwrap(), quic_session: None };
+        let (mut attempt, _) = SavedSession::from_bytes(&saved.to_bytes()).unwrap().resume().unwrap();
+        assert!(matches!(attempt.on_data(&RESUME_REJECT), Err(ResumptionError::Rejected)));
+    }
+}
@@ -2,7 +2,11 @@ use tracing::{info, error, warn, debug, trace};
 use silent_speaker::logging::init;
 
 use silent_speaker::critical_sender::CriticalSender;
-use silent_speaker::dynamic_framing::{SaltGenerator, build_dynamic_frame, DynamicStreamParser, parse_dynamic_frame, DynamicFramingError, SilentConfig};
+use silent_speaker::dynamic_framing::{SaltGenerator, Direction, Role, RatchetHandle, stream_generator, datagram_generators, build_dynamic_frame, DynamicStreamParser, parse_dynamic_frame, DynamicFramingError, SilentConfig, Secret, FrameType, build_datagram_frame, parse_datagram_frame};
+use silent_speaker::cover_traffic::{CoverTraffic, PacedSender, COVER_STREAM_ID};
+use silent_speaker::morphing::MorphingProfile;
+use silent_speaker::handshake::{HandshakeAuth, HandshakeError, ServerHandshake, CLIENT_HANDSHAKE_STREAM_ID, SERVER_HANDSHAKE_STREAM_ID, PSK_ENV_VAR};
+use silent_speaker::resumption::{ResumptionStore, ResumptionTicket, SessionState, CLIENT_RESUME_STREAM_ID, SERVER_RESUME_STREAM_ID, RESUME_HELLO_LEN, RESUME_REJECT};
 
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -21,7 +25,6 @@ use silent_speaker::fec::FECReassembler;
 // Duplicate import removed
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
-use silent_speaker::SESSION_BASE_SEED;
 
 struct PartialResponse {
     body: Vec<u8>,
@@ -35,7 +38,15 @@ struct Client {
     // stream_parsers: HashMap<u64, StreamParser>, // OLD
     stream_parsers: HashMap<u64, DynamicStreamParser>, // NEW
     generators: HashMap<u64, SaltGenerator>, // NEW: For sending ACKs
+    ratchets: HashMap<u64, RatchetHandle>, // 每条流的DH棘轮（收发方向共享）
     fec_reassembler: FECReassembler,
+    handshake: ServerHandshake,
+    session_seed: Option<Secret>, // 握手完成后得到的连接级基础种子（释放时清零）
+    pacer: Option<PacedSender>, // 掩护流量：恒定速率发送队列（未启用时为None）
+    datagrams: Option<(SaltGenerator, SaltGenerator)>, // 数据报模式的（发送, 接收）生成器，握手完成后创建
+    silent_config: SilentConfig, // 分帧配置：握手前为本地配置，握手完成后为与客户端协商的结果
+    ticket: Option<ResumptionTicket>, // 会话恢复票据，握手或会话恢复完成后创建，连接关闭时用于加密保存会话状态
+    resume_hello: Vec<u8>, // 尚未收全的ResumeHello
 }
 
 type ClientMap = HashMap<quiche::ConnectionId<'static>, Client>;
@@ -45,6 +56,15 @@ fn main() {
     // 日志系统初始化
     init();
 
+    // 加载握手认证密钥（预共享密钥）
+    let handshake_auth = match HandshakeAuth::from_env() {
+        Ok(auth) => auth,
+        Err(e) => {
+            error!("无法加载预共享密钥 (环境变量 {}): {}", PSK_ENV_VAR, e);
+            return;
+        }
+    };
+
     // 创建FEC关键信令发送器
     let mut critical_sender = CriticalSender::new(4, 2, 100).expect("FEC发送器初始化失败");
 
@@ -98,6 +118,7 @@ fn main() {
     config.set_initial_max_streams_bidi(100);
     config.set_initial_max_streams_uni(100);
     config.set_disable_active_migration(true);
+    config.enable_dgram(true, 1000, 1000); // 数据报模式动态帧（低延迟消息）
     config.enable_early_data();
 
     let rng = SystemRandom::new();
@@ -106,13 +127,58 @@ fn main() {
 
     let mut clients = ClientMap::new();
 
+    // 已关闭连接的加密会话状态（按票据ID索引），客户端重连时凭ResumeHello恢复
+    let mut resumption_store = ResumptionStore::new();
+
+    // 分帧配置（序号提示、双棘轮、头部保护、加密套件），通过环境变量覆盖默认值，握手时与客户端协商
+    let mut silent_config = SilentConfig::from_env().unwrap_or_default();
+    info!(
+        "本地分帧配置: 序号提示 {}, 双棘轮 {} (间隔 {}), 头部保护 {}, 加密套件 {:?}",
+        silent_config.enable_sequence_hint,
+        silent_config.enable_double_ratchet,
+        silent_config.ratchet_interval,
+        silent_config.enable_header_protection,
+        silent_config.cipher_suite
+    );
+
+    // 掩护流量（恒定速率发送），通过环境变量启用
+    silent_config.cover_traffic = CoverTraffic::from_env();
+    if let Some(cover) = silent_config.cover_traffic {
+        info!(
+            "已启用掩护流量: {} 帧/秒, 单元 {} 字节, 抖动 {:?}",
+            cover.frames_per_second,
+            cover.cell_size,
+            cover.jitter
+        );
+    }
+
+    // 流量变形：按配置文件中的分布整形帧大小与发送间隔（优先于掩护流量）
+    // 变形只影响发送端，配置文件不属于协商的 SilentConfig；各连接共享同一份
+    let morphing = match MorphingProfile::from_env() {
+        Some(Ok(profile)) => {
+            info!("已加载流量变形配置: {}", profile.name());
+            Some(Arc::new(profile))
+        }
+        Some(Err(e)) => {
+            warn!("流量变形配置加载失败: {}", e);
+            None
+        }
+        None => None,
+    };
+
     let local_addr = socket.local_addr().unwrap();
 
     loop {
         // Find the shorter timeout from all the active connections.
         //
         // TODO: use event loop that properly supports timers
-        let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
+        // 启用掩护流量时，还需要在下一个发送时隙醒来
+        let now = std::time::Instant::now();
+        let timeout = clients
+            .values()
+            .filter_map(|c| c.conn.timeout())
+            .chain(clients.values().filter_map(|c| c.pacer.as_ref().map(|p| p.timeout(now))))
+            .min();
 
         poll.poll(&mut events, timeout).unwrap();
 
@@ -280,7 +346,15 @@ fn main() {
                     conn_id: numeric_conn_id,  // 存储数字连接ID
                     stream_parsers: HashMap::new(),
                     generators: HashMap::new(), // Init generators
+                    ratchets: HashMap::new(),
                     fec_reassembler: FECReassembler::new(4, 2),
+                    handshake: ServerHandshake::new(silent_config),
+                    session_seed: None,
+                    pacer: PacedSender::from_config(&silent_config, morphing.clone()),
+                    datagrams: None,
+                    silent_config,
+                    ticket: None,
+                    resume_hello: Vec::new(),
                 };
 
                 clients.insert(scid.clone(), client);
@@ -318,7 +392,15 @@ fn main() {
                 }
 
                 // Process all readable streams.
-                for s in client.conn.readable() {
+                // 握手与会话恢复流优先：0-RTT数据可能与ResumeHello同时到达。
+                // 会话建立前不读取数据流，数据留在quiche中，会话建立后再读取。
+                let session_streams = [CLIENT_HANDSHAKE_STREAM_ID, CLIENT_RESUME_STREAM_ID];
+                let mut readable: Vec<u64> = client.conn.readable().collect();
+                readable.sort_by_key(|s| !session_streams.contains(s));
+                for s in readable {
+                    if client.session_seed.is_none() && !session_streams.contains(&s) {
+                        continue;
+                    }
                     while let Ok((read, fin)) =
                         client.conn.stream_recv(s, &mut buf)
                     {
@@ -338,12 +420,20 @@ fn main() {
                             fin
                         );
 
-                        handle_stream(client, s, stream_buf, &critical_sender);
+                        handle_stream(client, s, stream_buf, &critical_sender, &handshake_auth, &mut resumption_store);
                     }
                 }
+
+                // 数据报模式：不受流上队头阻塞影响的低延迟消息
+                handle_datagrams(client, &mut buf);
             }
         }
 
+        // 恒定速率发送：填充到期的时隙
+        for client in clients.values_mut() {
+            send_paced_slots(client);
+        }
+
         // Generate outgoing QUIC packets for all active connections and send
         // them on the UDP socket, until quiche reports that there are no more
         // packets to be sent.
@@ -388,6 +478,7 @@ fn main() {
                     c.conn.trace_id(),
                     c.conn.stats()
                 );
+                seal_session(c, &mut resumption_store);
             }
 
             !c.conn.is_closed()
@@ -451,6 +542,123 @@ fn validate_token<'a>(
     Some(quiche::ConnectionId::from_ref(&token[addr.len()..]))
 }
 
+/// 处理连接上收到的QUIC数据报
+///
+/// 每个数据报是一个独立的动态帧，可乱序到达或丢失。文本消息通过数据报回复ACK；
+/// 启用掩护流量时不回复，以免数据报的发送时机暴露真实消息。
+fn handle_datagrams(client: &mut Client, buf: &mut [u8]) {
+    let conn = &mut client.conn;
+
+    while let Ok(len) = conn.dgram_recv(buf) {
+        let Some((sender, receiver)) = client.datagrams.as_mut() else {
+            warn!("{} 握手完成前收到数据报，已丢弃", conn.trace_id());
+            continue;
+        };
+
+        let payload = match parse_datagram_frame(receiver, &buf[..len], client.silent_config) {
+            Ok((FrameType::Data, payload)) => payload,
+            Ok((frame_type, _)) => {
+                debug!("{} 忽略 {:?} 类型的数据报", conn.trace_id(), frame_type);
+                continue;
+            }
+            Err(e) => {
+                warn!("{} 数据报解析失败: {}", conn.trace_id(), e);
+                continue;
+            }
+        };
+        let whisper = match Whisper::decode(&payload[..]) {
+            Ok(whisper) => whisper,
+            Err(e) => {
+                warn!("{} 数据报中的Protobuf解析失败: {}", conn.trace_id(), e);
+                continue;
+            }
+        };
+        let Some(Payload::Content(content)) = &whisper.payload else {
+            info!("{} 收到非文本数据报消息", conn.trace_id());
+            continue;
+        };
+        info!("{} 收到数据报消息: {}", conn.trace_id(), content);
+
+        if client.pacer.is_some() {
+            continue;
+        }
+        let mut ack_whisper = Whisper::default();
+        ack_whisper.id = uuid::Uuid::new_v4().as_bytes().to_vec();
+        ack_whisper.payload = Some(Payload::Content(format!("确认ACK: 收到 '{}'", content)));
+        ack_whisper.timestamp_ns = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        ack_whisper.priority = whisper.priority;
+
+        match build_datagram_frame(sender, FrameType::Data, &ack_whisper.encode_to_vec(), client.silent_config) {
+            Ok(frame) => {
+                if let Err(e) = conn.dgram_send(&frame) {
+                    error!("{} 发送数据报ACK失败: {:?}", conn.trace_id(), e);
+                }
+            }
+            Err(e) => error!("{} 构建数据报帧失败: {}", conn.trace_id(), e),
+        }
+    }
+}
+
+/// 发送一条动态帧消息（服务端 -> 客户端）
+///
+/// 启用掩护流量时消息进入恒定速率发送队列，在下一个空闲时隙中构建并发送；
+/// 否则立即构建动态帧并写入流。
+#[allow(clippy::too_many_arguments)]
+fn send_dynamic_message(
+    conn: &mut quiche::Connection,
+    generators: &mut HashMap<u64, SaltGenerator>,
+    ratchets: &mut HashMap<u64, RatchetHandle>,
+    pacer: Option<&mut PacedSender>,
+    session_seed: &[u8; 32],
+    config: SilentConfig,
+    stream_id: u64,
+    payload: Vec<u8>,
+) -> Result<(), String> {
+    if let Some(pacer) = pacer {
+        pacer.enqueue(stream_id, payload, false);
+        return Ok(());
+    }
+
+    let generator = generators.entry(stream_id).or_insert_with(|| {
+        stream_generator(ratchets, session_seed, stream_id, Direction::ServerToClient)
+    });
+    let framed = build_dynamic_frame(generator, &payload, config)
+        .map_err(|e| format!("构建动态帧失败: {}", e))?;
+    conn.stream_send(stream_id, &framed, false)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(())
+}
+
+/// 在到期的发送时隙中发送排队消息，空闲时隙发送掩护帧
+///
+/// 帧大小由发送器决定（恒定单元或流量变形分布），观察者无法区分真实消息与掩护帧。
+fn send_paced_slots(client: &mut Client) {
+    let (Some(pacer), Some(session_seed)) = (client.pacer.as_mut(), client.session_seed.as_deref()) else {
+        return;
+    };
+    if !client.conn.is_established() {
+        return;
+    }
+
+    let now = std::time::Instant::now();
+    while let Some(slot) = pacer.next_slot(now) {
+        let generator = client.generators.entry(slot.stream_id).or_insert_with(|| {
+            stream_generator(&mut client.ratchets, session_seed, slot.stream_id, Direction::ServerToClient)
+        });
+        match slot.build(generator, client.silent_config) {
+            Ok(frame) => {
+                if let Err(e) = client.conn.stream_send(slot.stream_id, &frame, slot.fin) {
+                    error!("{} 流 {} 时隙发送失败: {:?}", client.conn.trace_id(), slot.stream_id, e);
+                }
+            }
+            Err(e) => error!("{} 流 {} 构建时隙帧失败: {}", client.conn.trace_id(), slot.stream_id, e),
+        }
+    }
+}
+
 /// Handles incoming Whisper Protobuf messages with FEC support and message framing.
 /// 
 /// This function processes framed messages using the new framing protocol:
@@ -461,6 +669,8 @@ fn validate_token<'a>(
 /// * `stream_id` - QUIC stream ID where the message arrived
 /// * `buf` - Raw message bytes (may contain partial or multiple framed messages)
 /// * `critical_sender` - FEC critical message sender (for future FEC reassembly)
+/// * `handshake_auth` - Credentials used to authenticate the session handshake
+/// * `resumption_store` - Sealed session states of closed connections, for ResumeHellos
 /// 
 /// # Returns
 /// * Nothing, but may send ACK responses back to the client
@@ -469,7 +679,19 @@ fn handle_stream(
     stream_id: u64, 
     buf: &[u8],
     critical_sender: &CriticalSender,
+    handshake_auth: &HandshakeAuth,
+    resumption_store: &mut ResumptionStore,
 ) {
+    // 握手控制流与会话恢复流单独处理
+    if stream_id == CLIENT_HANDSHAKE_STREAM_ID {
+        handle_handshake(client, buf, handshake_auth);
+        return;
+    }
+    if stream_id == CLIENT_RESUME_STREAM_ID {
+        handle_resume(client, buf, resumption_store);
+        return;
+    }
+
     let conn = &mut client.conn;
     
     tracing::trace!(
@@ -478,12 +700,30 @@ fn handle_stream(
         stream_id,
         buf.len()
     );
+
+    // 握手完成前不接受数据流
+    let session_seed = match client.session_seed.as_deref() {
+        Some(seed) => seed,
+        None => {
+            warn!(
+                "{} 流 {} 在握手完成前收到数据，已丢弃",
+                conn.trace_id(),
+                stream_id
+            );
+            return;
+        }
+    };
+    
+    // 客户端打开掩护流后，空闲时隙的掩护帧也从这条流发回
+    if let Some(pacer) = client.pacer.as_mut().filter(|_| stream_id == COVER_STREAM_ID) {
+        pacer.set_cover_stream(stream_id);
+    }
     
     // 步骤1: 获取或创建解析器
     let parser = client.stream_parsers
         .entry(stream_id)
         .or_insert_with(|| {
-             let generator = SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id);
+             let generator = stream_generator(&mut client.ratchets, session_seed, stream_id, Direction::ClientToServer);
              DynamicStreamParser::new(generator)
         });
     
@@ -512,9 +752,12 @@ fn handle_stream(
     }
     
     // 步骤4: 收集所有解析出的消息
+    // 解析失败时解析器自行向后扫描下一个有效帧（重新同步），无需重置
+    // 客户端合并发送的批量帧（FrameType::Batch）也由解析器拆分，每条消息单独返回
+    let resyncs = parser.resync_stats();
     let mut messages = Vec::new();
     loop {
-        match parser.try_parse_next(SilentConfig::default()) {
+        match parser.try_parse_next(client.silent_config) {
             Ok(Some(payload)) => {
                 // Decode Protobuf
                 match Whisper::decode(&payload[..]) {
@@ -526,19 +769,56 @@ fn handle_stream(
                 // 数据不完整，等待更多数据
                 break;
             }
+            Err(DynamicFramingError::UnknownEpoch { epoch, .. }) => {
+                // 帧边界仍然同步，跳过该帧，等待对端在下一个换钥帧中重发应答
+                warn!(
+                    "{} 流 {} 收到未知密钥纪元 {} 的帧，已跳过",
+                    conn.trace_id(),
+                    stream_id,
+                    epoch
+                );
+            }
+            Err(DynamicFramingError::Replay { seq, .. }) => {
+                // 重放（重复）帧，丢弃后继续解析后续帧
+                warn!(
+                    "{} 流 {} 收到重放帧 (序号 {})，已丢弃",
+                    conn.trace_id(),
+                    stream_id,
+                    seq
+                );
+            }
+            Err(DynamicFramingError::ResyncRateLimited) => {
+                // 序号搜索被限速：数据仍保留在缓冲区中，收到后续数据时再重试
+                warn!(
+                    "{} 流 {} 重新同步过于频繁，暂缓解析",
+                    conn.trace_id(),
+                    stream_id
+                );
+                break;
+            }
             Err(e) => {
+                // 扫描预算耗尽，已扫描的数据被丢弃，后续数据仍可继续解析
                 error!(
-                    "{} 流 {} 消息解析失败: {:?}，重置解析器",
+                    "{} 流 {} 消息解析失败: {:?}",
                     conn.trace_id(),
                     stream_id,
                     e
                 );
-                parser.clear();
-                return;
             }
         }
     }
     
+    let stats = parser.resync_stats();
+    if stats.recovered > resyncs.recovered {
+        warn!(
+            "{} 流 {} 重新同步成功 {} 次，共跳过 {} 字节",
+            conn.trace_id(),
+            stream_id,
+            stats.recovered - resyncs.recovered,
+            stats.bytes_skipped - resyncs.bytes_skipped
+        );
+    }
+    
     // 步骤5: 记录日志状态
     if messages.is_empty() && parser.buffer_size() > 0 {
         debug!(
@@ -562,6 +842,133 @@ fn handle_stream(
     }
 }
 
+/// 处理握手控制流上的数据
+///
+/// 收到完整的 ClientHello 后回复 ServerHello，并保存连接级基础种子和协商后的分帧配置。
+/// 认证失败或双方配置不兼容时直接关闭连接。
+fn handle_handshake(client: &mut Client, buf: &[u8], handshake_auth: &HandshakeAuth) {
+    let conn = &mut client.conn;
+
+    if client.session_seed.is_some() && !client.handshake.is_complete() {
+        warn!("{} 会话已恢复，忽略ClientHello", conn.trace_id());
+        return;
+    }
+
+    match client.handshake.on_data(handshake_auth, buf) {
+        Ok(Some((server_hello, seed))) => {
+            match conn.stream_send(SERVER_HANDSHAKE_STREAM_ID, &server_hello, false) {
+                Ok(_) => {
+                    if let Some(config) = client.handshake.negotiated_config() {
+                        client.silent_config = config;
+                    }
+                    info!(
+                        "{} 会话握手完成，协商分帧配置: 双棘轮 {} (间隔 {}), 头部保护 {}",
+                        conn.trace_id(),
+                        client.silent_config.enable_double_ratchet,
+                        client.silent_config.ratchet_interval,
+                        client.silent_config.enable_header_protection
+                    );
+                    client.datagrams = Some(datagram_generators(&seed, Role::Server));
+                    client.ticket = Some(ResumptionTicket::from_base_seed(&seed));
+                    client.session_seed = Some(seed);
+                }
+                Err(e) => {
+                    error!("{} 发送ServerHello失败: {:?}", conn.trace_id(), e);
+                    conn.close(false, 0x1, b"handshake failed").ok();
+                }
+            }
+        }
+        Ok(None) => {
+            debug!("{} ClientHello不完整，等待更多数据", conn.trace_id());
+        }
+        Err(e @ HandshakeError::IncompatibleConfig(_)) => {
+            error!("{} 与客户端的分帧配置不兼容: {}", conn.trace_id(), e);
+            conn.close(false, 0x1, b"incompatible config").ok();
+        }
+        Err(e) => {
+            error!("{} 会话握手失败: {}", conn.trace_id(), e);
+            conn.close(false, 0x1, b"handshake failed").ok();
+        }
+    }
+}
+
+/// 处理会话恢复流上的ResumeHello
+///
+/// 找到对应的加密会话状态后恢复各流的生成器与解析器，客户端的流从断开处继续；
+/// 没有对应状态或验证失败时回复拒绝，客户端随后改为完整握手。
+fn handle_resume(client: &mut Client, buf: &[u8], resumption_store: &mut ResumptionStore) {
+    let conn = &mut client.conn;
+
+    if client.session_seed.is_some() {
+        warn!("{} 会话已建立，忽略ResumeHello", conn.trace_id());
+        return;
+    }
+    client.resume_hello.extend_from_slice(buf);
+    if client.resume_hello.len() < RESUME_HELLO_LEN {
+        debug!("{} ResumeHello不完整，等待更多数据", conn.trace_id());
+        return;
+    }
+    let hello = std::mem::take(&mut client.resume_hello);
+
+    let resumed = resumption_store.resume(&hello).and_then(|(state, ticket, accept)| {
+        let config = state.negotiated_config(client.silent_config)?;
+        Ok((state, ticket, accept, config))
+    });
+    match resumed {
+        Ok((mut state, ticket, accept, config)) => {
+            if let Err(e) = conn.stream_send(SERVER_RESUME_STREAM_ID, &accept, false) {
+                error!("{} 发送会话恢复应答失败: {:?}", conn.trace_id(), e);
+                conn.close(false, 0x1, b"resume failed").ok();
+                return;
+            }
+            state.attach_ratchets(&mut client.ratchets);
+            info!(
+                "{} 会话已恢复: {} 条发送流, {} 条接收流",
+                conn.trace_id(),
+                state.senders.len(),
+                state.parsers.len()
+            );
+            client.silent_config = config;
+            client.generators = state.senders;
+            client.stream_parsers = state.parsers;
+            client.datagrams = state.datagrams;
+            client.session_seed = Some(state.base_seed);
+            client.ticket = Some(ticket);
+        }
+        Err(e) => {
+            warn!("{} 无法恢复会话，等待完整握手: {}", conn.trace_id(), e);
+            if let Err(e) = conn.stream_send(SERVER_RESUME_STREAM_ID, &RESUME_REJECT, false) {
+                error!("{} 发送会话恢复应答失败: {:?}", conn.trace_id(), e);
+            }
+        }
+    }
+}
+
+/// 连接关闭时加密保存会话状态，客户端重连后可凭ResumeHello恢复
+fn seal_session(client: &mut Client, resumption_store: &mut ResumptionStore) {
+    let (Some(ticket), Some(base_seed)) = (client.ticket.take(), client.session_seed.take()) else {
+        return;
+    };
+    let state = SessionState {
+        base_seed,
+        config: client.silent_config.encode_wire(),
+        senders: std::mem::take(&mut client.generators),
+        parsers: std::mem::take(&mut client.stream_parsers),
+        datagrams: client.datagrams.take(),
+    };
+    match state.seal(&ticket) {
+        Ok(sealed) => {
+            resumption_store.insert(ticket, sealed);
+            info!(
+                "{} 会话状态已保存，可供重连恢复 (共 {} 个)",
+                client.conn.trace_id(),
+                resumption_store.len()
+            );
+        }
+        Err(e) => error!("{} 保存会话状态失败: {}", client.conn.trace_id(), e),
+    }
+}
+
 // 新增：处理消息的函数，不接收整个client
 fn process_messages(
     client: &mut Client,
@@ -588,6 +995,11 @@ fn process_single_message(
     critical_sender: &CriticalSender,
 ) {
     let conn = &mut client.conn;
+
+    // 调用方保证握手已完成
+    let Some(session_seed) = client.session_seed.as_deref() else {
+        return;
+    };
     
     // 根据消息负载类型进行不同处理
     match &whisper.payload {
@@ -623,21 +1035,11 @@ fn process_single_message(
             // 使用分帧函数包装ACK消息
             // let framed_ack = silent_speaker::frame_message(&ack_whisper);
             
-            // Generate Dynamic Frame
-            let generator = client.generators.entry(stream_id).or_insert_with(|| {
-                 SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id)
-            });
-            
+            // Generate Dynamic Frame and send the ACK (or queue it for a cover traffic slot)
             let bytes = ack_whisper.encode_to_vec();
-            match build_dynamic_frame(generator, &bytes, SilentConfig::default()) {
-                Ok(framed_ack) => {
-                    // 发送ACK回执到客户端
-                    match conn.stream_send(stream_id, &framed_ack, false) {
-                        Ok(_) => tracing::trace!("{} 已发送ACK", conn.trace_id()),
-                        Err(e) => error!("{} 发送ACK失败: {:?}", conn.trace_id(), e),
-                    }
-                },
-                Err(e) => error!("{} 构建ACK动态帧失败: {}", conn.trace_id(), e),
+            match send_dynamic_message(conn, &mut client.generators, &mut client.ratchets, client.pacer.as_mut(), session_seed, client.silent_config, stream_id, bytes) {
+                Ok(()) => tracing::trace!("{} 已发送ACK", conn.trace_id()),
+                Err(e) => error!("{} 发送ACK失败: {}", conn.trace_id(), e),
             }
         }
         
@@ -694,17 +1096,12 @@ fn process_single_message(
                         
                         // let framed_ack = silent_speaker::frame_message(&ack_whisper);
                         
-                        let generator = client.generators.entry(stream_id).or_insert_with(|| {
-                             SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id)
-                        });
                         let bytes = ack_whisper.encode_to_vec();
                         
-                        if let Ok(framed_ack) = build_dynamic_frame(generator, &bytes, SilentConfig::default()) {
-                            // 发送恢复确认
-                            match conn.stream_send(stream_id, &framed_ack, false) {
-                                Ok(_) => debug!("{} 已发送FEC恢复确认", conn.trace_id()),
-                                Err(e) => error!("{} 发送FEC恢复确认失败: {:?}", conn.trace_id(), e),
-                            }
+                        // 发送恢复确认
+                        match send_dynamic_message(conn, &mut client.generators, &mut client.ratchets, client.pacer.as_mut(), session_seed, client.silent_config, stream_id, bytes) {
+                            Ok(()) => debug!("{} 已发送FEC恢复确认", conn.trace_id()),
+                            Err(e) => error!("{} 发送FEC恢复确认失败: {}", conn.trace_id(), e),
                         }
                         
                         // 这里可以进一步处理恢复的原始数据
@@ -735,16 +1132,11 @@ fn process_single_message(
                         ack_whisper.priority = Priority::Normal as i32;
                         
                         // let framed_ack = silent_speaker::frame_message(&ack_whisper);
-                        let generator = client.generators.entry(stream_id).or_insert_with(|| {
-                             SaltGenerator::new_diversified(SESSION_BASE_SEED, stream_id)
-                        });
                         let bytes = ack_whisper.encode_to_vec();
                         
-                        if let Ok(framed_ack) = build_dynamic_frame(generator, &bytes, SilentConfig::default()) {
-                            match conn.stream_send(stream_id, &framed_ack, false) {
-                                Ok(_) => debug!("{} 已发送FEC块确认", conn.trace_id()),
-                                Err(e) => error!("{} 发送FEC块确认失败: {:?}", conn.trace_id(), e),
-                            }
+                        match send_dynamic_message(conn, &mut client.generators, &mut client.ratchets, client.pacer.as_mut(), session_seed, client.silent_config, stream_id, bytes) {
+                            Ok(()) => debug!("{} 已发送FEC块确认", conn.trace_id()),
+                            Err(e) => error!("{} 发送FEC块确认失败: {}", conn.trace_id(), e),
                         }
                     }
                     
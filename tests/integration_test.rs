@@ -1,4 +1,4 @@
-use silent_speaker::dynamic_framing::{SaltGenerator, build_dynamic_frame, DynamicStreamParser, SilentConfig};
+use silent_speaker::dynamic_framing::{SaltGenerator, build_dynamic_frame, DynamicStreamParser, SilentConfig, SilentStream, Direction};
 use silent_speaker::stream::UnifiedStreamManager;
 use silent_speaker::fec::{FECEncoder, FECReassembler};
 use silent_speaker::whisper::{Whisper, Priority, FecWhisper};
@@ -30,7 +30,7 @@ fn test_integrated_stack_normal_message() {
     let (stream_id, data_to_send) = allocation.unwrap();
 
     let generator = sender_generators.entry(stream_id).or_insert_with(|| {
-        SaltGenerator::new_diversified(TEST_SESSION_SEED, stream_id)
+        SaltGenerator::new_diversified(&TEST_SESSION_SEED, stream_id)
     });
 
     let config = SilentConfig::default();
@@ -38,7 +38,7 @@ fn test_integrated_stack_normal_message() {
 
     // 4. 接收端：解析
     let parser = receiver_parsers.entry(stream_id).or_insert_with(|| {
-        let generator = SaltGenerator::new_diversified(TEST_SESSION_SEED, stream_id); // Same seed/stream_id
+        let generator = SaltGenerator::new_diversified(&TEST_SESSION_SEED, stream_id); // Same seed/stream_id
         DynamicStreamParser::new(generator)
     });
     
@@ -98,13 +98,13 @@ fn test_integrated_stack_fec_message() {
 
         // Frame
         let generator = sender_generators.entry(*stream_id).or_insert_with(|| {
-            SaltGenerator::new_diversified(TEST_SESSION_SEED, *stream_id)
+            SaltGenerator::new_diversified(&TEST_SESSION_SEED, *stream_id)
         });
         let wired_frame = build_dynamic_frame(generator, &bytes, SilentConfig::default()).unwrap();
 
         // Receive
         let parser = receiver_parsers.entry(*stream_id).or_insert_with(|| {
-             let generator = SaltGenerator::new_diversified(TEST_SESSION_SEED, *stream_id);
+             let generator = SaltGenerator::new_diversified(&TEST_SESSION_SEED, *stream_id);
              DynamicStreamParser::new(generator)
         });
         parser.append_data(&wired_frame).unwrap();
@@ -135,3 +135,36 @@ fn test_integrated_stack_fec_message() {
     // Reassembler returns Some only when newly complete.
     panic!("FEC Reassembly did not complete (should have recovered)");
 }
+
+#[test]
+fn test_silent_stream_over_tcp() {
+    // fengni 分帧脱离 QUIC，直接运行在 TCP 上
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Bind failed");
+    let addr = listener.local_addr().unwrap();
+    let config = SilentConfig::default();
+    let generator = |direction| SaltGenerator::new_directional(&TEST_SESSION_SEED, 0, direction);
+
+    // 服务端：逐条回显，附加前缀
+    let server = std::thread::spawn(move || {
+        let (socket, _) = listener.accept().expect("Accept failed");
+        let mut stream = SilentStream::new(socket, generator(Direction::ServerToClient), generator(Direction::ClientToServer), config);
+        while let Some(message) = stream.recv_message().expect("Server recv failed") {
+            let mut reply = b"echo: ".to_vec();
+            reply.extend_from_slice(&message);
+            stream.send_message(&reply).expect("Server send failed");
+        }
+    });
+
+    let socket = std::net::TcpStream::connect(addr).expect("Connect failed");
+    let mut client = SilentStream::new(socket, generator(Direction::ClientToServer), generator(Direction::ServerToClient), config);
+    for text in ["first", "second"] {
+        client.send_message(text.as_bytes()).expect("Client send failed");
+        let reply = client.recv_message().expect("Client recv failed").expect("Server closed early");
+        assert_eq!(reply, format!("echo: {}", text).into_bytes());
+    }
+
+    // 关闭写端后服务端在帧边界处收到 EOF 并退出
+    client.get_ref().shutdown(std::net::Shutdown::Write).unwrap();
+    server.join().unwrap();
+    assert!(client.recv_message().unwrap().is_none());
+}